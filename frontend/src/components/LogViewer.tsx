@@ -26,6 +26,30 @@ const STORAGE_KEY_SHOW_LINE_NUMBERS = 'serialDebug_showLineNumbers';
 
 const SCROLL_BOTTOM_THRESHOLD = 50;
 
+// Highlight rule tags name a color; other CSS colors are used as given and
+// any other tag gets a stable color of its own
+const HIGHLIGHT_COLORS: Record<string, string> = {
+  red: '#ef4444',
+  orange: '#f97316',
+  yellow: '#eab308',
+  green: '#22c55e',
+  blue: '#3b82f6',
+  purple: '#a855f7',
+};
+
+const highlightColor = (tag: string): string => {
+  const named = HIGHLIGHT_COLORS[tag.toLowerCase()];
+  if (named) return named;
+  if (CSS.supports('color', tag)) return tag;
+  let hash = 0;
+  for (const char of tag) hash = (hash * 31 + char.charCodeAt(0)) | 0;
+  return `hsl(${Math.abs(hash) % 360}, 70%, 55%)`;
+};
+
+/** Color of the first highlight rule an entry matched */
+const entryHighlight = (log: LogEntry): string | undefined =>
+  log.highlights?.length ? highlightColor(log.highlights[0]) : undefined;
+
 const DEFAULT_SPECIAL_CHAR_CONFIG: SpecialCharConfig = {
   enabled: true,
   convertLF: true,
@@ -88,6 +112,9 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
     raw_recording_active: false,
     text_file_path: null,
     raw_file_path: null,
+    raw_rx_file_path: null,
+    write_error: null,
+    dropped_bytes: 0,
   });
 
   const [showLineNumbers, setShowLineNumbers] = useState<boolean>(() => {
@@ -146,6 +173,7 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
     const syncDisplaySettings = async () => {
       try {
         await invoke('set_display_format', { format: receiveFormat });
+        await invoke('reformat_logs');
       } catch (error) {
         console.error('Error syncing display format:', error);
       }
@@ -157,6 +185,7 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
     const syncTextEncoding = async () => {
       try {
         await invoke('set_text_encoding_display', { encoding: textEncoding });
+        await invoke('reformat_logs');
       } catch (error) {
         console.error('Error syncing text encoding:', error);
       }
@@ -168,6 +197,7 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
     const syncSpecialCharConfig = async () => {
       try {
         await invoke('set_special_char_config', { config: toBackendSpecialCharConfig(specialCharConfig) });
+        await invoke('reformat_logs');
       } catch (error) {
         console.error('Error syncing special char config:', error);
       }
@@ -179,6 +209,7 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
     const syncShowTimestamps = async () => {
       try {
         await invoke('set_show_timestamps', { show: showTimestamps });
+        await invoke('reformat_logs');
       } catch (error) {
         console.error('Error syncing show timestamps:', error);
       }
@@ -662,14 +693,20 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
           </div>
         ) : (
           <div className="space-y-0.5">
-            {logs.map((log, index) => (
+            {logs.map((log, index) => {
+              if (log.hidden) return null;
+              const highlight = entryHighlight(log);
+              return (
               <div
                 key={index}
                 ref={(el) => { logEntryRefs.current[index] = el; }}
+                data-highlights={log.highlights?.join(' ') || undefined}
                 className="py-1 px-2 rounded-[4px] transition-colors duration-150"
                 style={{
-                  borderLeft: `2px solid ${log.direction === 'Sent' ? colors.accent : colors.success}`,
-                  backgroundColor: log.direction === 'Sent' ? colors.logSentBg : colors.logReceivedBg
+                  borderLeft: `2px solid ${highlight ?? (log.direction === 'Sent' ? colors.accent : colors.success)}`,
+                  backgroundColor: highlight
+                    ? `color-mix(in srgb, ${highlight} 20%, transparent)`
+                    : log.direction === 'Sent' ? colors.logSentBg : colors.logReceivedBg
                 }}
               >
                 <div className="flex items-start gap-2">
@@ -693,9 +730,9 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
                   )}
                   <span
                     className="font-bold text-xs uppercase select-none"
-                    style={{ color: log.direction === 'Sent' ? colors.accent : colors.success }}
+                    style={{ color: log.direction === 'Sent' ? colors.accent : log.direction === 'System' ? colors.warning : colors.success }}
                   >
-                    {log.direction === 'Sent' ? 'TX' : 'RX'}
+                    {log.direction === 'Sent' ? 'TX' : log.direction === 'System' ? 'SYS' : 'RX'}
                   </span>
                   <span
                     className="flex-1 break-all font-mono text-sm whitespace-pre-wrap"
@@ -705,7 +742,8 @@ const LogViewer: React.FC<LogViewerProps> = ({ logs, onClear, onExport, isConnec
                   </span>
                 </div>
               </div>
-            ))}
+              );
+            })}
           </div>
         )}
       </div>
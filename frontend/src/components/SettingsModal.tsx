@@ -202,6 +202,7 @@ const SettingsModal: React.FC<SettingsModalProps> = ({ open, onOpenChange }) =>
       : Math.round(parseUtcOffset(tz) * 60);
     try {
       await invoke('set_timezone_offset', { offsetMinutes });
+      await invoke('reformat_logs');
     } catch (error) {
       console.error('Error setting timezone offset:', error);
     }
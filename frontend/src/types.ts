@@ -22,8 +22,8 @@ export type DataBits = 'Five' | 'Six' | 'Seven' | 'Eight';
 export type Parity = 'None' | 'Odd' | 'Even' | 'Mark' | 'Space';
 export type StopBits = 'One' | 'OnePointFive' | 'Two';
 export type FlowControl = 'None' | 'Software' | 'Hardware';
-export type DataFormat = 'Text' | 'Hex';
-export type Direction = 'Sent' | 'Received';
+export type DataFormat = 'Text' | 'Hex' | 'Escaped';
+export type Direction = 'Sent' | 'Received' | 'System';
 export type TextEncoding = 'utf-8' | 'gbk';
 
 // Checksum types
@@ -46,6 +46,20 @@ export interface LogEntry {
   display_text: string;
   /** Pre-formatted timestamp string (undefined if timestamps were disabled when entry was created) */
   timestamp_formatted?: string;
+  /** Matched a backend hide rule */
+  hidden?: boolean;
+  /** Tags of the backend highlight rules this entry matched */
+  highlights?: string[];
+}
+
+// Note attached to a log entry, kept when the display buffer is cleared
+export interface Bookmark {
+  entry_id: number;
+  note: string;
+  created_at: string;
+  entry_timestamp: string;
+  direction: Direction;
+  preview: string;
 }
 
 export interface ConnectionStatus {
@@ -108,6 +122,24 @@ export interface RecordingStatus {
   raw_recording_active: boolean;
   text_file_path: string | null;
   raw_file_path: string | null;
+  raw_rx_file_path: string | null;
+  write_error: string | null;
+  dropped_bytes: number;
+}
+
+export type RawRecordingMode = 'Combined' | 'Split' | 'Container';
+
+export interface AutoRecordSettings {
+  text: boolean;
+  raw: boolean;
+}
+
+export interface RecordingRotation {
+  max_file_bytes: number | null;
+  max_duration_secs: number | null;
+  max_lines: number | null;
+  compress: boolean;
+  max_directory_bytes: number | null;
 }
 // Timezone configuration
 export type TimezoneOption = 'System' | string; // 'System' or UTC offset like 'UTC+8', 'UTC-5', etc.
@@ -0,0 +1,175 @@
+//! ANSI escape sequences in received text
+//!
+//! Splits text into runs with their SGR (Select Graphic Rendition) style, so
+//! colors and emphasis can be rendered outside a terminal. Other CSI
+//! sequences such as cursor movement are dropped.
+
+/// Terminal color as set by SGR 30-37, 38;5;n, 38;2;r;g;b and friends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// xterm palette index; 0-15 are the standard and bright colors
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// `#rrggbb` for CSS, using the xterm palette for indexed colors
+    pub fn to_css(self) -> String {
+        let (r, g, b) = match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Indexed(index) => xterm_rgb(index),
+        };
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    /// Apply the parameters of one SGR sequence (`ESC [ params m`)
+    fn apply(&mut self, params: &[u16]) {
+        let mut iter = params.iter().copied();
+        while let Some(param) = iter.next() {
+            match param {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.foreground = Some(Color::Indexed((param - 30) as u8)),
+                38 => self.foreground = extended_color(&mut iter),
+                39 => self.foreground = None,
+                40..=47 => self.background = Some(Color::Indexed((param - 40) as u8)),
+                48 => self.background = extended_color(&mut iter),
+                49 => self.background = None,
+                90..=97 => self.foreground = Some(Color::Indexed((param - 90 + 8) as u8)),
+                100..=107 => self.background = Some(Color::Indexed((param - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Split `text` into styled runs, dropping the escape sequences themselves.
+/// Empty runs are left out.
+pub fn segments(text: &str) -> Vec<(Style, &str)> {
+    let mut segments = Vec::new();
+    let mut style = Style::default();
+    let mut run_start = 0;
+
+    while let Some(found) = text[run_start..].find('\x1b') {
+        let esc = run_start + found;
+        push_segment(&mut segments, style, &text[run_start..esc]);
+        let (length, params) = parse_csi(&text[esc..]);
+        if let Some(params) = params {
+            style.apply(&params);
+        }
+        run_start = esc + length;
+    }
+    push_segment(&mut segments, style, &text[run_start..]);
+    segments
+}
+
+fn push_segment<'a>(segments: &mut Vec<(Style, &'a str)>, style: Style, text: &'a str) {
+    if !text.is_empty() {
+        segments.push((style, text));
+    }
+}
+
+/// Length of the escape sequence at the start of `sequence` and, for SGR
+/// sequences, their parameters. A lone or unknown ESC is one byte long.
+fn parse_csi(sequence: &str) -> (usize, Option<Vec<u16>>) {
+    let bytes = sequence.as_bytes();
+    if bytes.get(1) != Some(&b'[') {
+        return (1, None);
+    }
+    // Parameter and intermediate bytes, then one final byte in 0x40..=0x7E
+    let Some(end) = bytes[2..].iter().position(|byte| (0x40..=0x7E).contains(byte)) else {
+        return (sequence.len(), None);
+    };
+    let end = end + 2;
+    if bytes[end] != b'm' {
+        return (end + 1, None);
+    }
+    // Empty parameters count as 0, so `ESC [ m` resets like `ESC [ 0 m`
+    let params = sequence[2..end]
+        .split(';')
+        .map(|param| param.parse().unwrap_or(0))
+        .collect();
+    (end + 1, Some(params))
+}
+
+/// Color after SGR 38 or 48: `5;n` for the palette or `2;r;g;b`
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let channel = |value: Option<u16>| value.map(|value| value.min(255) as u8);
+    match params.next()? {
+        5 => channel(params.next()).map(Color::Indexed),
+        2 => Some(Color::Rgb(channel(params.next())?, channel(params.next())?, channel(params.next())?)),
+        _ => None,
+    }
+}
+
+fn xterm_rgb(index: u8) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+    ];
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    match index {
+        0..=15 => BASIC[index as usize],
+        16..=231 => {
+            let cube = index - 16;
+            (
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_text_into_styled_runs() {
+        let segments = segments("ok \x1b[1;31mERR\x1b[0m done\x1b[2K\x1b[38;5;208m!");
+        let texts: Vec<&str> = segments.iter().map(|(_, text)| *text).collect();
+        assert_eq!(texts, ["ok ", "ERR", " done", "!"]);
+
+        assert!(segments[0].0.is_plain());
+        assert_eq!(segments[1].0, Style { foreground: Some(Color::Indexed(1)), bold: true, ..Default::default() });
+        assert!(segments[2].0.is_plain());
+        assert_eq!(segments[3].0.foreground.unwrap().to_css(), "#ff8700");
+    }
+
+    #[test]
+    fn handles_truecolor_and_broken_sequences() {
+        let segments = segments("\x1b[48;2;1;2;3mA\x1b[mB\x1bC\x1b[12");
+        assert_eq!(segments[0].0.background, Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(segments[1], (Style::default(), "B"));
+        assert_eq!(segments[2], (Style::default(), "C"));
+        assert_eq!(segments.len(), 3);
+    }
+}
@@ -0,0 +1,490 @@
+//! Espressif ROM serial loader (ESP8266 and ESP32 family)
+//!
+//! Commands are SLIP-framed packets as documented for esptool's serial
+//! protocol. Only the ROM loader is used (no flasher stub), so flash writes go
+//! in 1 KiB blocks and MD5 verification is not available on the ESP8266.
+
+use crate::firmware_image::FirmwareImage;
+use crate::transfer::{self, ControlPort, TransferControl, TransferError};
+use crate::types::EspFlashOptions;
+use anyhow::Result;
+use md5::{Digest, Md5};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const CMD_FLASH_BEGIN: u8 = 0x02;
+const CMD_FLASH_DATA: u8 = 0x03;
+const CMD_FLASH_END: u8 = 0x04;
+const CMD_SYNC: u8 = 0x08;
+const CMD_READ_REG: u8 = 0x0A;
+const CMD_SPI_ATTACH: u8 = 0x0D;
+const CMD_CHANGE_BAUDRATE: u8 = 0x0F;
+const CMD_SPI_FLASH_MD5: u8 = 0x13;
+
+const CHECKSUM_SEED: u8 = 0xEF;
+const FLASH_BLOCK_SIZE: usize = 0x400;
+pub const FLASH_SECTOR_SIZE: u32 = 0x1000;
+const CHIP_DETECT_MAGIC_REG: u32 = 0x4000_1000;
+const ESP8266_MAGIC: u32 = 0xFFF0_C101;
+const ESP32_MAGIC: u32 = 0x00F0_1D83;
+
+const RESET_ATTEMPTS: u32 = 3;
+const SYNC_ATTEMPTS: u32 = 7;
+const SYNC_TIMEOUT: Duration = Duration::from_millis(200);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+const MD5_TIMEOUT_PER_MB: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Error)]
+pub enum EspError {
+    #[error("ESP ROM loader did not answer SYNC; check the boot mode and baud rate")]
+    NoSync,
+    #[error("ESP {command} failed (status {status:#04x}, error {error:#04x})")]
+    CommandFailed { command: &'static str, status: u8, error: u8 },
+    #[error("MD5 mismatch at 0x{offset:08X}: flash {actual}, image {expected}")]
+    Md5Mismatch { offset: u32, expected: String, actual: String },
+    #[error("Malformed {0} response")]
+    MalformedResponse(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Esp8266,
+    Esp32,
+    /// Later ESP32 variants (S2/S3/C3/...), identified by their magic value
+    Esp32Series(u32),
+}
+
+impl Chip {
+    fn from_magic(magic: u32) -> Self {
+        match magic {
+            ESP8266_MAGIC => Chip::Esp8266,
+            ESP32_MAGIC => Chip::Esp32,
+            other => Chip::Esp32Series(other),
+        }
+    }
+
+    /// Trailing status bytes in every ROM loader response
+    fn status_len(&self) -> usize {
+        match self {
+            Chip::Esp8266 => 2,
+            _ => 4,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Chip::Esp8266 => "ESP8266".to_string(),
+            Chip::Esp32 => "ESP32".to_string(),
+            Chip::Esp32Series(magic) => format!("ESP32-series chip (magic 0x{:08X})", magic),
+        }
+    }
+}
+
+/// Outcome of a flashing session for the log summary
+#[derive(Debug, Clone)]
+pub struct FlashReport {
+    pub chip: Chip,
+    pub verified: bool,
+}
+
+impl FlashReport {
+    pub fn summary(&self) -> String {
+        match (self.verified, self.chip) {
+            (true, _) => format!("{}, MD5 verified", self.chip.name()),
+            (false, Chip::Esp8266) => format!("{}, MD5 verify not supported by the ROM loader", self.chip.name()),
+            (false, _) => self.chip.name(),
+        }
+    }
+}
+
+pub fn slip_encode(packet: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(packet.len() + 2);
+    frame.push(SLIP_END);
+    for &byte in packet {
+        match byte {
+            SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            other => frame.push(other),
+        }
+    }
+    frame.push(SLIP_END);
+    frame
+}
+
+/// Read the next non-empty SLIP frame, skipping anything before it (e.g. boot
+/// messages). `None` if no complete frame arrived within `timeout`.
+fn read_slip_frame<P: ControlPort + ?Sized>(
+    port: &mut P,
+    timeout: Duration,
+    control: &TransferControl,
+) -> Result<Option<Vec<u8>>> {
+    let deadline = Instant::now() + timeout;
+    let mut in_frame = false;
+    let mut escaped = false;
+    let mut packet = Vec::new();
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Some(byte) = transfer::read_byte(port, remaining, control)? else {
+            return Ok(None);
+        };
+        if !in_frame {
+            in_frame = byte == SLIP_END;
+            continue;
+        }
+        match (escaped, byte) {
+            (false, SLIP_END) if packet.is_empty() => {}
+            (false, SLIP_END) => return Ok(Some(packet)),
+            (false, SLIP_ESC) => escaped = true,
+            (true, SLIP_ESC_END) => {
+                packet.push(SLIP_END);
+                escaped = false;
+            }
+            (true, SLIP_ESC_ESC) => {
+                packet.push(SLIP_ESC);
+                escaped = false;
+            }
+            (true, _) => {
+                // Invalid escape: drop the frame and resynchronize on the next END
+                packet.clear();
+                escaped = false;
+                in_frame = false;
+            }
+            (false, other) => packet.push(other),
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(CHECKSUM_SEED, |acc, b| acc ^ b) as u32
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Timeout scaled with the amount of flash touched, never below the default
+fn scaled_timeout(per_mb: Duration, size: usize) -> Duration {
+    per_mb.mul_f64(size as f64 / (1024.0 * 1024.0)).max(COMMAND_TIMEOUT)
+}
+
+/// The loader side of a session once the chip is known
+struct Loader<'a, P: ControlPort + ?Sized> {
+    port: &'a mut P,
+    control: &'a TransferControl,
+    /// Status bytes per response; 2 until the chip is detected
+    status_len: usize,
+}
+
+impl<P: ControlPort + ?Sized> Loader<'_, P> {
+    /// Send a command and return the response value and payload (without status bytes)
+    fn command(
+        &mut self,
+        op: u8,
+        name: &'static str,
+        data: &[u8],
+        checksum: u32,
+        timeout: Duration,
+    ) -> Result<(u32, Vec<u8>)> {
+        let mut packet = vec![0x00, op];
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(data);
+        self.port.write_all(&slip_encode(&packet))?;
+        self.port.flush()?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(frame) = read_slip_frame(self.port, remaining, self.control)? else {
+                return Err(TransferError::Timeout(format!("ESP {} response", name)).into());
+            };
+            // Stale responses (e.g. repeated SYNC replies) are skipped
+            if frame.len() < 8 || frame[0] != 0x01 || frame[1] != op {
+                continue;
+            }
+            let value = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+            let body = &frame[8..];
+            if body.len() < self.status_len {
+                return Err(EspError::MalformedResponse(name).into());
+            }
+            let status_at = body.len() - self.status_len;
+            if body[status_at] != 0 {
+                return Err(EspError::CommandFailed {
+                    command: name,
+                    status: body[status_at],
+                    error: body.get(status_at + 1).copied().unwrap_or(0),
+                }
+                .into());
+            }
+            return Ok((value, body[..status_at].to_vec()));
+        }
+    }
+
+    fn sync(&mut self) -> Result<bool> {
+        let mut data = vec![0x07, 0x07, 0x12, 0x20];
+        data.extend_from_slice(&[0x55; 32]);
+        for _ in 0..SYNC_ATTEMPTS {
+            self.control.check_cancelled()?;
+            match self.command(CMD_SYNC, "SYNC", &data, 0, SYNC_TIMEOUT) {
+                Ok(_) => {
+                    // The ROM answers each SYNC several times
+                    transfer::drain_input(self.port, self.control)?;
+                    return Ok(true);
+                }
+                Err(e) if matches!(e.downcast_ref::<TransferError>(), Some(TransferError::Cancelled)) => return Err(e),
+                Err(_) => {}
+            }
+        }
+        Ok(false)
+    }
+
+    fn read_reg(&mut self, address: u32) -> Result<u32> {
+        Ok(self.command(CMD_READ_REG, "READ_REG", &words(&[address]), 0, COMMAND_TIMEOUT)?.0)
+    }
+
+    fn change_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.command(CMD_CHANGE_BAUDRATE, "CHANGE_BAUDRATE", &words(&[baud_rate, 0]), 0, COMMAND_TIMEOUT)?;
+        self.port.set_baud_rate(baud_rate)?;
+        thread::sleep(Duration::from_millis(50));
+        transfer::drain_input(self.port, self.control)
+    }
+
+    fn flash_region(
+        &mut self,
+        chip: Chip,
+        offset: u32,
+        data: &[u8],
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<()> {
+        let num_blocks = data.len().div_ceil(FLASH_BLOCK_SIZE) as u32;
+        let erase_size = match chip {
+            Chip::Esp8266 => esp8266_erase_size(offset, data.len() as u32),
+            _ => data.len() as u32,
+        };
+        let mut params = vec![erase_size, num_blocks, FLASH_BLOCK_SIZE as u32, offset];
+        if let Chip::Esp32Series(_) = chip {
+            // Later chips take an extra "encrypted" word
+            params.push(0);
+        }
+        let erase_timeout = scaled_timeout(ERASE_TIMEOUT_PER_MB, data.len());
+        self.command(CMD_FLASH_BEGIN, "FLASH_BEGIN", &words(&params), 0, erase_timeout)?;
+
+        for (sequence, chunk) in data.chunks(FLASH_BLOCK_SIZE).enumerate() {
+            self.control.check_cancelled()?;
+            let mut block = chunk.to_vec();
+            block.resize(FLASH_BLOCK_SIZE, 0xFF);
+            let mut payload = words(&[FLASH_BLOCK_SIZE as u32, sequence as u32, 0, 0]);
+            payload.extend_from_slice(&block);
+            self.command(CMD_FLASH_DATA, "FLASH_DATA", &payload, checksum(&block), COMMAND_TIMEOUT)?;
+            progress(chunk.len());
+        }
+        Ok(())
+    }
+
+    fn flash_md5(&mut self, offset: u32, len: usize) -> Result<String> {
+        let timeout = scaled_timeout(MD5_TIMEOUT_PER_MB, len);
+        let (_, body) = self.command(CMD_SPI_FLASH_MD5, "SPI_FLASH_MD5", &words(&[offset, len as u32, 0, 0]), 0, timeout)?;
+        match body.len() {
+            // ROM loaders answer in hex text, flasher stubs in raw bytes
+            32 => Ok(String::from_utf8_lossy(&body).to_ascii_lowercase()),
+            16 => Ok(body.iter().map(|b| format!("{:02x}", b)).collect()),
+            _ => Err(EspError::MalformedResponse("SPI_FLASH_MD5").into()),
+        }
+    }
+}
+
+/// Work around the ESP8266 ROM erasing more than requested in FLASH_BEGIN
+/// (same calculation as esptool)
+fn esp8266_erase_size(offset: u32, size: u32) -> u32 {
+    const SECTORS_PER_BLOCK: u32 = 16;
+    let num_sectors = size.div_ceil(FLASH_SECTOR_SIZE);
+    let start_sector = offset / FLASH_SECTOR_SIZE;
+    let head_sectors = (SECTORS_PER_BLOCK - start_sector % SECTORS_PER_BLOCK).min(num_sectors);
+    if num_sectors < 2 * head_sectors {
+        num_sectors.div_ceil(2) * FLASH_SECTOR_SIZE
+    } else {
+        (num_sectors - head_sectors) * FLASH_SECTOR_SIZE
+    }
+}
+
+/// Classic esptool reset: EN low, then release EN with GPIO0 held low
+fn reset_into_loader<P: ControlPort + ?Sized>(port: &mut P) -> Result<()> {
+    port.set_dtr(false)?;
+    port.set_rts(true)?;
+    thread::sleep(Duration::from_millis(100));
+    port.set_dtr(true)?;
+    port.set_rts(false)?;
+    thread::sleep(Duration::from_millis(50));
+    port.set_dtr(false)?;
+    Ok(())
+}
+
+fn hard_reset<P: ControlPort + ?Sized>(port: &mut P) -> Result<()> {
+    port.set_rts(true)?;
+    thread::sleep(Duration::from_millis(100));
+    port.set_rts(false)?;
+    Ok(())
+}
+
+/// Bytes reported as progress for flashing `image`
+pub fn progress_total(image: &FirmwareImage) -> u64 {
+    image.len()
+}
+
+/// Sync with the ROM loader, write every image segment and verify it by MD5
+pub fn flash<P: ControlPort + ?Sized>(
+    port: &mut P,
+    image: &FirmwareImage,
+    options: &EspFlashOptions,
+    control: &TransferControl,
+) -> Result<FlashReport> {
+    let mut loader = Loader { port, control, status_len: 2 };
+
+    let mut synced = false;
+    for _ in 0..RESET_ATTEMPTS {
+        if options.auto_reset {
+            reset_into_loader(loader.port)?;
+        }
+        transfer::drain_input(loader.port, control)?;
+        if loader.sync()? {
+            synced = true;
+            break;
+        }
+    }
+    if !synced {
+        return Err(EspError::NoSync.into());
+    }
+
+    let chip = Chip::from_magic(loader.read_reg(CHIP_DETECT_MAGIC_REG)?);
+    loader.status_len = chip.status_len();
+    if chip != Chip::Esp8266 {
+        loader.command(CMD_SPI_ATTACH, "SPI_ATTACH", &[0; 8], 0, COMMAND_TIMEOUT)?;
+    }
+    if let Some(baud_rate) = options.flash_baud_rate {
+        loader.change_baud_rate(baud_rate)?;
+    }
+
+    let total = progress_total(image);
+    let mut done = 0u64;
+    for segment in &image.segments {
+        loader.flash_region(chip, segment.address, &segment.data, &mut |written| {
+            done += written as u64;
+            control.report(done, total);
+        })?;
+    }
+    // Stay in the loader so the flash can still be read for verification
+    loader.command(CMD_FLASH_END, "FLASH_END", &words(&[1]), 0, COMMAND_TIMEOUT)?;
+
+    let verified = options.verify && chip != Chip::Esp8266;
+    if verified {
+        for segment in &image.segments {
+            let expected: String = Md5::digest(&segment.data).iter().map(|b| format!("{:02x}", b)).collect();
+            let actual = loader.flash_md5(segment.address, segment.data.len())?;
+            if actual != expected {
+                return Err(EspError::Md5Mismatch { offset: segment.address, expected, actual }.into());
+            }
+        }
+    }
+
+    if options.reset_after {
+        hard_reset(loader.port)?;
+    }
+    Ok(FlashReport { chip, verified })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::test_support::{control, duplex_pair, PipeEnd};
+    use std::io::Write;
+
+    fn respond(port: &mut PipeEnd, op: u8, value: u32, body: &[u8]) {
+        let mut packet = vec![0x01, op];
+        packet.extend_from_slice(&((body.len() + 4) as u16).to_le_bytes());
+        packet.extend_from_slice(&value.to_le_bytes());
+        packet.extend_from_slice(body);
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        port.write_all(&slip_encode(&packet)).unwrap();
+    }
+
+    /// ESP32 ROM loader stand-in with 64 KiB of flash
+    fn simulated_esp32(mut port: PipeEnd) -> Vec<u8> {
+        let mut flash = vec![0xFFu8; 0x10000];
+        let mut write_offset = 0usize;
+        let control = control();
+
+        while let Some(frame) = read_slip_frame(&mut port, Duration::from_secs(2), &control).unwrap() {
+            let op = frame[1];
+            let data = &frame[8..];
+            let word = |i: usize| u32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]);
+            match op {
+                CMD_SYNC => respond(&mut port, op, 0, &[]),
+                CMD_READ_REG => respond(&mut port, op, ESP32_MAGIC, &[]),
+                CMD_SPI_ATTACH | CMD_CHANGE_BAUDRATE => respond(&mut port, op, 0, &[]),
+                CMD_FLASH_BEGIN => {
+                    assert_eq!(data.len(), 16);
+                    write_offset = word(3) as usize;
+                    respond(&mut port, op, 0, &[]);
+                }
+                CMD_FLASH_DATA => {
+                    let block = &data[16..];
+                    assert_eq!(u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]), checksum(block));
+                    let start = write_offset + word(1) as usize * FLASH_BLOCK_SIZE;
+                    flash[start..start + block.len()].copy_from_slice(block);
+                    respond(&mut port, op, 0, &[]);
+                }
+                CMD_FLASH_END => respond(&mut port, op, 0, &[]),
+                CMD_SPI_FLASH_MD5 => {
+                    let (start, len) = (word(0) as usize, word(1) as usize);
+                    let hex: String = Md5::digest(&flash[start..start + len]).iter().map(|b| format!("{:02x}", b)).collect();
+                    respond(&mut port, op, 0, hex.as_bytes());
+                    return flash;
+                }
+                other => panic!("unexpected command {:02X}", other),
+            }
+        }
+        flash
+    }
+
+    #[test]
+    fn slip_frames_round_trip() {
+        let packet = vec![1, SLIP_END, 2, SLIP_ESC, 3];
+        let frame = slip_encode(&packet);
+        assert_eq!(frame, vec![SLIP_END, 1, SLIP_ESC, SLIP_ESC_END, 2, SLIP_ESC, SLIP_ESC_ESC, 3, SLIP_END]);
+
+        let (mut writer, mut reader) = duplex_pair();
+        writer.write_all(b"boot noise").unwrap();
+        writer.write_all(&frame).unwrap();
+        assert_eq!(read_slip_frame(&mut reader, Duration::from_secs(1), &control()).unwrap(), Some(packet));
+    }
+
+    #[test]
+    fn flashes_binary_at_offset_with_md5_verify() {
+        let data: Vec<u8> = (0..2500u32).map(|i| (i % 253) as u8).collect();
+        let image = FirmwareImage::from_binary(0x8000, data.clone());
+        let (mut host, target) = duplex_pair();
+        let target = thread::spawn(move || simulated_esp32(target));
+
+        let options = EspFlashOptions { auto_reset: false, reset_after: false, ..Default::default() };
+        let report = flash(&mut host, &image, &options, &control()).unwrap();
+        let flash_contents = target.join().unwrap();
+
+        assert_eq!(report.summary(), "ESP32, MD5 verified");
+        assert_eq!(&flash_contents[0x8000..0x8000 + data.len()], &data[..]);
+    }
+
+    #[test]
+    fn esp8266_erase_size_matches_esptool() {
+        assert_eq!(esp8266_erase_size(0, 0x1000), 0x1000);
+        assert_eq!(esp8266_erase_size(0, 0x10000), 0x8000);
+        assert_eq!(esp8266_erase_size(0, 0x40000), 0x30000);
+    }
+}
@@ -0,0 +1,337 @@
+//! Log export to TXT, CSV, JSON, pcapng and HTML files
+//!
+//! TXT and CSV hold each entry's bytes as (lossy) text. The hex and base64
+//! options add lossless copies of the bytes, which `log_import` prefers when
+//! the file is read back. JSON always holds the bytes and the display text.
+//! Timestamps are written with the full date and the UTC offset.
+//!
+//! pcapng captures use link type USER0 (147) with one interface per port, so
+//! a Wireshark dissector can be assigned to the payload via DLT_USER. Each
+//! sent or received entry is one packet whose direction is stored in the
+//! `epb_flags` option; bookmarks become packet comments. System entries are
+//! not traffic and are left out.
+//!
+//! HTML pages are written by `html_export`.
+
+use crate::html_export;
+use crate::serial_manager::format_bytes_as_hex;
+use crate::types::{Direction, DisplaySettings, ExportFilter, ExportFormat, ExportOptions, LogEntry};
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{FixedOffset, Utc};
+use regex::bytes::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Prefix of the TXT lines that annotate the entry above them
+pub const TXT_ANNOTATION_PREFIX: &str = "    >> ";
+
+/// Entry timestamps of TXT exports and text recordings
+pub const TXT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f %:z";
+
+// pcapng block types, options and constants
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPT_END: u16 = 0;
+const PCAPNG_OPT_COMMENT: u16 = 1;
+const PCAPNG_SHB_USERAPPL: u16 = 4;
+const PCAPNG_IF_NAME: u16 = 2;
+const PCAPNG_EPB_FLAGS: u16 = 2;
+const PCAPNG_EPB_INBOUND: u32 = 0b01;
+const PCAPNG_EPB_OUTBOUND: u32 = 0b10;
+/// LINKTYPE_USER0, free for private encapsulations
+const LINKTYPE_USER0: u16 = 147;
+
+/// Log entry as written by the JSON export, with its bookmark note
+#[derive(Serialize)]
+struct ExportedEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a LogEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bookmark: Option<&'a str>,
+}
+
+/// Write `logs` in `format`. `bookmarks` maps entry ids to their notes;
+/// `settings` gives the text encoding of the HTML report.
+pub fn write_logs<W: Write>(
+    mut writer: W,
+    logs: &[LogEntry],
+    bookmarks: &HashMap<i64, String>,
+    format: &ExportFormat,
+    options: &ExportOptions,
+    settings: &DisplaySettings,
+    tz_offset: &FixedOffset,
+) -> Result<()> {
+    let bookmark_note = |entry: &LogEntry| entry.id.and_then(|id| bookmarks.get(&id)).map(String::as_str);
+
+    match format {
+        ExportFormat::Txt => {
+            let now_with_tz = Utc::now().with_timezone(tz_offset);
+            writeln!(writer, "RSerial Debug Assistant - Log Export")?;
+            writeln!(writer, "Generated: {}", now_with_tz.format("%Y-%m-%d %H:%M:%S %z"))?;
+            writeln!(writer, "{}", "=".repeat(60))?;
+            writeln!(writer)?;
+
+            for log in logs {
+                writeln!(
+                    writer,
+                    "[{}] {}: {}",
+                    log.timestamp.with_timezone(tz_offset).format(TXT_TIMESTAMP_FORMAT),
+                    log.direction.label(),
+                    String::from_utf8_lossy(&log.data)
+                )?;
+                if options.include_hex {
+                    writeln!(writer, "{}Hex: {}", TXT_ANNOTATION_PREFIX, format_bytes_as_hex(&log.data))?;
+                }
+                if options.include_base64 {
+                    writeln!(writer, "{}Base64: {}", TXT_ANNOTATION_PREFIX, BASE64.encode(&log.data))?;
+                }
+                if options.include_display_text {
+                    // Keep the annotation on one line
+                    let display_text = log.display_text.replace('\r', "\\r").replace('\n', "\\n");
+                    writeln!(writer, "{}Display: {}", TXT_ANNOTATION_PREFIX, display_text)?;
+                }
+                if let Some(note) = bookmark_note(log) {
+                    writeln!(writer, "{}Bookmark: {}", TXT_ANNOTATION_PREFIX, note)?;
+                }
+            }
+        }
+        ExportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            let mut header = vec!["timestamp", "direction", "port", "data"];
+            if options.include_hex {
+                header.push("hex");
+            }
+            if options.include_base64 {
+                header.push("base64");
+            }
+            if options.include_display_text {
+                header.push("display_text");
+            }
+            header.push("bookmark");
+            csv_writer.write_record(&header)?;
+
+            for log in logs {
+                let mut record = vec![
+                    log.timestamp.with_timezone(tz_offset).to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+                    format!("{:?}", log.direction),
+                    log.port_name.clone(),
+                    String::from_utf8_lossy(&log.data).into_owned(),
+                ];
+                if options.include_hex {
+                    record.push(format_bytes_as_hex(&log.data));
+                }
+                if options.include_base64 {
+                    record.push(BASE64.encode(&log.data));
+                }
+                if options.include_display_text {
+                    record.push(log.display_text.clone());
+                }
+                record.push(bookmark_note(log).unwrap_or_default().to_string());
+                csv_writer.write_record(&record)?;
+            }
+            csv_writer.flush()?;
+        }
+        ExportFormat::Pcapng => write_pcapng(writer, logs, bookmarks)?,
+        ExportFormat::Html => html_export::write_html(writer, logs, bookmarks, settings, tz_offset)?,
+        ExportFormat::Json => {
+            let entries: Vec<ExportedEntry> = logs
+                .iter()
+                .map(|entry| ExportedEntry { bookmark: bookmark_note(entry), entry })
+                .collect();
+            serde_json::to_writer_pretty(&mut writer, &entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `entry` passes every filter that is set; `pattern` is the
+/// filter's compiled pattern
+pub fn filter_matches(filter: &ExportFilter, pattern: Option<&Regex>, entry: &LogEntry) -> bool {
+    filter.start_time.is_none_or(|start| entry.timestamp >= start)
+        && filter.end_time.is_none_or(|end| entry.timestamp <= end)
+        && filter.direction.as_ref().is_none_or(|direction| entry.direction == *direction)
+        && (filter.entry_ids.is_empty() || entry.id.is_some_and(|id| filter.entry_ids.contains(&id)))
+        && !(filter.exclude_hidden && entry.hidden)
+        && pattern.is_none_or(|pattern| pattern.is_match(&entry.data))
+}
+
+fn write_pcapng<W: Write>(mut writer: W, logs: &[LogEntry], bookmarks: &HashMap<i64, String>) -> Result<()> {
+    let mut options = Vec::new();
+    push_option(&mut options, PCAPNG_SHB_USERAPPL, b"RSerial Debug Assistant");
+    push_option(&mut options, PCAPNG_OPT_END, &[]);
+    let mut body = Vec::new();
+    body.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes()); // major version
+    body.extend_from_slice(&0u16.to_le_bytes()); // minor version
+    body.extend_from_slice(&(-1i64).to_le_bytes()); // section length not specified
+    body.extend_from_slice(&options);
+    write_block(&mut writer, PCAPNG_SECTION_HEADER, &body)?;
+
+    let mut interfaces: HashMap<&str, u32> = HashMap::new();
+    for log in logs {
+        let flags = match log.direction {
+            Direction::Received => PCAPNG_EPB_INBOUND,
+            Direction::Sent => PCAPNG_EPB_OUTBOUND,
+            Direction::System => continue,
+        };
+
+        let interface_id = match interfaces.get(log.port_name.as_str()) {
+            Some(id) => *id,
+            None => {
+                let id = interfaces.len() as u32;
+                let mut body = Vec::new();
+                body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+                body.extend_from_slice(&0u16.to_le_bytes()); // reserved
+                body.extend_from_slice(&0u32.to_le_bytes()); // no snapshot length limit
+                if !log.port_name.is_empty() {
+                    push_option(&mut body, PCAPNG_IF_NAME, log.port_name.as_bytes());
+                }
+                push_option(&mut body, PCAPNG_OPT_END, &[]);
+                write_block(&mut writer, PCAPNG_INTERFACE_DESCRIPTION, &body)?;
+                interfaces.insert(&log.port_name, id);
+                id
+            }
+        };
+
+        // Default timestamp resolution is microseconds
+        let timestamp = log.timestamp.timestamp_micros() as u64;
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface_id.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        body.extend_from_slice(&(log.data.len() as u32).to_le_bytes()); // captured length
+        body.extend_from_slice(&(log.data.len() as u32).to_le_bytes()); // original length
+        body.extend_from_slice(&log.data);
+        pad_to_32_bits(&mut body);
+        push_option(&mut body, PCAPNG_EPB_FLAGS, &flags.to_le_bytes());
+        if let Some(note) = log.id.and_then(|id| bookmarks.get(&id)) {
+            push_option(&mut body, PCAPNG_OPT_COMMENT, note.as_bytes());
+        }
+        push_option(&mut body, PCAPNG_OPT_END, &[]);
+        write_block(&mut writer, PCAPNG_ENHANCED_PACKET, &body)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write a block with its type and the total length before and after `body`
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> Result<()> {
+    let total_length = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_length.to_le_bytes())?;
+    Ok(())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad_to_32_bits(body);
+}
+
+fn pad_to_32_bits(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DataFormat;
+    use chrono::TimeZone;
+
+    fn entry(id: i64, data: &[u8]) -> LogEntry {
+        LogEntry {
+            id: Some(id),
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            direction: Direction::Received,
+            data: data.to_vec(),
+            format: DataFormat::Text,
+            port_name: "COM3".to_string(),
+            display_text: "a,\"b\"\nc".to_string(),
+            timestamp_formatted: None,
+            hidden: false,
+            highlights: Vec::new(),
+        }
+    }
+
+    fn export(format: ExportFormat, options: &ExportOptions) -> String {
+        let bookmarks = HashMap::from([(2, "second".to_string())]);
+        let logs = [entry(1, &[0x00, 0xFF, b'\n']), entry(2, b"OK")];
+        let mut out = Vec::new();
+        write_logs(&mut out, &logs, &bookmarks, &format, options, &DisplaySettings::default(), &FixedOffset::east_opt(8 * 3600).unwrap()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_escapes_fields_and_adds_binary_columns() {
+        let options = ExportOptions { include_hex: true, include_base64: true, include_display_text: true };
+        let csv = export(ExportFormat::Csv, &options);
+        let mut lines = csv.lines();
+
+        assert_eq!(lines.next().unwrap(), "timestamp,direction,port,data,hex,base64,display_text,bookmark");
+        assert_eq!(lines.next().unwrap(), "2024-03-01T08:00:00.000+08:00,Received,COM3,\"\0\u{FFFD}");
+        assert_eq!(lines.next().unwrap(), "\",00 FF 0A,AP8K,\"a,\"\"b\"\"");
+        assert!(csv.ends_with("c\",second\n"));
+    }
+
+    #[test]
+    fn pcapng_writes_one_packet_per_transfer_with_direction() {
+        let bookmarks = HashMap::from([(2, "ack".to_string())]);
+        let mut sent = entry(1, b"PING!");
+        sent.direction = Direction::Sent;
+        let mut system = entry(3, b"note");
+        system.direction = Direction::System;
+        let logs = [sent, entry(2, b"PONG"), system];
+        let mut out = Vec::new();
+        write_logs(&mut out, &logs, &bookmarks, &ExportFormat::Pcapng, &ExportOptions::default(), &DisplaySettings::default(), &FixedOffset::east_opt(0).unwrap()).unwrap();
+
+        // Walk the blocks by their lengths
+        let mut blocks = Vec::new();
+        let mut rest = out.as_slice();
+        while !rest.is_empty() {
+            let block_type = u32::from_le_bytes(rest[0..4].try_into().unwrap());
+            let length = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(rest[length - 4..length], rest[4..8]);
+            blocks.push((block_type, rest[8..length - 4].to_vec()));
+            rest = &rest[length..];
+        }
+
+        let types: Vec<u32> = blocks.iter().map(|(block_type, _)| *block_type).collect();
+        assert_eq!(types, [PCAPNG_SECTION_HEADER, PCAPNG_INTERFACE_DESCRIPTION, PCAPNG_ENHANCED_PACKET, PCAPNG_ENHANCED_PACKET]);
+        assert_eq!(blocks[0].1[0..4], PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        assert_eq!(blocks[1].1[0..2], LINKTYPE_USER0.to_le_bytes());
+
+        let packet = &blocks[2].1;
+        let micros = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap().timestamp_micros() as u64;
+        let low = u32::from_le_bytes(packet[8..12].try_into().unwrap());
+        assert_eq!(low, micros as u32);
+        assert_eq!(packet[12..16], 5u32.to_le_bytes());
+        assert_eq!(&packet[20..25], b"PING!");
+        // Data padded to 8 bytes, then the flags option
+        assert_eq!(packet[28..36], [2, 0, 4, 0, 2, 0, 0, 0]);
+
+        let reply = &blocks[3].1;
+        assert_eq!(reply[24..32], [2, 0, 4, 0, 1, 0, 0, 0]);
+        assert_eq!(reply[32..39], [1, 0, 3, 0, b'a', b'c', b'k']);
+    }
+
+    #[test]
+    fn txt_annotates_entries_with_full_timestamps() {
+        let txt = export(ExportFormat::Txt, &ExportOptions { include_base64: true, ..Default::default() });
+        let body: Vec<&str> = txt.lines().skip(4).collect();
+
+        assert_eq!(body[0], "[2024-03-01 08:00:00.000 +08:00] RX: \0\u{FFFD}");
+        assert_eq!(body[2], "    >> Base64: AP8K");
+        assert_eq!(body[3], "[2024-03-01 08:00:00.000 +08:00] RX: OK");
+        assert_eq!(body[5], "    >> Bookmark: second");
+    }
+}
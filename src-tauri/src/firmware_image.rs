@@ -0,0 +1,395 @@
+//! Firmware images for bootloader flashing
+//!
+//! An image is a list of non-overlapping segments ordered by address. Raw
+//! binaries become a single segment at a caller-supplied base address; Intel
+//! HEX and Motorola S-record files keep the addresses recorded in the file.
+//! Gaps between segments are kept until a caller flattens the image.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use thiserror::Error;
+
+/// Parse error with the 1-based line number of the offending record
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ImageError {
+    #[error("Line {line}: {reason}")]
+    InvalidRecord { line: usize, reason: String },
+    #[error("Line {line}: checksum mismatch (expected {expected:02X}, found {found:02X})")]
+    ChecksumMismatch { line: usize, expected: u8, found: u8 },
+    #[error("Data at 0x{address:08X} overlaps earlier data")]
+    Overlap { address: u32 },
+    #[error("Image contains no data")]
+    Empty,
+    #[error("Image spans {span} bytes (0x{start:08X}..0x{end:08X}), too sparse to send as one binary")]
+    SpanTooLarge { start: u32, end: u64, span: u64 },
+}
+
+/// Largest address range `flatten` will turn into a single buffer
+pub const MAX_FLAT_SPAN: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+impl ImageFormat {
+    /// Guess the format from a file extension; anything unknown is binary
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "hex" | "ihex" | "ihx" => ImageFormat::IntelHex,
+            "s19" | "s28" | "s37" | "srec" | "mot" | "sx" => ImageFormat::SRecord,
+            _ => ImageFormat::Binary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// Address one past the last byte
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.data.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FirmwareImage {
+    pub segments: Vec<Segment>,
+    /// Start address from the file, if it recorded one
+    pub entry_point: Option<u32>,
+}
+
+impl FirmwareImage {
+    pub fn from_binary(address: u32, data: Vec<u8>) -> Self {
+        Self {
+            segments: vec![Segment { address, data }],
+            entry_point: None,
+        }
+    }
+
+    /// Build an image from possibly unordered chunks, merging adjacent ones
+    fn from_chunks(mut chunks: Vec<(u32, Vec<u8>)>, entry_point: Option<u32>) -> Result<Self, ImageError> {
+        chunks.retain(|(_, data)| !data.is_empty());
+        if chunks.is_empty() {
+            return Err(ImageError::Empty);
+        }
+        chunks.sort_by_key(|(address, _)| *address);
+
+        let mut segments: Vec<Segment> = Vec::new();
+        for (address, data) in chunks {
+            match segments.last_mut() {
+                Some(last) if (address as u64) < last.end() => return Err(ImageError::Overlap { address }),
+                Some(last) if address as u64 == last.end() => last.data.extend_from_slice(&data),
+                _ => segments.push(Segment { address, data }),
+            }
+        }
+        Ok(Self { segments, entry_point })
+    }
+
+    /// Number of data bytes (gaps excluded)
+    pub fn len(&self) -> u64 {
+        self.segments.iter().map(|s| s.data.len() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lowest address holding data
+    pub fn start_address(&self) -> Option<u32> {
+        self.segments.first().map(|s| s.address)
+    }
+
+    /// Merge segments separated by at most `max_gap` bytes, filling the gap with `fill`
+    pub fn fill_gaps(&mut self, max_gap: u32, fill: u8) {
+        let mut merged: Vec<Segment> = Vec::with_capacity(self.segments.len());
+        for segment in self.segments.drain(..) {
+            match merged.last_mut() {
+                Some(last) if segment.address as u64 - last.end() <= max_gap as u64 => {
+                    let gap = (segment.address as u64 - last.end()) as usize;
+                    last.data.resize(last.data.len() + gap, fill);
+                    last.data.extend_from_slice(&segment.data);
+                }
+                _ => merged.push(segment),
+            }
+        }
+        self.segments = merged;
+    }
+
+    /// One contiguous buffer from the lowest to the highest address, gaps
+    /// filled with `fill`. Returns the start address and the data.
+    pub fn flatten(&self, fill: u8) -> Result<(u32, Vec<u8>), ImageError> {
+        let (Some(first), Some(last)) = (self.segments.first(), self.segments.last()) else {
+            return Err(ImageError::Empty);
+        };
+        let span = last.end() - first.address as u64;
+        if span > MAX_FLAT_SPAN {
+            return Err(ImageError::SpanTooLarge { start: first.address, end: last.end(), span });
+        }
+        let mut data = vec![fill; span as usize];
+        for segment in &self.segments {
+            let offset = (segment.address - first.address) as usize;
+            data[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        Ok((first.address, data))
+    }
+}
+
+/// Load `path` as Intel HEX or S-record (by extension) or as a raw binary
+/// placed at `base_address`
+pub fn load_file(path: &Path, base_address: u32) -> Result<FirmwareImage> {
+    let format = ImageFormat::from_path(path);
+    let image = match format {
+        ImageFormat::IntelHex | ImageFormat::SRecord => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let (parsed, name) = match format {
+                ImageFormat::IntelHex => (parse_intel_hex(&text), "Intel HEX"),
+                _ => (parse_srecord(&text), "S-record"),
+            };
+            parsed.with_context(|| format!("Invalid {} file {}", name, path.display()))?
+        }
+        ImageFormat::Binary => {
+            let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            FirmwareImage::from_binary(base_address, data)
+        }
+    };
+    if image.is_empty() {
+        return Err(ImageError::Empty.into());
+    }
+    Ok(image)
+}
+
+/// Parse an Intel HEX file (record types 00-05)
+pub fn parse_intel_hex(text: &str) -> Result<FirmwareImage, ImageError> {
+    let mut chunks = Vec::new();
+    let mut upper_address: u32 = 0;
+    let mut entry_point = None;
+    let mut seen_eof = false;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = raw_line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        if seen_eof {
+            return Err(invalid(line, "data after end-of-file record"));
+        }
+        let Some(hex) = record.strip_prefix(':') else {
+            return Err(invalid(line, "record does not start with ':'"));
+        };
+        let bytes = decode_hex(hex).ok_or_else(|| invalid(line, "record contains non-hex characters or an odd digit count"))?;
+        if bytes.len() < 5 {
+            return Err(invalid(line, "record is too short"));
+        }
+        let count = bytes[0] as usize;
+        if bytes.len() != count + 5 {
+            return Err(invalid(line, format!("byte count {} does not match record length", count)));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = body.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)).wrapping_neg();
+        if expected != checksum[0] {
+            return Err(ImageError::ChecksumMismatch { line, expected, found: checksum[0] });
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..4 + count];
+        match bytes[3] {
+            0x00 => chunks.push((upper_address.wrapping_add(offset), data.to_vec())),
+            0x01 => seen_eof = true,
+            0x02 if count == 2 => upper_address = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if count == 2 => upper_address = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            0x03 if count == 4 => {
+                let segment = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let offset = u16::from_be_bytes([data[2], data[3]]) as u32;
+                entry_point = Some((segment << 4) + offset);
+            }
+            0x05 if count == 4 => entry_point = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+            0x02..=0x05 => return Err(invalid(line, format!("record type {:02X} has wrong length {}", bytes[3], count))),
+            other => return Err(invalid(line, format!("unknown record type {:02X}", other))),
+        }
+    }
+
+    if !seen_eof {
+        return Err(invalid(text.lines().count().max(1), "missing end-of-file record"));
+    }
+    FirmwareImage::from_chunks(chunks, entry_point)
+}
+
+/// Parse a Motorola S-record file (S0-S3, S5-S9)
+pub fn parse_srecord(text: &str) -> Result<FirmwareImage, ImageError> {
+    let mut chunks = Vec::new();
+    let mut entry_point = None;
+    let mut data_records: u32 = 0;
+    let mut seen_end = false;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = raw_line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        if seen_end {
+            return Err(invalid(line, "data after termination record"));
+        }
+        let mut chars = record.chars();
+        if chars.next() != Some('S') {
+            return Err(invalid(line, "record does not start with 'S'"));
+        }
+        let kind = chars.next().and_then(|c| c.to_digit(10)).ok_or_else(|| invalid(line, "missing record type"))?;
+        let bytes = decode_hex(&record[2..]).ok_or_else(|| invalid(line, "record contains non-hex characters or an odd digit count"))?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(invalid(line, "byte count does not match record length"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = !body.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        if expected != checksum[0] {
+            return Err(ImageError::ChecksumMismatch { line, expected, found: checksum[0] });
+        }
+
+        let address_len = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(invalid(line, format!("unknown record type S{}", kind))),
+        };
+        let fields = &body[1..];
+        if fields.len() < address_len {
+            return Err(invalid(line, "record is too short for its address"));
+        }
+        let address = fields[..address_len].iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        let data = &fields[address_len..];
+
+        match kind {
+            0 => {}
+            1..=3 => {
+                chunks.push((address, data.to_vec()));
+                data_records += 1;
+            }
+            5 | 6 if address != data_records => {
+                return Err(invalid(line, format!("record count {} does not match {} data records", address, data_records)));
+            }
+            5 | 6 => {}
+            _ => {
+                entry_point = Some(address);
+                seen_end = true;
+            }
+        }
+    }
+
+    if !seen_end {
+        return Err(invalid(text.lines().count().max(1), "missing termination record"));
+    }
+    FirmwareImage::from_chunks(chunks, entry_point)
+}
+
+fn invalid(line: usize, reason: impl Into<String>) -> ImageError {
+    ImageError::InvalidRecord { line, reason: reason.into() }
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            // from_str_radix alone would accept a sign, e.g. "+F"
+            if pair.len() != 2 || !pair.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_intel_hex_with_extended_address() {
+        let text = ":020000040800F2\n\
+                    :0400000001020304F2\n\
+                    :02000400AABB95\n\
+                    :0400000508000101ED\n\
+                    :00000001FF\n";
+        let image = parse_intel_hex(text).unwrap();
+
+        assert_eq!(
+            image.segments,
+            vec![Segment { address: 0x0800_0000, data: vec![1, 2, 3, 4, 0xAA, 0xBB] }]
+        );
+        assert_eq!(image.entry_point, Some(0x0800_0101));
+        assert_eq!(image.len(), 6);
+    }
+
+    #[test]
+    fn reports_bad_checksum_with_line_number() {
+        let err = parse_intel_hex(":0400000001020304F2\n:0100000000FE\n:00000001FF\n").unwrap_err();
+        assert_eq!(err, ImageError::ChecksumMismatch { line: 2, expected: 0xFF, found: 0xFE });
+
+        let err = parse_intel_hex(":0400000001020304F2\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 1, .. }));
+    }
+
+    #[test]
+    fn parses_srecords_with_gaps() {
+        let text = "S00600004844521B\n\
+                    S107000001020304EE\n\
+                    S30908000010AABBCCDDD0\n\
+                    S5030002FA\n\
+                    S70508000000F2\n";
+        let image = parse_srecord(text).unwrap();
+
+        assert_eq!(
+            image.segments,
+            vec![
+                Segment { address: 0, data: vec![1, 2, 3, 4] },
+                Segment { address: 0x0800_0010, data: vec![0xAA, 0xBB, 0xCC, 0xDD] },
+            ]
+        );
+        assert_eq!(image.entry_point, Some(0x0800_0000));
+    }
+
+    #[test]
+    fn srecord_errors_name_the_line() {
+        let err = parse_srecord("S107000001020304EF\n").unwrap_err();
+        assert_eq!(err, ImageError::ChecksumMismatch { line: 1, expected: 0xEE, found: 0xEF });
+
+        let err = parse_srecord("S107000001020304EE\nS5030002FA\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 2, .. }));
+
+        // Truncated before the S9 record
+        let err = parse_srecord("S107000001020304EE\nS5030001FB\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 2, .. }));
+
+        let err = parse_srecord("S10700+001020304EE\nS9030000FC\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 1, .. }));
+        assert!(parse_srecord("S107000001020304EE\nS9030000FC\n").is_ok());
+    }
+
+    #[test]
+    fn flattens_and_merges_gaps() {
+        let mut image = FirmwareImage::from_chunks(vec![(0x100, vec![1, 2]), (0x104, vec![3])], None).unwrap();
+        assert_eq!(image.flatten(0xFF).unwrap(), (0x100, vec![1, 2, 0xFF, 0xFF, 3]));
+
+        image.fill_gaps(2, 0x00);
+        assert_eq!(image.segments, vec![Segment { address: 0x100, data: vec![1, 2, 0, 0, 3] }]);
+
+        let sparse = FirmwareImage::from_chunks(vec![(0, vec![1]), (0x2000_0000, vec![2])], None).unwrap();
+        assert!(matches!(sparse.flatten(0xFF), Err(ImageError::SpanTooLarge { .. })));
+        assert!(matches!(
+            FirmwareImage::from_chunks(vec![(0, vec![1, 2]), (1, vec![3])], None),
+            Err(ImageError::Overlap { address: 1 })
+        ));
+    }
+}
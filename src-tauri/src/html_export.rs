@@ -0,0 +1,248 @@
+//! Standalone HTML log export
+//!
+//! The file embeds its own style and script, so it opens in any browser.
+//! Every entry holds both its text (with ANSI colors rendered) and its hex
+//! bytes; the page switches between them. Bookmarked entries carry their note
+//! and can be stepped through or shown on their own. Entries hidden by log
+//! rules are included but only shown on request.
+
+use crate::ansi;
+use crate::send_syntax;
+use crate::serial_manager::format_bytes_as_hex;
+use crate::types::{Direction, DisplaySettings, LogEntry};
+use anyhow::Result;
+use chrono::{FixedOffset, Utc};
+use std::collections::HashMap;
+use std::io::Write;
+
+const TITLE: &str = "RSerial Debug Assistant - Log Export";
+
+const STYLE: &str = r#"
+body { margin: 0; background: #1e1e1e; color: #d4d4d4; font: 13px/1.5 -apple-system, "Segoe UI", sans-serif; }
+header { position: sticky; top: 0; padding: 8px 16px; background: #252526; border-bottom: 1px solid #3c3c3c; }
+h1 { margin: 0; font-size: 16px; }
+.summary { margin: 2px 0 6px; color: #9d9d9d; }
+button { margin-right: 6px; padding: 3px 10px; border: 1px solid #3c3c3c; border-radius: 4px; background: #333; color: inherit; cursor: pointer; }
+label { margin-right: 12px; }
+main { padding: 8px 16px; font-family: ui-monospace, Menlo, Consolas, monospace; }
+.entry { padding: 1px 6px; border-left: 3px solid transparent; white-space: pre-wrap; word-break: break-all; }
+.entry.tx { border-left-color: #3794ff; }
+.entry.tx .dir { color: #3794ff; }
+.entry.rx { border-left-color: #4ec9b0; }
+.entry.rx .dir { color: #4ec9b0; }
+.entry.sys { color: #9d9d9d; font-style: italic; }
+.entry.bookmarked { background: rgba(255, 204, 0, 0.08); }
+.entry.current { outline: 1px solid #ffcc00; }
+.ts { color: #808080; margin-right: 8px; }
+.dir { font-weight: bold; margin-right: 8px; }
+.note { color: #ffcc00; }
+.note::before { content: "\2691  "; }
+.hex { display: none; }
+body.show-hex .hex { display: inline; }
+body.show-hex .text { display: none; }
+.rule-hidden { display: none; }
+body.show-hidden .rule-hidden { display: block; opacity: 0.5; }
+body.bookmarks-only .entry:not(.bookmarked) { display: none; }
+"#;
+
+const SCRIPT: &str = r#"
+var current = -1;
+function toggleHex() {
+  document.body.classList.toggle('show-hex');
+}
+function toggleClass(name, on) {
+  document.body.classList.toggle(name, on);
+}
+function jumpBookmark(step) {
+  var marks = document.querySelectorAll('.entry.bookmarked');
+  if (marks.length === 0) return;
+  if (current >= 0 && marks[current]) marks[current].classList.remove('current');
+  current = (current + step + marks.length) % marks.length;
+  marks[current].classList.add('current');
+  marks[current].scrollIntoView({ block: 'center' });
+}
+"#;
+
+/// Write `logs` as a standalone HTML page. `bookmarks` maps entry ids to
+/// their notes; text is decoded with the encoding in `settings`.
+pub fn write_html<W: Write>(
+    mut writer: W,
+    logs: &[LogEntry],
+    bookmarks: &HashMap<i64, String>,
+    settings: &DisplaySettings,
+    tz_offset: &FixedOffset,
+) -> Result<()> {
+    let bookmark_count = logs
+        .iter()
+        .filter(|entry| entry.id.is_some_and(|id| bookmarks.contains_key(&id)))
+        .count();
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>\n<style>{}</style>\n</head>", TITLE, STYLE)?;
+    writeln!(writer, "<body>\n<header>\n<h1>{}</h1>", TITLE)?;
+    writeln!(
+        writer,
+        "<p class=\"summary\">Generated: {} &middot; {} entries &middot; {} bookmarks</p>",
+        Utc::now().with_timezone(tz_offset).format("%Y-%m-%d %H:%M:%S %:z"),
+        logs.len(),
+        bookmark_count
+    )?;
+    writeln!(writer, "<div>")?;
+    writeln!(writer, "<button type=\"button\" onclick=\"toggleHex()\">Text / Hex</button>")?;
+    writeln!(writer, "<button type=\"button\" onclick=\"jumpBookmark(-1)\">Previous bookmark</button>")?;
+    writeln!(writer, "<button type=\"button\" onclick=\"jumpBookmark(1)\">Next bookmark</button>")?;
+    writeln!(
+        writer,
+        "<label><input type=\"checkbox\" onchange=\"toggleClass('bookmarks-only', this.checked)\"> Bookmarks only</label>"
+    )?;
+    writeln!(
+        writer,
+        "<label><input type=\"checkbox\" onchange=\"toggleClass('show-hidden', this.checked)\"> Hidden entries</label>"
+    )?;
+    writeln!(writer, "</div>\n</header>\n<main>")?;
+
+    for log in logs {
+        write_entry(&mut writer, log, log.id.and_then(|id| bookmarks.get(&id)), settings, tz_offset)?;
+    }
+
+    writeln!(writer, "</main>\n<script>{}</script>\n</body>\n</html>", SCRIPT)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_entry<W: Write>(
+    writer: &mut W,
+    log: &LogEntry,
+    bookmark: Option<&String>,
+    settings: &DisplaySettings,
+    tz_offset: &FixedOffset,
+) -> Result<()> {
+    let mut classes = vec![match log.direction {
+        Direction::Sent => "entry tx",
+        Direction::Received => "entry rx",
+        Direction::System => "entry sys",
+    }];
+    if bookmark.is_some() {
+        classes.push("bookmarked");
+    }
+    if log.hidden {
+        classes.push("rule-hidden");
+    }
+
+    write!(writer, "<div class=\"{}\"", classes.join(" "))?;
+    if let Some(id) = log.id {
+        write!(writer, " id=\"entry-{}\"", id)?;
+    }
+    if !log.highlights.is_empty() {
+        write!(writer, " data-highlights=\"{}\"", escape(&log.highlights.join(" ")))?;
+    }
+    write!(
+        writer,
+        "><span class=\"ts\">{}</span><span class=\"dir\">{}</span>",
+        log.timestamp.with_timezone(tz_offset).format("%Y-%m-%d %H:%M:%S%.3f %:z"),
+        log.direction.label()
+    )?;
+    // System entries carry their summary text as UTF-8
+    let text = match log.direction {
+        Direction::System => String::from_utf8_lossy(&log.data).into_owned(),
+        _ => send_syntax::decode_text(&log.data, &settings.encoding),
+    };
+    write!(
+        writer,
+        "<span class=\"text\">{}</span><span class=\"hex\">{}</span>",
+        ansi_to_html(&text),
+        format_bytes_as_hex(&log.data)
+    )?;
+    if let Some(note) = bookmark {
+        write!(writer, "\n<span class=\"note\">{}</span>", escape(note))?;
+    }
+    writeln!(writer, "</div>")?;
+    Ok(())
+}
+
+/// Escaped text with one styled span per ANSI-colored run
+fn ansi_to_html(text: &str) -> String {
+    let mut html = String::new();
+    for (style, run) in ansi::segments(text) {
+        if style.is_plain() {
+            html.push_str(&escape(run));
+            continue;
+        }
+
+        let mut css = Vec::new();
+        if let Some(color) = style.foreground {
+            css.push(format!("color:{}", color.to_css()));
+        }
+        if let Some(color) = style.background {
+            css.push(format!("background:{}", color.to_css()));
+        }
+        if style.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if style.italic {
+            css.push("font-style:italic".to_string());
+        }
+        if style.underline {
+            css.push("text-decoration:underline".to_string());
+        }
+        html.push_str(&format!("<span style=\"{}\">{}</span>", css.join(";"), escape(run)));
+    }
+    html
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DataFormat, TextEncoding};
+    use chrono::TimeZone;
+
+    #[test]
+    fn renders_entries_with_colors_hex_and_bookmarks() {
+        let entry = LogEntry {
+            id: Some(7),
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+            direction: Direction::Received,
+            data: b"<b>\x1b[32mOK\x1b[0m".to_vec(),
+            format: DataFormat::Text,
+            port_name: "COM3".to_string(),
+            display_text: String::new(),
+            timestamp_formatted: None,
+            hidden: false,
+            highlights: vec!["ok".to_string()],
+        };
+        let bookmarks = HashMap::from([(7, "first \"OK\"".to_string())]);
+        let mut out = Vec::new();
+        let settings = DisplaySettings::default();
+        write_html(&mut out, std::slice::from_ref(&entry), &bookmarks, &settings, &FixedOffset::east_opt(3600).unwrap()).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains("1 entries &middot; 1 bookmarks"));
+        assert!(html.contains("<div class=\"entry rx bookmarked\" id=\"entry-7\" data-highlights=\"ok\">"));
+        assert!(html.contains("<span class=\"ts\">2024-03-01 01:00:00.000 +01:00</span>"));
+        assert!(html.contains("<span class=\"text\">&lt;b&gt;<span style=\"color:#00cd00\">OK</span></span>"));
+        assert!(html.contains("<span class=\"hex\">3C 62 3E 1B 5B 33 32 6D 4F 4B 1B 5B 30 6D</span>"));
+        assert!(html.contains("<span class=\"note\">first &quot;OK&quot;</span>"));
+
+        let gbk = LogEntry { id: None, data: send_syntax::encode_text("温度", &TextEncoding::Gbk), ..entry };
+        let settings = DisplaySettings { encoding: TextEncoding::Gbk, ..Default::default() };
+        let mut out = Vec::new();
+        write_html(&mut out, &[gbk], &HashMap::new(), &settings, &FixedOffset::east_opt(0).unwrap()).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("<span class=\"text\">温度</span>"));
+    }
+}
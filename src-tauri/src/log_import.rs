@@ -0,0 +1,312 @@
+//! Reading exported logs and text recordings back into log entries
+//!
+//! - JSON exports restore the exact bytes, direction, port and bookmark of
+//!   every entry.
+//! - CSV and TXT exports hold the (lossy) text of each entry; their optional
+//!   hex or base64 copies restore the exact bytes.
+//! - Older CSV exports, text recordings and TXT exports have timestamps
+//!   without an offset, so the offset they were written with has to be
+//!   supplied again. Entries with only a time of day are placed on today's
+//!   date; a time going backwards is taken as passing midnight.
+//! - Raw captures (`.rsrec`) give one entry per recorded chunk of bytes.
+//! - Recording segments gzipped by rotation (`.txt.gz`, `.rsrec.gz`) are read
+//!   like the file inside.
+
+use crate::export::TXT_ANNOTATION_PREFIX;
+use crate::raw_capture;
+use crate::recording;
+use crate::send_syntax;
+use crate::types::{DataFormat, Direction, LogEntry};
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+/// A log entry read from a file, with the bookmark note it was exported with
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportedEntry {
+    #[serde(flatten)]
+    pub entry: LogEntry,
+    #[serde(default)]
+    pub bookmark: Option<String>,
+}
+
+/// Read a JSON or CSV export, text recording, TXT export or raw capture,
+/// chosen by the file extension. `timezone_offset_minutes` is the offset
+/// local timestamps in the file were written with.
+pub fn load_entries(path: &Path, timezone_offset_minutes: i32) -> Result<Vec<ImportedEntry>> {
+    let mut content = Vec::new();
+    recording::open_file(path)?.read_to_end(&mut content)?;
+    let extension_of = |path: &Path| {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    };
+    let mut extension = extension_of(path);
+    if extension == "gz" {
+        extension = extension_of(&path.with_extension(""));
+    }
+    let tz_offset = FixedOffset::east_opt(timezone_offset_minutes * 60)
+        .ok_or_else(|| anyhow!("Invalid timezone offset: {} minutes", timezone_offset_minutes))?;
+
+    let entries = match extension.as_str() {
+        "json" => serde_json::from_slice(&content)?,
+        "csv" => parse_csv(&content, &tz_offset)?,
+        "txt" | "log" => parse_text(&String::from_utf8_lossy(&content), &tz_offset)?,
+        raw_capture::EXTENSION => raw_capture::to_entries(raw_capture::parse_records(content.as_slice())?)
+            .into_iter()
+            .map(|entry| ImportedEntry { entry, bookmark: None })
+            .collect(),
+        _ => return Err(anyhow!("Unsupported log file {} (expected .json, .csv, .txt or .rsrec, optionally gzipped)", path.display())),
+    };
+    if entries.is_empty() {
+        return Err(anyhow!("{} contains no log entries", path.display()));
+    }
+    Ok(entries)
+}
+
+/// Parse a CSV export by its header names. `port`, `hex`, `base64` and
+/// `bookmark` are optional; the bytes are taken from base64, hex or `data`,
+/// whichever is present first.
+fn parse_csv(content: &[u8], tz_offset: &FixedOffset) -> Result<Vec<ImportedEntry>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(content);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header.trim().eq_ignore_ascii_case(name));
+    let missing = |name: &str| anyhow!("CSV file has no '{}' column", name);
+
+    let timestamp_col = column("timestamp").ok_or_else(|| missing("timestamp"))?;
+    let direction_col = column("direction").ok_or_else(|| missing("direction"))?;
+    let data_col = column("data").ok_or_else(|| missing("data"))?;
+    let port_col = column("port");
+    let hex_col = column("hex");
+    let base64_col = column("base64");
+    let bookmark_col = column("bookmark");
+
+    let mut entries = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record?;
+        // Header is line 1
+        let line = index + 2;
+        let field = |col: usize| record.get(col).unwrap_or_default();
+
+        let timestamp = parse_timestamp(field(timestamp_col), tz_offset)
+            .with_context(|| format!("Line {}: invalid timestamp '{}'", line, field(timestamp_col)))?;
+        let direction = parse_direction(field(direction_col))
+            .ok_or_else(|| anyhow!("Line {}: unknown direction '{}'", line, field(direction_col)))?;
+        let mut entry = text_entry(timestamp, direction, field(data_col), port_col.map(field).unwrap_or_default());
+        if let Some(col) = base64_col {
+            set_data(&mut entry, decode_base64(field(col)).with_context(|| format!("Line {}", line))?);
+        } else if let Some(col) = hex_col {
+            set_data(&mut entry, decode_hex(field(col)).with_context(|| format!("Line {}", line))?);
+        }
+
+        entries.push(ImportedEntry {
+            entry,
+            bookmark: bookmark_col.map(field).filter(|note| !note.is_empty()).map(str::to_string),
+        });
+    }
+    Ok(entries)
+}
+
+/// RFC 3339 timestamps carry their own offset, plain ones use `tz_offset`
+fn parse_timestamp(text: &str, tz_offset: &FixedOffset) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    let local = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")?;
+    tz_offset
+        .from_local_datetime(&local)
+        .single()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("ambiguous local time"))
+}
+
+fn parse_direction(text: &str) -> Option<Direction> {
+    match text.trim() {
+        "Sent" | "TX" => Some(Direction::Sent),
+        "Received" | "RX" => Some(Direction::Received),
+        "System" | "SYS" => Some(Direction::System),
+        _ => None,
+    }
+}
+
+/// Time of an entry line in a TXT export or text recording
+enum Stamp {
+    Full(DateTime<Utc>),
+    TimeOfDay(NaiveTime),
+}
+
+/// Parse `[timestamp] DIR: text` lines. Lines that don't start an entry
+/// continue the text of the previous one or, with the annotation prefix, add
+/// its bytes or bookmark; anything before the first entry (the TXT export
+/// header) is skipped.
+fn parse_text(content: &str, tz_offset: &FixedOffset) -> Result<Vec<ImportedEntry>> {
+    let mut entries: Vec<ImportedEntry> = Vec::new();
+    let mut day = Utc::now().with_timezone(tz_offset).date_naive();
+    let mut last_time: Option<NaiveTime> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let Some((stamp, direction, text)) = parse_entry_line(line) else {
+            let Some(last) = entries.last_mut() else {
+                continue;
+            };
+            match line.strip_prefix(TXT_ANNOTATION_PREFIX).and_then(|rest| rest.split_once(": ")) {
+                Some(("Bookmark", note)) => last.bookmark = Some(note.to_string()),
+                Some(("Hex", hex)) => {
+                    set_data(&mut last.entry, decode_hex(hex).with_context(|| format!("Line {}", index + 1))?)
+                }
+                Some(("Base64", text)) => {
+                    set_data(&mut last.entry, decode_base64(text).with_context(|| format!("Line {}", index + 1))?)
+                }
+                Some(("Display", _)) => {}
+                _ => {
+                    last.entry.data.push(b'\n');
+                    last.entry.data.extend_from_slice(line.as_bytes());
+                    last.entry.display_text.push('\n');
+                    last.entry.display_text.push_str(line);
+                }
+            }
+            continue;
+        };
+
+        let timestamp = match stamp {
+            Stamp::Full(timestamp) => timestamp,
+            Stamp::TimeOfDay(time) => {
+                if last_time.is_some_and(|last| time < last) {
+                    day = day.succ_opt().unwrap_or(day);
+                }
+                last_time = Some(time);
+                tz_offset
+                    .from_local_datetime(&day.and_time(time))
+                    .single()
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .unwrap_or_else(|| day.and_time(time).and_utc())
+            }
+        };
+
+        entries.push(ImportedEntry {
+            entry: text_entry(timestamp, direction, text, ""),
+            bookmark: None,
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_entry_line(line: &str) -> Option<(Stamp, Direction, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let (stamp, rest) = rest.split_once("] ")?;
+    let stamp = match DateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M:%S%.f %:z") {
+        Ok(timestamp) => Stamp::Full(timestamp.with_timezone(&Utc)),
+        Err(_) => Stamp::TimeOfDay(NaiveTime::parse_from_str(stamp, "%H:%M:%S%.f").ok()?),
+    };
+    let (label, text) = rest.split_once(": ").or_else(|| rest.strip_suffix(':').map(|label| (label, "")))?;
+    Some((stamp, parse_direction(label)?, text))
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    send_syntax::parse_hex(text).map_err(|e| anyhow!("invalid hex data: {}", e))
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    BASE64.decode(text.trim()).map_err(|e| anyhow!("invalid base64 data: {}", e))
+}
+
+/// Replace the lossy text of an entry with its exact bytes
+fn set_data(entry: &mut LogEntry, data: Vec<u8>) {
+    entry.display_text = String::from_utf8_lossy(&data).into_owned();
+    entry.data = data;
+}
+
+fn text_entry(timestamp: DateTime<Utc>, direction: Direction, text: &str, port_name: &str) -> LogEntry {
+    LogEntry {
+        id: None,
+        timestamp,
+        direction,
+        data: text.as_bytes().to_vec(),
+        format: DataFormat::Text,
+        port_name: port_name.to_string(),
+        display_text: text.to_string(),
+        timestamp_formatted: None,
+        hidden: false,
+        highlights: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_csv_exports_with_quoted_fields() {
+        let content = "timestamp,direction,port,data,bookmark\n\
+            2024-03-01 08:00:00.250,Sent,COM3,\"AT\",\"\"\n\
+            2024-03-01 08:00:00.400,Received,COM3,\"say \"\"hi\"\"\nOK\",\"reply\"\n";
+        let entries = parse_csv(content.as_bytes(), &FixedOffset::east_opt(8 * 3600).unwrap()).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry.direction, Direction::Sent);
+        assert_eq!(entries[0].entry.port_name, "COM3");
+        assert_eq!(entries[0].entry.timestamp.to_rfc3339(), "2024-03-01T00:00:00.250+00:00");
+        assert_eq!(entries[0].bookmark, None);
+        assert_eq!(entries[1].entry.data, b"say \"hi\"\nOK");
+        assert_eq!(entries[1].bookmark.as_deref(), Some("reply"));
+
+        let error = parse_csv(b"timestamp,data\n", &FixedOffset::east_opt(0).unwrap()).unwrap_err();
+        assert!(error.to_string().contains("direction"));
+    }
+
+    #[test]
+    fn binary_columns_and_annotations_restore_exact_bytes() {
+        let csv = "timestamp,direction,port,data,hex,bookmark\n\
+            2024-03-01T08:00:00.000+08:00,Received,COM3,\"\u{FFFD}\",00 FF,\n";
+        let entries = parse_csv(csv.as_bytes(), &FixedOffset::east_opt(0).unwrap()).unwrap();
+        assert_eq!(entries[0].entry.data, [0x00, 0xFF]);
+        assert_eq!(entries[0].entry.timestamp.to_rfc3339(), "2024-03-01T00:00:00+00:00");
+
+        let txt = "[2024-03-01 08:00:00.000 +08:00] RX: \u{FFFD}\n\
+            \n\
+            \x20   >> Base64: AP8K\n\
+            \x20   >> Display: 00 FF 0A\n";
+        let entries = parse_text(txt, &FixedOffset::east_opt(0).unwrap()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].entry.data, [0x00, 0xFF, 0x0A]);
+        assert_eq!(entries[0].entry.timestamp.to_rfc3339(), "2024-03-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn parses_text_recordings_with_continuations_and_midnight() {
+        let content = "RSerial Debug Assistant - Log Export\n\
+            Generated: 2024-01-01 23:59:59 +0000\n\
+            \n\
+            [23:59:59.500] TX: AT\n\
+            \x20   >> Bookmark: first command\n\
+            [23:59:59.900] RX: line one\n\
+            line two\n\
+            [00:00:00.100] SYS: done\n";
+        let entries = parse_text(content, &FixedOffset::east_opt(0).unwrap()).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].entry.direction, Direction::Sent);
+        assert_eq!(entries[0].entry.data, b"AT");
+        assert_eq!(entries[0].bookmark.as_deref(), Some("first command"));
+        assert_eq!(entries[1].entry.data, b"line one\nline two");
+        assert_eq!(entries[2].entry.direction, Direction::System);
+        let gap = entries[2].entry.timestamp - entries[1].entry.timestamp;
+        assert_eq!(gap.num_milliseconds(), 200);
+    }
+
+    #[test]
+    fn json_exports_keep_bytes_and_bookmarks() {
+        let entry = text_entry(Utc::now(), Direction::Received, "", "ttyUSB0");
+        let mut value = serde_json::to_value(LogEntry { data: vec![0x00, 0xFF], ..entry }).unwrap();
+        value["bookmark"] = "boot".into();
+        let entries: Vec<ImportedEntry> = serde_json::from_value(serde_json::Value::Array(vec![value])).unwrap();
+
+        assert_eq!(entries[0].entry.data, [0x00, 0xFF]);
+        assert_eq!(entries[0].entry.port_name, "ttyUSB0");
+        assert_eq!(entries[0].bookmark.as_deref(), Some("boot"));
+    }
+}
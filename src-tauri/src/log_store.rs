@@ -0,0 +1,770 @@
+//! Per-session SQLite store behind the in-memory log view
+//!
+//! Every entry pushed to the log gets an id and a row in the session database,
+//! so history is not limited by `max_log_entries`. The database is created in
+//! `<log dir>/sessions/` when the first entry arrives; clearing the log starts
+//! a new session file. Display text is not stored: pages are rendered with the
+//! current display settings when read back.
+//!
+//! Filter and highlight rules are applied as entries are inserted, and the rule
+//! set is saved in each session database alongside the entries it marked. On
+//! start-up the rules of the newest session are restored.
+//! Bookmarks are kept for the whole app run, so they survive clearing the log;
+//! each session database gets a copy of them.
+
+use crate::serial_manager::format_date_for_filename_with_offset;
+use crate::pattern;
+use crate::types::{Bookmark, DataFormat, Direction, ExportFilter, LogEntry, LogRule, LogRuleAction, SearchQuery, TextEncoding};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use regex::bytes::Regex;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OpenFlags, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS log_entries (
+        id INTEGER PRIMARY KEY,
+        timestamp_us INTEGER NOT NULL,
+        direction TEXT NOT NULL,
+        data BLOB NOT NULL,
+        format TEXT NOT NULL,
+        port_name TEXT NOT NULL,
+        hidden INTEGER NOT NULL DEFAULT 0,
+        highlights TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_log_entries_timestamp ON log_entries(timestamp_us);
+    CREATE TABLE IF NOT EXISTS bookmarks (
+        entry_id INTEGER PRIMARY KEY,
+        bookmark TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS session_settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+const ENTRY_COLUMNS: &str = "id, timestamp_us, direction, data, format, port_name, hidden, highlights";
+
+/// `session_settings` key holding the JSON rule list
+const RULES_KEY: &str = "log_rules";
+
+/// Characters of the entry text copied into a bookmark
+const BOOKMARK_PREVIEW_CHARS: usize = 200;
+
+/// Largest page returned by `page`
+pub const MAX_PAGE_SIZE: usize = 5000;
+
+/// Entry ids before and after an entry, see `neighbour_ids`
+pub type ContextIds = (Vec<i64>, Vec<i64>);
+
+/// An entry found by `search`, with the byte range of its first match
+pub struct SearchHit {
+    pub entry: LogEntry,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Default)]
+struct Session {
+    conn: Option<Connection>,
+    path: Option<PathBuf>,
+    /// Set after a failed open so every entry does not retry (and warn) again
+    unavailable: bool,
+}
+
+pub struct LogStore {
+    log_directory: Arc<Mutex<String>>,
+    timezone_offset_minutes: Arc<Mutex<i32>>,
+    session: Mutex<Session>,
+    /// Rules with their compiled patterns, in evaluation order
+    rules: Mutex<Vec<(LogRule, Regex)>>,
+    bookmarks: Mutex<BTreeMap<i64, Bookmark>>,
+    /// Ids keep increasing across sessions so the frontend never sees a reused id
+    next_id: AtomicI64,
+}
+
+impl LogStore {
+    /// The store shares the manager's log directory and timezone settings; both
+    /// are read when a session file is created.
+    pub fn new(log_directory: Arc<Mutex<String>>, timezone_offset_minutes: Arc<Mutex<i32>>) -> Self {
+        Self {
+            log_directory,
+            timezone_offset_minutes,
+            session: Mutex::new(Session::default()),
+            rules: Mutex::new(Vec::new()),
+            bookmarks: Mutex::new(BTreeMap::new()),
+            next_id: AtomicI64::new(1),
+        }
+    }
+
+    /// Close the current session; the next entry opens a new database file
+    pub fn start_new_session(&self) {
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        *session = Session::default();
+    }
+
+    /// Path of the current session database, if one has been created
+    pub fn session_path(&self) -> Option<PathBuf> {
+        self.session.lock().unwrap_or_else(|e| e.into_inner()).path.clone()
+    }
+
+    /// Replace the filter and highlight rules. Patterns are compiled with
+    /// `encoding`; nothing changes if any rule fails to compile.
+    pub fn set_rules(&self, rules: Vec<LogRule>, encoding: &TextEncoding) -> Result<()> {
+        let compiled = rules
+            .into_iter()
+            .map(|rule| {
+                let regex = pattern::compile(&rule.pattern, &rule.mode, rule.case_sensitive, encoding)
+                    .map_err(|e| anyhow!("Rule '{}': {}", rule.name, e))?;
+                Ok((rule, regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        *self.rules.lock().unwrap_or_else(|e| e.into_inner()) = compiled;
+
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(conn) = session.conn.as_ref() {
+            self.save_rules(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Load the rules saved with the newest earlier session in the log
+    /// directory, so they survive a restart. Does nothing when rules are set.
+    pub fn restore_rules(&self, encoding: &TextEncoding) -> Result<()> {
+        if !self.rules.lock().unwrap_or_else(|e| e.into_inner()).is_empty() {
+            return Ok(());
+        }
+        let current = self.session_path();
+        let Ok(files) = std::fs::read_dir(self.sessions_dir()?) else {
+            return Ok(());
+        };
+        let newest = files
+            .filter_map(|file| file.ok())
+            .map(|file| file.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "db") && Some(path) != current.as_ref())
+            .filter_map(|path| Some((std::fs::metadata(&path).and_then(|meta| meta.modified()).ok()?, path)))
+            .max();
+        let Some((_, path)) = newest else {
+            return Ok(());
+        };
+
+        let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+        let saved: Option<String> = conn
+            .query_row("SELECT value FROM session_settings WHERE key = ?1", params![RULES_KEY], |row| row.get(0))
+            .optional()?;
+        let rules: Vec<LogRule> = match saved {
+            Some(saved) => serde_json::from_str(&saved)?,
+            None => Vec::new(),
+        };
+        if !rules.is_empty() {
+            info!("Restoring {} log rules from {}", rules.len(), path.display());
+            self.set_rules(rules, encoding)?;
+        }
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<LogRule> {
+        let rules = self.rules.lock().unwrap_or_else(|e| e.into_inner());
+        rules.iter().map(|(rule, _)| rule.clone()).collect()
+    }
+
+    /// Mark an entry that already has an id; an existing bookmark on the same
+    /// entry is replaced. `entry.display_text` is used as the preview.
+    pub fn add_bookmark(&self, entry: &LogEntry, note: String) -> Result<Bookmark> {
+        let entry_id = entry.id.ok_or_else(|| anyhow!("Log entry has no id"))?;
+        let bookmark = Bookmark {
+            entry_id,
+            note,
+            created_at: Utc::now(),
+            entry_timestamp: entry.timestamp,
+            direction: entry.direction.clone(),
+            preview: entry.display_text.chars().take(BOOKMARK_PREVIEW_CHARS).collect(),
+        };
+        self.bookmarks.lock().unwrap_or_else(|e| e.into_inner()).insert(entry_id, bookmark.clone());
+        self.persist_bookmark(entry_id);
+        Ok(bookmark)
+    }
+
+    /// Change the note of a bookmark, None if the entry is not bookmarked
+    pub fn update_bookmark(&self, entry_id: i64, note: String) -> Option<Bookmark> {
+        let bookmark = {
+            let mut bookmarks = self.bookmarks.lock().unwrap_or_else(|e| e.into_inner());
+            let bookmark = bookmarks.get_mut(&entry_id)?;
+            bookmark.note = note;
+            bookmark.clone()
+        };
+        self.persist_bookmark(entry_id);
+        Some(bookmark)
+    }
+
+    /// Returns whether the entry was bookmarked
+    pub fn remove_bookmark(&self, entry_id: i64) -> bool {
+        let removed = self.bookmarks.lock().unwrap_or_else(|e| e.into_inner()).remove(&entry_id).is_some();
+        if removed {
+            self.persist_bookmark(entry_id);
+        }
+        removed
+    }
+
+    /// Bookmarks in entry order
+    pub fn bookmarks(&self) -> Vec<Bookmark> {
+        self.bookmarks.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+    }
+
+    /// The closest bookmark after `from_id` (or before it when `backwards`).
+    /// Without `from_id` the first (or last) bookmark is returned.
+    pub fn adjacent_bookmark(&self, from_id: Option<i64>, backwards: bool) -> Option<Bookmark> {
+        let bookmarks = self.bookmarks.lock().unwrap_or_else(|e| e.into_inner());
+        let found = match (from_id, backwards) {
+            (Some(id), false) => bookmarks.range(id + 1..).next(),
+            (Some(id), true) => bookmarks.range(..id).next_back(),
+            (None, false) => bookmarks.iter().next(),
+            (None, true) => bookmarks.iter().next_back(),
+        };
+        found.map(|(_, bookmark)| bookmark.clone())
+    }
+
+    /// Look up a single entry of the current session
+    pub fn get(&self, id: i64) -> Result<Option<LogEntry>> {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(conn) = session.conn.as_ref() else {
+            return Ok(None);
+        };
+        Ok(conn
+            .query_row(
+                &format!("SELECT {} FROM log_entries WHERE id = ?1", ENTRY_COLUMNS),
+                params![id],
+                row_to_entry,
+            )
+            .optional()?)
+    }
+
+    /// Assign the next id to `entry`, apply the rules and persist it. Storage
+    /// errors are logged rather than returned so a failing disk never
+    /// interrupts capture.
+    pub fn insert(&self, entry: &mut LogEntry) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        entry.id = Some(id);
+        self.apply_rules(entry);
+
+        let mut session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(conn) = self.connection(&mut session) else {
+            return;
+        };
+        let result = conn
+            .prepare_cached(&format!("INSERT INTO log_entries ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)", ENTRY_COLUMNS))
+            .and_then(|mut statement| {
+                statement.execute(params![
+                    id,
+                    entry.timestamp.timestamp_micros(),
+                    enum_to_text(&entry.direction),
+                    entry.data,
+                    enum_to_text(&entry.format),
+                    entry.port_name,
+                    entry.hidden,
+                    highlights_to_text(&entry.highlights),
+                ])
+            });
+        if let Err(e) = result {
+            warn!("Failed to store log entry {}: {}", id, e);
+        }
+    }
+
+    /// Entries older than `before_id` (or the newest ones), or newer than
+    /// `after_id` when given, in ascending id order. The flag tells whether more
+    /// entries exist beyond the page in the requested direction.
+    pub fn page(&self, before_id: Option<i64>, after_id: Option<i64>, limit: usize) -> Result<(Vec<LogEntry>, bool)> {
+        let limit = limit.clamp(1, MAX_PAGE_SIZE);
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(conn) = session.conn.as_ref() else {
+            return Ok((Vec::new(), false));
+        };
+
+        let (sql, anchor) = match after_id {
+            Some(after) => (
+                format!("SELECT {} FROM log_entries WHERE id > ?1 ORDER BY id ASC LIMIT ?2", ENTRY_COLUMNS),
+                after,
+            ),
+            None => (
+                format!("SELECT {} FROM log_entries WHERE id < ?1 ORDER BY id DESC LIMIT ?2", ENTRY_COLUMNS),
+                before_id.unwrap_or(i64::MAX),
+            ),
+        };
+        let mut statement = conn.prepare_cached(&sql)?;
+        let mut entries = statement
+            .query_map(params![anchor, (limit + 1) as i64], row_to_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let has_more = entries.len() > limit;
+        entries.truncate(limit);
+        if after_id.is_none() {
+            entries.reverse();
+        }
+        Ok((entries, has_more))
+    }
+
+    /// Number of entries in the current session
+    pub fn count(&self) -> Result<i64> {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        match session.conn.as_ref() {
+            Some(conn) => Ok(conn.query_row("SELECT COUNT(*) FROM log_entries", [], |row| row.get(0))?),
+            None => Ok(0),
+        }
+    }
+
+    /// Scan the session for entries matching `pattern` and the query's
+    /// direction and time filters. Returns None when there is no session
+    /// database, otherwise up to `limit` hits and whether more entries matched.
+    ///
+    /// The scan runs on its own read-only connection, which WAL allows next
+    /// to the writer, so capture is not held up by long searches.
+    pub fn search(&self, pattern: &Regex, query: &SearchQuery, limit: usize) -> Result<Option<(Vec<SearchHit>, bool)>> {
+        let Some(path) = self.session_path() else {
+            return Ok(None);
+        };
+        let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+
+        // Filters go to SQL so the timestamp index can narrow the scan
+        let mut filter = SqlFilter::default();
+        filter.add_range(query.direction.as_ref(), query.start_time, query.end_time);
+        let sql = format!(
+            "SELECT {} FROM log_entries {} ORDER BY id {}",
+            ENTRY_COLUMNS,
+            filter.where_clause(),
+            if query.newest_first { "DESC" } else { "ASC" },
+        );
+
+        let mut statement = conn.prepare(&sql)?;
+        let mut rows = statement.query(params_from_iter(filter.values))?;
+        let mut hits = Vec::new();
+        while let Some(row) = rows.next()? {
+            // Only matching rows are decoded into entries
+            let Some(found) = pattern.find(row.get_ref(3)?.as_blob()?) else {
+                continue;
+            };
+            if hits.len() == limit {
+                return Ok(Some((hits, true)));
+            }
+            hits.push(SearchHit {
+                entry: row_to_entry(row)?,
+                start: found.start(),
+                end: found.end(),
+            });
+        }
+        Ok(Some((hits, false)))
+    }
+
+    /// Entries of the session within the filter's time window, direction and
+    /// id list, in id order. Pattern and hidden filters are left to the caller.
+    /// Returns None when there is no session database.
+    pub fn select(&self, export_filter: &ExportFilter) -> Result<Option<Vec<LogEntry>>> {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(conn) = session.conn.as_ref() else {
+            return Ok(None);
+        };
+
+        let mut filter = SqlFilter::default();
+        filter.add_range(export_filter.direction.as_ref(), export_filter.start_time, export_filter.end_time);
+        if !export_filter.entry_ids.is_empty() {
+            // One JSON parameter instead of one per id
+            filter.conditions.push("id IN (SELECT value FROM json_each(?))");
+            filter.values.push(Value::Text(serde_json::to_string(&export_filter.entry_ids)?));
+        }
+        let sql = format!("SELECT {} FROM log_entries {} ORDER BY id ASC", ENTRY_COLUMNS, filter.where_clause());
+
+        let mut statement = conn.prepare(&sql)?;
+        let entries = statement
+            .query_map(params_from_iter(filter.values), row_to_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Some(entries))
+    }
+
+    /// Ids of up to `count` entries on each side of `id`, in ascending order
+    pub fn neighbour_ids(&self, id: i64, count: usize) -> Result<ContextIds> {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(conn) = session.conn.as_ref().filter(|_| count > 0) else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        let mut before = conn
+            .prepare_cached("SELECT id FROM log_entries WHERE id < ?1 ORDER BY id DESC LIMIT ?2")?
+            .query_map(params![id, count as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        before.reverse();
+        let after = conn
+            .prepare_cached("SELECT id FROM log_entries WHERE id > ?1 ORDER BY id ASC LIMIT ?2")?
+            .query_map(params![id, count as i64], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        Ok((before, after))
+    }
+
+    /// Mark the entry hidden if an enabled hide rule matches and collect the
+    /// tags of matching highlight rules
+    fn apply_rules(&self, entry: &mut LogEntry) {
+        let rules = self.rules.lock().unwrap_or_else(|e| e.into_inner());
+        for (rule, regex) in rules.iter() {
+            if !rule.enabled
+                || rule.direction.as_ref().is_some_and(|d| *d != entry.direction)
+                || !regex.is_match(&entry.data)
+            {
+                continue;
+            }
+            match rule.action {
+                LogRuleAction::Hide => entry.hidden = true,
+                LogRuleAction::Highlight => {
+                    if !entry.highlights.contains(&rule.tag) {
+                        entry.highlights.push(rule.tag.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write the bookmark on `entry_id` (or its removal) to the current session
+    fn persist_bookmark(&self, entry_id: i64) {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(conn) = session.conn.as_ref() else {
+            return;
+        };
+        let bookmark = self.bookmarks.lock().unwrap_or_else(|e| e.into_inner()).get(&entry_id).cloned();
+        if let Err(e) = save_bookmark(conn, entry_id, bookmark.as_ref()) {
+            warn!("Failed to store bookmark on entry {}: {}", entry_id, e);
+        }
+    }
+
+    fn save_rules(&self, conn: &Connection) -> Result<()> {
+        let rules = serde_json::to_string(&self.rules())?;
+        conn.execute(
+            "INSERT OR REPLACE INTO session_settings (key, value) VALUES (?1, ?2)",
+            params![RULES_KEY, rules],
+        )?;
+        Ok(())
+    }
+
+    fn connection<'a>(&self, session: &'a mut Session) -> Option<&'a Connection> {
+        if session.conn.is_none() && !session.unavailable {
+            match self.open_session() {
+                Ok((conn, path)) => {
+                    info!("Log session database: {}", path.display());
+                    session.conn = Some(conn);
+                    session.path = Some(path);
+                }
+                Err(e) => {
+                    warn!("Log history will not be persisted: {}", e);
+                    session.unavailable = true;
+                }
+            }
+        }
+        session.conn.as_ref()
+    }
+
+    fn sessions_dir(&self) -> Result<PathBuf> {
+        let log_dir = self.log_directory.lock().map(|dir| dir.clone()).map_err(|_| anyhow!("log directory unavailable"))?;
+        Ok(PathBuf::from(log_dir).join("sessions"))
+    }
+
+    fn open_session(&self) -> Result<(Connection, PathBuf)> {
+        let dir = self.sessions_dir()?;
+        create_dir_all(&dir)?;
+
+        let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+        let stamp = format_date_for_filename_with_offset(tz_offset);
+        let path = (0..)
+            .map(|n| match n {
+                0 => dir.join(format!("session_{}.db", stamp)),
+                n => dir.join(format!("session_{}_{}.db", stamp, n)),
+            })
+            .find(|path| !path.exists())
+            .ok_or_else(|| anyhow!("no free session file name"))?;
+
+        let conn = Connection::open(&path)?;
+        // WAL with NORMAL sync keeps per-entry inserts cheap enough for the reader thread
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        self.save_rules(&conn)?;
+        for bookmark in self.bookmarks() {
+            save_bookmark(&conn, bookmark.entry_id, Some(&bookmark))?;
+        }
+        Ok((conn, path))
+    }
+}
+
+/// WHERE conditions with their positional parameters
+#[derive(Default)]
+struct SqlFilter {
+    conditions: Vec<&'static str>,
+    values: Vec<Value>,
+}
+
+impl SqlFilter {
+    fn add_range(&mut self, direction: Option<&Direction>, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) {
+        if let Some(direction) = direction {
+            self.conditions.push("direction = ?");
+            self.values.push(Value::Text(enum_to_text(direction)));
+        }
+        if let Some(start) = start {
+            self.conditions.push("timestamp_us >= ?");
+            self.values.push(Value::Integer(start.timestamp_micros()));
+        }
+        if let Some(end) = end {
+            self.conditions.push("timestamp_us <= ?");
+            self.values.push(Value::Integer(end.timestamp_micros()));
+        }
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+}
+
+/// Bookmarks are stored as JSON; None deletes the row
+fn save_bookmark(conn: &Connection, entry_id: i64, bookmark: Option<&Bookmark>) -> Result<()> {
+    match bookmark {
+        Some(bookmark) => conn.execute(
+            "INSERT OR REPLACE INTO bookmarks (entry_id, bookmark) VALUES (?1, ?2)",
+            params![entry_id, serde_json::to_string(bookmark)?],
+        )?,
+        None => conn.execute("DELETE FROM bookmarks WHERE entry_id = ?1", params![entry_id])?,
+    };
+    Ok(())
+}
+
+/// Unit enum variants are stored by name
+fn enum_to_text<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+fn enum_from_text<T: DeserializeOwned>(text: String) -> rusqlite::Result<T> {
+    serde_json::from_value(serde_json::Value::String(text))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+/// Highlight tags are stored as a JSON array, NULL when there are none
+fn highlights_to_text(highlights: &[String]) -> Option<String> {
+    if highlights.is_empty() {
+        None
+    } else {
+        serde_json::to_string(highlights).ok()
+    }
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<LogEntry> {
+    let micros: i64 = row.get(1)?;
+    let direction: Direction = enum_from_text(row.get(2)?)?;
+    let format: DataFormat = enum_from_text(row.get(4)?)?;
+    Ok(LogEntry {
+        id: Some(row.get(0)?),
+        timestamp: DateTime::<Utc>::from_timestamp_micros(micros).unwrap_or_default(),
+        direction,
+        data: row.get(3)?,
+        format,
+        port_name: row.get(5)?,
+        display_text: String::new(),
+        timestamp_formatted: None,
+        hidden: row.get(6)?,
+        highlights: row
+            .get::<_, Option<String>>(7)?
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(text: &str) -> LogEntry {
+        LogEntry {
+            id: None,
+            timestamp: Utc::now(),
+            direction: Direction::Received,
+            data: text.as_bytes().to_vec(),
+            format: DataFormat::Text,
+            port_name: "COM3".to_string(),
+            display_text: text.to_string(),
+            timestamp_formatted: None,
+            hidden: false,
+            highlights: Vec::new(),
+        }
+    }
+
+    fn temp_store() -> (LogStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("log-store-test-{}", uuid::Uuid::new_v4()));
+        let store = LogStore::new(
+            Arc::new(Mutex::new(dir.to_string_lossy().to_string())),
+            Arc::new(Mutex::new(0)),
+        );
+        (store, dir)
+    }
+
+    #[test]
+    fn assigns_ids_and_pages_both_ways() {
+        let (store, dir) = temp_store();
+        for i in 0..10 {
+            let mut e = entry(&format!("line {}", i));
+            store.insert(&mut e);
+            assert_eq!(e.id, Some(i + 1));
+        }
+
+        let (latest, more) = store.page(None, None, 3).unwrap();
+        assert_eq!(latest.iter().map(|e| e.id.unwrap()).collect::<Vec<_>>(), vec![8, 9, 10]);
+        assert!(more);
+
+        let (older, more) = store.page(Some(3), None, 5).unwrap();
+        assert_eq!(older.iter().map(|e| e.id.unwrap()).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(!more);
+
+        let (newer, _) = store.page(None, Some(8), 5).unwrap();
+        assert_eq!(newer.iter().map(|e| e.id.unwrap()).collect::<Vec<_>>(), vec![9, 10]);
+        assert_eq!(newer[0].data, b"line 8");
+        assert!(matches!(newer[0].direction, Direction::Received));
+        assert_eq!(store.count().unwrap(), 10);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn search_applies_pattern_and_filters() {
+        let (store, dir) = temp_store();
+        for (i, text) in ["boot ok", "ERROR 1", "tick", "error 2", "ERROR 3"].iter().enumerate() {
+            let mut e = entry(text);
+            if i == 4 {
+                e.direction = Direction::Sent;
+            }
+            store.insert(&mut e);
+        }
+        let pattern = Regex::new("(?i)error").unwrap();
+
+        let (hits, truncated) = store.search(&pattern, &SearchQuery::default(), 10).unwrap().unwrap();
+        assert_eq!(hits.iter().map(|h| h.entry.id.unwrap()).collect::<Vec<_>>(), vec![2, 4, 5]);
+        assert_eq!((hits[1].start, hits[1].end), (0, 5));
+        assert!(!truncated);
+
+        let query = SearchQuery { direction: Some(Direction::Received), newest_first: true, ..Default::default() };
+        let (hits, truncated) = store.search(&pattern, &query, 1).unwrap().unwrap();
+        assert_eq!(hits[0].entry.id, Some(4));
+        assert!(truncated);
+
+        assert_eq!(store.neighbour_ids(2, 2).unwrap(), (vec![1], vec![3, 4]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rules_mark_entries_and_are_restored_from_the_last_session() {
+        let (store, dir) = temp_store();
+        let rules = vec![
+            LogRule { name: "debug".to_string(), pattern: "^DBG".to_string(), ..Default::default() },
+            LogRule {
+                name: "errors".to_string(),
+                pattern: "error".to_string(),
+                mode: crate::types::PatternMode::Text,
+                case_sensitive: false,
+                action: LogRuleAction::Highlight,
+                tag: "red".to_string(),
+                ..Default::default()
+            },
+        ];
+        store.set_rules(rules, &TextEncoding::Utf8).unwrap();
+
+        let mut debug = entry("DBG error in loop");
+        store.insert(&mut debug);
+        assert!(debug.hidden);
+        assert_eq!(debug.highlights, vec!["red"]);
+        let mut plain = entry("ready");
+        store.insert(&mut plain);
+        assert!(!plain.hidden && plain.highlights.is_empty());
+
+        let (stored, _) = store.page(None, None, 10).unwrap();
+        assert!(stored[0].hidden);
+        assert_eq!(stored[0].highlights, vec!["red"]);
+
+        let saved: String = Connection::open(store.session_path().unwrap())
+            .unwrap()
+            .query_row("SELECT value FROM session_settings WHERE key = ?1", params![RULES_KEY], |row| row.get(0))
+            .unwrap();
+        assert!(saved.contains("^DBG"));
+
+        let bad = vec![LogRule { name: "broken".to_string(), pattern: "(".to_string(), ..Default::default() }];
+        assert!(store.set_rules(bad, &TextEncoding::Utf8).is_err());
+        assert_eq!(store.rules().len(), 2);
+
+        // The next run picks them up from the session file
+        drop(store);
+        let restarted = LogStore::new(Arc::new(Mutex::new(dir.to_string_lossy().to_string())), Arc::new(Mutex::new(0)));
+        restarted.restore_rules(&TextEncoding::Utf8).unwrap();
+        assert_eq!(restarted.rules().len(), 2);
+        let mut debug = entry("DBG again");
+        restarted.insert(&mut debug);
+        assert!(debug.hidden);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn new_session_starts_empty_but_keeps_ids_increasing() {
+        let (store, dir) = temp_store();
+        store.insert(&mut entry("first"));
+        let first_path = store.session_path().unwrap();
+
+        store.start_new_session();
+        assert_eq!(store.count().unwrap(), 0);
+        let mut e = entry("second");
+        store.insert(&mut e);
+
+        assert_eq!(e.id, Some(2));
+        assert_ne!(store.session_path().unwrap(), first_path);
+        assert!(first_path.exists());
+        let (entries, _) = store.page(None, None, 10).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].data, b"second");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bookmarks_survive_new_sessions_and_navigate_in_order() {
+        let (store, dir) = temp_store();
+        let mut marked = Vec::new();
+        for text in ["a", "b", "c"] {
+            let mut e = entry(text);
+            store.insert(&mut e);
+            marked.push(e);
+        }
+        store.add_bookmark(&marked[0], "boot".to_string()).unwrap();
+        store.add_bookmark(&marked[2], "fault".to_string()).unwrap();
+
+        store.start_new_session();
+        assert_eq!(store.bookmarks().len(), 2);
+        // The new session database is opened by its first entry
+        store.insert(&mut entry("d"));
+        let conn = Connection::open(store.session_path().unwrap()).unwrap();
+        let saved: i64 = conn.query_row("SELECT COUNT(*) FROM bookmarks", [], |row| row.get(0)).unwrap();
+        assert_eq!(saved, 2);
+
+        assert_eq!(store.adjacent_bookmark(None, false).unwrap().note, "boot");
+        assert_eq!(store.adjacent_bookmark(Some(1), false).unwrap().note, "fault");
+        assert_eq!(store.adjacent_bookmark(Some(3), true).unwrap().note, "boot");
+        assert!(store.adjacent_bookmark(Some(3), false).is_none());
+
+        assert_eq!(store.update_bookmark(3, "fixed".to_string()).unwrap().note, "fixed");
+        assert!(store.remove_bookmark(1));
+        assert!(!store.remove_bookmark(1));
+        assert_eq!(store.bookmarks().len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
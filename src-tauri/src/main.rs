@@ -2,12 +2,31 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::collections::HashMap;
-use std::sync::Mutex;
-use tauri::State;
-
+use std::sync::{Arc, Mutex};
+use tauri::{Emitter, Manager, State};
+
+mod ansi;
+mod esp_flasher;
+mod export;
+mod firmware_image;
+mod html_export;
+mod log_import;
+mod log_store;
+mod pattern;
+mod raw_capture;
+mod recording;
+mod recording_writer;
+mod replay;
+mod send_syntax;
 mod serial_manager;
+mod simulator;
+mod stm32_flasher;
+mod transfer;
+mod triggers;
 mod types;
 mod updater;
+mod xmodem;
+mod zmodem;
 
 use serial_manager::SerialManager;
 use types::*;
@@ -66,37 +85,7 @@ async fn send_data(
 
     // Process data conversion in a separate task to avoid blocking UI
     let bytes = tokio::task::spawn_blocking(move || {
-        match format {
-            DataFormat::Text => {
-                // Encode text using the specified encoding
-                match text_encoding {
-                    TextEncoding::Utf8 => Ok(data.into_bytes()),
-                    TextEncoding::Gbk => {
-                        let (encoded, _, had_errors) = encoding_rs::GBK.encode(&data);
-                        if had_errors {
-                            // If encoding fails for some characters, still send what we can
-                            log::warn!("Some characters could not be encoded to GBK");
-                        }
-                        Ok(encoded.into_owned())
-                    }
-                }
-            }
-            DataFormat::Hex => {
-                let cleaned = data.replace(" ", "").replace("\n", "");
-                if cleaned.len() % 2 != 0 {
-                    return Err("Hex string must have even number of characters".to_string());
-                }
-
-                let mut bytes = Vec::new();
-                for i in (0..cleaned.len()).step_by(2) {
-                    match u8::from_str_radix(&cleaned[i..i+2], 16) {
-                        Ok(byte) => bytes.push(byte),
-                        Err(_) => return Err("Invalid hex characters".to_string()),
-                    }
-                }
-                Ok(bytes)
-            }
-        }
+        send_syntax::resolve(&data, &format, &text_encoding).map_err(|e| e.to_string())
     }).await.map_err(|e| e.to_string())??;
 
     let mut manager = state.serial_manager.lock().unwrap();
@@ -123,15 +112,207 @@ async fn clear_logs(state: State<'_, AppState>) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn get_log_page(
+    state: State<'_, AppState>,
+    before_id: Option<i64>,
+    after_id: Option<i64>,
+    limit: Option<usize>,
+) -> Result<LogPage, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.get_log_page(before_id, after_id, limit.unwrap_or(500))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn search_logs(state: State<'_, AppState>, query: SearchQuery) -> Result<SearchResult, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.search_logs(query)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_log_rules(state: State<'_, AppState>, rules: Vec<LogRule>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_log_rules(rules)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_log_rules(state: State<'_, AppState>) -> Result<Vec<LogRule>, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_log_rules())
+}
+
+// Bookmark commands
+
+#[tauri::command]
+async fn add_bookmark(state: State<'_, AppState>, entry_id: i64, note: Option<String>) -> Result<Bookmark, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.add_bookmark(entry_id, note.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_bookmark(state: State<'_, AppState>, entry_id: i64, note: String) -> Result<Bookmark, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.update_bookmark(entry_id, note)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_bookmark(state: State<'_, AppState>, entry_id: i64) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.remove_bookmark(entry_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_bookmarks(state: State<'_, AppState>) -> Result<Vec<Bookmark>, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_bookmarks())
+}
+
+#[tauri::command]
+async fn find_bookmark(
+    state: State<'_, AppState>,
+    from_id: Option<i64>,
+    backwards: Option<bool>,
+) -> Result<Option<Bookmark>, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.find_bookmark(from_id, backwards.unwrap_or(false)))
+}
+
+// Trigger commands
+
+#[tauri::command]
+async fn set_triggers(state: State<'_, AppState>, triggers: Vec<Trigger>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_triggers(triggers)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_triggers(state: State<'_, AppState>) -> Result<Vec<Trigger>, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_triggers())
+}
+
+#[tauri::command]
+async fn get_trigger_statuses(state: State<'_, AppState>) -> Result<Vec<TriggerStatus>, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_trigger_statuses())
+}
+
+#[tauri::command]
+async fn reset_trigger_counters(state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.reset_trigger_counters();
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_capture_paused(state: State<'_, AppState>, paused: bool) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_capture_paused(paused);
+    Ok(())
+}
+
+#[tauri::command]
+async fn is_capture_paused(state: State<'_, AppState>) -> Result<bool, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.is_capture_paused())
+}
+
+// Device simulator commands
+
+#[tauri::command]
+async fn set_simulator_config(state: State<'_, AppState>, config: SimulatorConfig) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_simulator_config(config)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_simulator_config(state: State<'_, AppState>) -> Result<SimulatorConfig, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_simulator_config())
+}
+
+#[tauri::command]
+async fn get_simulator_status(state: State<'_, AppState>) -> Result<SimulatorStatus, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_simulator_status())
+}
+
+#[tauri::command]
+async fn reset_simulator(state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.reset_simulator();
+    Ok(())
+}
+
+// Session replay commands
+
+#[tauri::command]
+async fn start_replay(
+    state: State<'_, AppState>,
+    file_path: String,
+    options: Option<ReplayOptions>,
+) -> Result<usize, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.start_replay(&file_path, options.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_replay(state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.stop_replay();
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_active_replay(state: State<'_, AppState>) -> Result<Option<ReplayTarget>, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_active_replay())
+}
+
+#[tauri::command]
+async fn get_log_session_info(state: State<'_, AppState>) -> Result<LogSessionInfo, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.get_log_session_info()
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn export_logs(
     state: State<'_, AppState>,
     file_path: String,
     format: ExportFormat,
+    options: Option<ExportOptions>,
+    filter: Option<ExportFilter>,
     timezone_offset_minutes: Option<i32>,
 ) -> Result<(), String> {
     let manager = state.serial_manager.lock().unwrap();
-    manager.export_logs(&file_path, format, timezone_offset_minutes.unwrap_or(0))
+    manager.export_logs(
+        &file_path,
+        format,
+        options.unwrap_or_default(),
+        filter,
+        timezone_offset_minutes.unwrap_or(0),
+    )
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_logs(
+    state: State<'_, AppState>,
+    file_path: String,
+    timezone_offset_minutes: Option<i32>,
+) -> Result<LogImportSummary, String> {
+    let mut manager = state.serial_manager.lock().unwrap();
+    manager.import_logs(&file_path, timezone_offset_minutes.unwrap_or(0))
         .map_err(|e| e.to_string())
 }
 
@@ -192,6 +373,73 @@ async fn get_frame_segmentation(state: State<'_, AppState>) -> Result<FrameSegme
     Ok(manager.get_frame_segmentation_config())
 }
 
+// File transfer commands
+
+#[tauri::command]
+async fn send_file(
+    state: State<'_, AppState>,
+    path: String,
+    chunk_size: usize,
+    inter_chunk_delay: u64,
+    decode_image: Option<bool>,
+) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.send_file(&path, chunk_size, inter_chunk_delay, decode_image.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn send_file_with_protocol(
+    state: State<'_, AppState>,
+    path: String,
+    protocol: TransferProtocol,
+    decode_image: Option<bool>,
+) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.send_file_with_protocol(&path, protocol, decode_image.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn receive_file_with_protocol(
+    state: State<'_, AppState>,
+    protocol: TransferProtocol,
+    save_path: Option<String>,
+) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.receive_file_with_protocol(protocol, save_path)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_transfer(state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.cancel_transfer();
+    Ok(())
+}
+
+// Bootloader commands
+
+#[tauri::command]
+async fn flash_stm32(
+    state: State<'_, AppState>,
+    options: Stm32FlashOptions,
+) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.flash_stm32(options)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn flash_esp(
+    state: State<'_, AppState>,
+    options: EspFlashOptions,
+) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.flash_esp(options)
+        .map_err(|e| e.to_string())
+}
+
 // Recording commands
 
 #[tauri::command]
@@ -238,6 +486,86 @@ async fn stop_raw_recording(state: State<'_, AppState>) -> Result<(), String> {
     manager.stop_raw_recording().map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn set_text_recording_content(state: State<'_, AppState>, content: TextRecordingContent) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_text_recording_content(content);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_text_recording_content(state: State<'_, AppState>) -> Result<TextRecordingContent, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_text_recording_content())
+}
+
+#[tauri::command]
+async fn set_raw_recording_mode(state: State<'_, AppState>, mode: RawRecordingMode) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_raw_recording_mode(mode);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_raw_recording_mode(state: State<'_, AppState>) -> Result<RawRecordingMode, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_raw_recording_mode())
+}
+
+#[tauri::command]
+async fn set_recording_rotation(state: State<'_, AppState>, rotation: RecordingRotation) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_recording_rotation(rotation);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_recording_rotation(state: State<'_, AppState>) -> Result<RecordingRotation, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_recording_rotation())
+}
+
+#[tauri::command]
+async fn set_auto_record(state: State<'_, AppState>, settings: AutoRecordSettings) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.set_auto_record(settings);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_auto_record(state: State<'_, AppState>) -> Result<AutoRecordSettings, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    Ok(manager.get_auto_record())
+}
+
+/// Split a raw capture into its sent and received bytes; returns both paths
+#[tauri::command]
+async fn split_raw_capture(file_path: String) -> Result<Vec<String>, String> {
+    let (tx_path, rx_path) = raw_capture::split(std::path::Path::new(&file_path))
+        .map_err(|e| e.to_string())?;
+    Ok(vec![tx_path.to_string_lossy().to_string(), rx_path.to_string_lossy().to_string()])
+}
+
+#[tauri::command]
+async fn convert_raw_capture(
+    state: State<'_, AppState>,
+    file_path: String,
+    output_path: String,
+    format: ExportFormat,
+    options: Option<ExportOptions>,
+    timezone_offset_minutes: Option<i32>,
+) -> Result<usize, String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.convert_raw_capture(
+        &file_path,
+        &output_path,
+        format,
+        options.unwrap_or_default(),
+        timezone_offset_minutes.unwrap_or(0),
+    )
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn get_recording_status(state: State<'_, AppState>) -> Result<RecordingStatus, String> {
     let manager = state.serial_manager.lock().unwrap();
@@ -247,16 +575,7 @@ async fn get_recording_status(state: State<'_, AppState>) -> Result<RecordingSta
 /// Encode text string to bytes using the specified encoding
 #[tauri::command]
 async fn encode_text(text: String, encoding: TextEncoding) -> Result<Vec<u8>, String> {
-    match encoding {
-        TextEncoding::Utf8 => Ok(text.into_bytes()),
-        TextEncoding::Gbk => {
-            let (encoded, _, had_errors) = encoding_rs::GBK.encode(&text);
-            if had_errors {
-                log::warn!("Some characters could not be encoded to GBK");
-            }
-            Ok(encoded.into_owned())
-        }
-    }
+    Ok(send_syntax::encode_text(&text, &encoding))
 }
 
 /// Decode bytes to text string using the specified encoding
@@ -307,6 +626,13 @@ async fn set_show_timestamps(state: State<'_, AppState>, show: bool) -> Result<(
     Ok(())
 }
 
+#[tauri::command]
+async fn reformat_logs(state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.serial_manager.lock().unwrap();
+    manager.reformat_logs();
+    Ok(())
+}
+
 #[tauri::command]
 async fn get_display_settings(state: State<'_, AppState>) -> Result<DisplaySettings, String> {
     let manager = state.serial_manager.lock().unwrap();
@@ -351,6 +677,15 @@ fn main() {
     tauri::Builder::default()
         .manage(AppState::default())
         .plugin(tauri_plugin_dialog::init())
+        .setup(|app| {
+            // Let the serial manager push events (transfer progress etc.) to the frontend
+            let app_handle = app.handle().clone();
+            let state = app.state::<AppState>();
+            state.serial_manager.lock().unwrap().set_event_emitter(Arc::new(move |event, payload| {
+                let _ = app_handle.emit(event, payload);
+            }));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             list_serial_ports,
             connect_to_port,
@@ -359,7 +694,31 @@ fn main() {
             get_connection_status,
             get_logs,
             clear_logs,
+            get_log_page,
+            get_log_session_info,
+            search_logs,
+            set_log_rules,
+            get_log_rules,
+            add_bookmark,
+            update_bookmark,
+            remove_bookmark,
+            get_bookmarks,
+            find_bookmark,
+            set_triggers,
+            get_triggers,
+            get_trigger_statuses,
+            reset_trigger_counters,
+            set_capture_paused,
+            is_capture_paused,
+            set_simulator_config,
+            get_simulator_config,
+            get_simulator_status,
+            reset_simulator,
+            start_replay,
+            stop_replay,
+            get_active_replay,
             export_logs,
+            import_logs,
             save_session,
             load_session,
             list_sessions,
@@ -367,6 +726,12 @@ fn main() {
             get_log_limit,
             set_frame_segmentation,
             get_frame_segmentation,
+            send_file,
+            send_file_with_protocol,
+            receive_file_with_protocol,
+            cancel_transfer,
+            flash_stm32,
+            flash_esp,
             set_log_directory,
             get_log_directory,
             set_timezone_offset,
@@ -374,6 +739,16 @@ fn main() {
             stop_text_recording,
             start_raw_recording,
             stop_raw_recording,
+            set_text_recording_content,
+            get_text_recording_content,
+            set_raw_recording_mode,
+            get_raw_recording_mode,
+            set_recording_rotation,
+            get_recording_rotation,
+            set_auto_record,
+            get_auto_record,
+            split_raw_capture,
+            convert_raw_capture,
             get_recording_status,
             encode_text,
             decode_bytes,
@@ -382,6 +757,7 @@ fn main() {
             set_special_char_config,
             set_show_timestamps,
             get_display_settings,
+            reformat_logs,
             check_for_updates,
             download_update,
             launch_installer_and_exit
@@ -0,0 +1,115 @@
+//! Byte patterns used to search and filter log entries
+//!
+//! Every mode compiles to a `regex::bytes::Regex`, so matching runs on the raw
+//! entry bytes whatever display format is selected:
+//! - `Text`: literal text, encoded with the current text encoding
+//! - `Regex`: a regular expression; non-ASCII literals match their UTF-8 bytes
+//! - `Hex`: byte pairs with optional whitespace, `??` matches any byte
+
+use crate::send_syntax::encode_text;
+use crate::types::{PatternMode, TextEncoding};
+use regex::bytes::{Regex, RegexBuilder};
+use std::fmt::Write;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PatternError {
+    #[error("Pattern is empty")]
+    Empty,
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error("Invalid hex pattern '{0}' (expected byte pairs or ??)")]
+    InvalidHex(String),
+}
+
+/// Compile `pattern` for matching against entry bytes. `case_sensitive` is
+/// ignored for hex patterns.
+pub fn compile(
+    pattern: &str,
+    mode: &PatternMode,
+    case_sensitive: bool,
+    encoding: &TextEncoding,
+) -> Result<Regex, PatternError> {
+    if pattern.is_empty() {
+        return Err(PatternError::Empty);
+    }
+
+    let (source, unicode) = match mode {
+        PatternMode::Regex => (pattern.to_string(), true),
+        PatternMode::Text => match encoding {
+            TextEncoding::Utf8 => (regex::escape(pattern), true),
+            // GBK bytes are not valid UTF-8, so match them byte by byte
+            TextEncoding::Gbk => (escape_bytes(&encode_text(pattern, encoding)), false),
+        },
+        PatternMode::Hex => (hex_to_regex(pattern)?, false),
+    };
+
+    Ok(RegexBuilder::new(&source)
+        .unicode(unicode)
+        .case_insensitive(!case_sensitive && *mode != PatternMode::Hex)
+        .dot_matches_new_line(*mode == PatternMode::Hex)
+        .build()?)
+}
+
+/// Regex source matching `bytes` literally (for non-Unicode regexes)
+pub(crate) fn escape_bytes(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut source, byte| {
+        let _ = write!(source, "\\x{:02X}", byte);
+        source
+    })
+}
+
+fn hex_to_regex(pattern: &str) -> Result<String, PatternError> {
+    let digits: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() {
+        return Err(PatternError::Empty);
+    }
+
+    let mut source = String::new();
+    for pair in digits.chunks(2) {
+        match pair {
+            ['?', '?'] => source.push('.'),
+            [high, low] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                let _ = write!(source, "\\x{}{}", high, low);
+            }
+            _ => return Err(PatternError::InvalidHex(pattern.to_string())),
+        }
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_patterns_are_literal_and_follow_the_encoding() {
+        let re = compile("a.b", &PatternMode::Text, false, &TextEncoding::Utf8).unwrap();
+        assert!(re.is_match(b"xx A.B yy"));
+        assert!(!re.is_match(b"axb"));
+
+        let re = compile("ERROR", &PatternMode::Text, true, &TextEncoding::Utf8).unwrap();
+        assert!(!re.is_match(b"error"));
+
+        let gbk = encode_text("温度", &TextEncoding::Gbk);
+        let re = compile("温度", &PatternMode::Text, false, &TextEncoding::Gbk).unwrap();
+        assert!(re.is_match(&[b"T=".as_slice(), &gbk].concat()));
+    }
+
+    #[test]
+    fn hex_patterns_support_wildcards() {
+        let re = compile("AA ?? 0d0a", &PatternMode::Hex, false, &TextEncoding::Utf8).unwrap();
+        let m = re.find(&[0x01, 0xAA, 0x0A, 0x0D, 0x0A]).unwrap();
+        assert_eq!((m.start(), m.end()), (1, 5));
+        assert!(!re.is_match(&[0xAA, 0x0D, 0x0A]));
+
+        assert!(matches!(
+            compile("AA B", &PatternMode::Hex, false, &TextEncoding::Utf8),
+            Err(PatternError::InvalidHex(_))
+        ));
+        assert!(matches!(
+            compile("", &PatternMode::Regex, false, &TextEncoding::Utf8),
+            Err(PatternError::Empty)
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! Timestamped raw capture files (`.rsrec`)
+//!
+//! A capture starts with the 8-byte magic `RSRAWREC` and a version byte,
+//! followed by one record per chunk of sent or received bytes:
+//!
+//! | size | field                                          |
+//! |------|------------------------------------------------|
+//! | 1    | direction: 0 = received, 1 = sent, 2 = system  |
+//! | 8    | timestamp, microseconds since the Unix epoch   |
+//! | 4    | data length                                    |
+//! | n    | data                                           |
+//!
+//! Integers are little-endian. Received records hold the bytes of one port
+//! read, not segmented frames; system records hold UTF-8 markers such as
+//! connects and disconnects. A capture cut short (e.g. by a crash) is read
+//! up to its last complete record. Captures gzipped by recording rotation are
+//! read as well.
+
+use crate::recording;
+use crate::types::{DataFormat, Direction, LogEntry};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub const EXTENSION: &str = "rsrec";
+const MAGIC: &[u8; 8] = b"RSRAWREC";
+const VERSION: u8 = 1;
+const RECORD_HEADER_LEN: usize = 13;
+
+/// One chunk of bytes from a capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawRecord {
+    pub direction: Direction,
+    pub timestamp: DateTime<Utc>,
+    pub data: Vec<u8>,
+}
+
+/// Write the file header of a new capture
+pub fn write_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])
+}
+
+/// Append one record
+pub fn write_record<W: Write>(writer: &mut W, direction: &Direction, timestamp: DateTime<Utc>, data: &[u8]) -> std::io::Result<()> {
+    let direction = match direction {
+        Direction::Received => 0u8,
+        Direction::Sent => 1u8,
+        Direction::System => 2u8,
+    };
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    header[0] = direction;
+    header[1..9].copy_from_slice(&timestamp.timestamp_micros().to_le_bytes());
+    header[9..13].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(data)
+}
+
+/// Read all complete records of a capture file
+pub fn read_records(path: &Path) -> Result<Vec<RawRecord>> {
+    parse_records(recording::open_file(path)?)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+/// Read all complete records from the start of a capture
+pub fn parse_records<R: Read>(mut reader: R) -> Result<Vec<RawRecord>> {
+    let mut magic = [0u8; 9];
+    if reader.read_exact(&mut magic).is_err() || &magic[..8] != MAGIC {
+        return Err(anyhow!("Not a raw capture"));
+    }
+    if magic[8] != VERSION {
+        return Err(anyhow!("Unsupported raw capture version {}", magic[8]));
+    }
+
+    let mut records = Vec::new();
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let direction = match header[0] {
+            0 => Direction::Received,
+            1 => Direction::Sent,
+            2 => Direction::System,
+            other => return Err(anyhow!("Invalid direction {} in record {}", other, records.len() + 1)),
+        };
+        let micros = i64::from_le_bytes(header[1..9].try_into()?);
+        let timestamp = DateTime::from_timestamp_micros(micros)
+            .ok_or_else(|| anyhow!("Invalid timestamp in record {}", records.len() + 1))?;
+        let length = u32::from_le_bytes(header[9..13].try_into()?) as u64;
+
+        // Grows with the data actually present, so a corrupt length cannot
+        // request a huge allocation
+        let mut data = Vec::new();
+        reader.by_ref().take(length).read_to_end(&mut data)?;
+        if (data.len() as u64) < length {
+            // Truncated last record
+            break;
+        }
+        records.push(RawRecord { direction, timestamp, data });
+    }
+    Ok(records)
+}
+
+/// Records as log entries, one per record
+pub fn to_entries(records: Vec<RawRecord>) -> Vec<LogEntry> {
+    records
+        .into_iter()
+        .map(|record| LogEntry {
+            id: None,
+            timestamp: record.timestamp,
+            direction: record.direction,
+            display_text: String::from_utf8_lossy(&record.data).into_owned(),
+            data: record.data,
+            format: DataFormat::Text,
+            port_name: String::new(),
+            timestamp_formatted: None,
+            hidden: false,
+            highlights: Vec::new(),
+        })
+        .collect()
+}
+
+/// Write the sent and received bytes of a capture to `<name>.tx.bin` and
+/// `<name>.rx.bin` next to it, leaving out system markers. Returns the two
+/// paths.
+pub fn split(path: &Path) -> Result<(PathBuf, PathBuf)> {
+    let records = read_records(path)?;
+    // `<name>.rsrec.gz` splits like `<name>.rsrec`
+    let base = match path.extension() {
+        Some(ext) if ext == "gz" => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    let tx_path = base.with_extension("tx.bin");
+    let rx_path = base.with_extension("rx.bin");
+    let mut tx = BufWriter::new(File::create(&tx_path)?);
+    let mut rx = BufWriter::new(File::create(&rx_path)?);
+    for record in &records {
+        match record.direction {
+            Direction::Sent => tx.write_all(&record.data)?,
+            Direction::Received => rx.write_all(&record.data)?,
+            Direction::System => {}
+        }
+    }
+    tx.flush()?;
+    rx.flush()?;
+    Ok((tx_path, rx_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip_and_split_by_direction() {
+        let dir = std::env::temp_dir().join(format!("raw-capture-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.rsrec");
+        let at = DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+
+        let mut file = File::create(&path).unwrap();
+        write_header(&mut file).unwrap();
+        write_record(&mut file, &Direction::Sent, at, b"AT\r\n").unwrap();
+        write_record(&mut file, &Direction::System, at, b"Connected").unwrap();
+        write_record(&mut file, &Direction::Received, at, &[0x00, 0xFF]).unwrap();
+        write_record(&mut file, &Direction::Received, at, b"OK").unwrap();
+        // A record cut short by a crash, claiming 4 GiB of data
+        file.write_all(&[0; 9]).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        file.write_all(b"cut").unwrap();
+        drop(file);
+
+        let records = read_records(&path).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0], RawRecord { direction: Direction::Sent, timestamp: at, data: b"AT\r\n".to_vec() });
+        assert_eq!(records[1].direction, Direction::System);
+        assert_eq!(records[2].data, [0x00, 0xFF]);
+
+        let (tx, rx) = split(&path).unwrap();
+        assert_eq!(tx, dir.join("capture.tx.bin"));
+        assert_eq!(std::fs::read(tx).unwrap(), b"AT\r\n");
+        assert_eq!(std::fs::read(rx).unwrap(), [0x00, 0xFF, b'O', b'K']);
+
+        assert!(read_records(&dir.join("capture.tx.bin")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,695 @@
+//! Text and raw recording files
+//!
+//! Text recordings hold one `[timestamp] DIR: text` line per log entry, in
+//! the same form as TXT exports; the text is decoded with the configured
+//! encoding, or written as hex or as rendered in the log view. Raw recordings
+//! hold the received and sent bytes without framing, either in one file, in
+//! one file per direction or as timestamped records (see `raw_capture`).
+//! Files are created in the log directory and named after the port and the
+//! start time, and written in the background (see `recording_writer`).
+//!
+//! With rotation, a recording moves on to a new file (`_002`, `_003`, ...)
+//! when the current one gets too large, too old or too long. Closed files can
+//! be gzipped, and the oldest recordings are deleted to keep the log
+//! directory under a size cap; both happen on a background thread.
+//!
+//! Auto recordings start on connect and outlive disconnects: a device that
+//! drops off the bus (e.g. on reset) and comes back continues in the same
+//! files. Connects and disconnects are marked as `SYS` lines in text
+//! recordings and as system records in raw captures; plain raw files have no
+//! room for markers.
+
+use crate::export::TXT_TIMESTAMP_FORMAT;
+use crate::raw_capture;
+use crate::recording_writer::{RecordingWriter, WriterFile};
+use crate::send_syntax;
+use crate::serial_manager::{format_bytes_as_hex, format_data_for_display, format_date_for_filename_with_offset};
+use crate::types::{AutoRecordSettings, DisplaySettings, Direction, RawRecordingMode, RecordingRotation, RecordingStatus, TextRecordingContent};
+use anyhow::{anyhow, Result};
+use chrono::{FixedOffset, Utc};
+use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use regex::Regex;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Names of recording files, including numbered, split and gzipped ones
+const RECORDING_FILE_PATTERN: &str =
+    r"_\d{4}-\d{2}-\d{2}_\d{2}-\d{2}-\d{2}(_\d{3,})?\.(txt|bin|tx\.bin|rx\.bin|rsrec)(\.gz)?$";
+
+/// What a recording writes, fixed when it starts
+#[derive(Debug, Clone, PartialEq)]
+enum RecordingKind {
+    Text,
+    Raw(RawRecordingMode),
+}
+
+struct Recording {
+    kind: RecordingKind,
+    port_name: String,
+    /// Number of the current file of a rotating recording
+    segment: Option<u32>,
+    /// The sent bytes when split, otherwise everything
+    file: WriterFile,
+    /// The received bytes when split
+    rx_file: Option<WriterFile>,
+    opened: Instant,
+    bytes: u64,
+    lines: u64,
+}
+
+impl Recording {
+    fn paths(&self) -> Vec<PathBuf> {
+        std::iter::once(&self.file)
+            .chain(self.rx_file.as_ref())
+            .map(|active| PathBuf::from(&active.path))
+            .collect()
+    }
+
+    /// Hand the files back to the writer to close, waiting until they are
+    /// on disk when `wait` is set. Returns their paths.
+    fn close(self, writer: &RecordingWriter, wait: bool) -> Result<Vec<PathBuf>> {
+        let paths = self.paths();
+        let mut result = Ok(());
+        for file in std::iter::once(self.file).chain(self.rx_file) {
+            if wait {
+                result = result.and(writer.close_and_wait(file));
+            } else {
+                writer.close(file);
+            }
+        }
+        result.map(|_| paths)
+    }
+
+    fn rotation_due(&self, rotation: &RecordingRotation) -> bool {
+        let limit = |value: Option<u64>| value.filter(|&limit| limit > 0);
+        limit(rotation.max_file_bytes).is_some_and(|max| self.bytes >= max)
+            || limit(rotation.max_duration_secs).is_some_and(|secs| self.opened.elapsed() >= Duration::from_secs(secs))
+            || (self.kind == RecordingKind::Text && limit(rotation.max_lines).is_some_and(|max| self.lines >= max))
+    }
+}
+
+pub struct Recorder {
+    writer: RecordingWriter,
+    text: Mutex<Option<Recording>>,
+    raw: Mutex<Option<Recording>>,
+    text_content: Mutex<TextRecordingContent>,
+    raw_mode: Mutex<RawRecordingMode>,
+    rotation: Mutex<RecordingRotation>,
+    auto_record: Mutex<AutoRecordSettings>,
+    /// Device the auto recordings were started for
+    device: Mutex<Option<String>>,
+    log_directory: Arc<Mutex<String>>,
+    timezone_offset_minutes: Arc<Mutex<i32>>,
+    display_settings: Arc<Mutex<DisplaySettings>>,
+}
+
+impl Recorder {
+    /// The recorder shares the manager's log directory, timezone and display settings
+    pub fn new(
+        log_directory: Arc<Mutex<String>>,
+        timezone_offset_minutes: Arc<Mutex<i32>>,
+        display_settings: Arc<Mutex<DisplaySettings>>,
+    ) -> Self {
+        Self {
+            writer: RecordingWriter::new(),
+            text: Mutex::new(None),
+            raw: Mutex::new(None),
+            text_content: Mutex::new(TextRecordingContent::default()),
+            raw_mode: Mutex::new(RawRecordingMode::default()),
+            rotation: Mutex::new(RecordingRotation::default()),
+            auto_record: Mutex::new(AutoRecordSettings::default()),
+            device: Mutex::new(None),
+            log_directory,
+            timezone_offset_minutes,
+            display_settings,
+        }
+    }
+
+    /// Applies to lines written from now on, including to an open recording
+    pub fn set_text_content(&self, content: TextRecordingContent) {
+        *self.text_content.lock().unwrap_or_else(|e| e.into_inner()) = content;
+    }
+
+    pub fn text_content(&self) -> TextRecordingContent {
+        self.text_content.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Applies to raw recordings started from now on
+    pub fn set_raw_mode(&self, mode: RawRecordingMode) {
+        *self.raw_mode.lock().unwrap_or_else(|e| e.into_inner()) = mode;
+    }
+
+    pub fn raw_mode(&self) -> RawRecordingMode {
+        self.raw_mode.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Applies to open recordings as well; a recording started without
+    /// rotation continues with file `_002`
+    pub fn set_rotation(&self, rotation: RecordingRotation) {
+        *self.rotation.lock().unwrap_or_else(|e| e.into_inner()) = rotation;
+    }
+
+    pub fn rotation(&self) -> RecordingRotation {
+        self.rotation.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Applies from the next connect or disconnect
+    pub fn set_auto_record(&self, settings: AutoRecordSettings) {
+        *self.auto_record.lock().unwrap_or_else(|e| e.into_inner()) = settings;
+    }
+
+    pub fn auto_record(&self) -> AutoRecordSettings {
+        self.auto_record.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Start the auto recordings on connect, or continue them when `device`
+    /// is the one they were started for. Recordings left open for another
+    /// device are stopped first. `marker` is written to the recordings.
+    pub fn connected(&self, device: &str, port_name: &str, marker: &str) -> Result<()> {
+        let settings = self.auto_record();
+        if !settings.is_enabled() {
+            return Ok(());
+        }
+
+        let same_device = self.device.lock().unwrap_or_else(|e| e.into_inner()).as_deref() == Some(device);
+        if !same_device {
+            self.stop_all();
+            *self.device.lock().unwrap_or_else(|e| e.into_inner()) = Some(device.to_string());
+        }
+
+        let status = self.status();
+        if settings.text && !status.text_recording_active {
+            self.start_text(port_name)?;
+        }
+        if settings.raw && !status.raw_recording_active {
+            self.start_raw(port_name)?;
+        }
+        self.mark(marker);
+        Ok(())
+    }
+
+    /// Keep the recordings open through a disconnect when auto recording is
+    /// on, otherwise stop them. `marker` is None when it was already written.
+    pub fn disconnected(&self, marker: Option<&str>) {
+        if self.auto_record().is_enabled() {
+            if let Some(marker) = marker {
+                self.mark(marker);
+            }
+        } else {
+            self.stop_all();
+        }
+    }
+
+    /// Write a system marker to the text recording and raw capture
+    pub fn mark(&self, marker: &str) {
+        self.write_text(marker.as_bytes(), Direction::System);
+        self.write_raw(marker.as_bytes(), Direction::System);
+    }
+
+    /// Generate a filename with port name, timestamp and, for rotating
+    /// recordings, the file number
+    pub fn generate_recording_filename(&self, port_name: &str, extension: &str, segment: Option<u32>) -> Result<PathBuf> {
+        let dir_path = self.log_directory_path();
+
+        // Create directory if it doesn't exist
+        if !dir_path.exists() {
+            create_dir_all(&dir_path)?;
+        }
+
+        // Sanitize port name for filename (replace special characters)
+        let safe_port_name = port_name.replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_");
+
+        let timestamp = format_date_for_filename_with_offset(self.timezone_offset());
+        let number = segment.map(|segment| format!("_{:03}", segment)).unwrap_or_default();
+        let filename = format!("{}_{}{}.{}", safe_port_name, timestamp, number, extension);
+
+        Ok(dir_path.join(filename))
+    }
+
+    /// Start text recording - creates a new text file and begins recording
+    pub fn start_text(&self, port_name: &str) -> Result<String> {
+        self.start(&self.text, RecordingKind::Text, port_name)
+    }
+
+    /// Stop text recording - closes the file
+    pub fn stop_text(&self) -> Result<()> {
+        self.stop(&self.text)
+    }
+
+    /// Start raw binary recording in the current raw mode. Returns the path
+    /// of the file, or of the sent bytes file when split.
+    pub fn start_raw(&self, port_name: &str) -> Result<String> {
+        self.start(&self.raw, RecordingKind::Raw(self.raw_mode()), port_name)
+    }
+
+    /// Stop raw binary recording - closes the file(s)
+    pub fn stop_raw(&self) -> Result<()> {
+        self.stop(&self.raw)
+    }
+
+    /// Stop all recordings
+    pub fn stop_all(&self) {
+        let _ = self.stop_text();
+        let _ = self.stop_raw();
+        *self.device.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        let text_file_path = self.text.lock()
+            .map(|guard| guard.as_ref().map(|recording| recording.file.path.clone()))
+            .unwrap_or(None);
+        let (raw_file_path, raw_rx_file_path) = self.raw.lock()
+            .map(|guard| match guard.as_ref() {
+                Some(recording) => (
+                    Some(recording.file.path.clone()),
+                    recording.rx_file.as_ref().map(|active| active.path.clone()),
+                ),
+                None => (None, None),
+            })
+            .unwrap_or((None, None));
+
+        let health = self.writer.health();
+
+        RecordingStatus {
+            text_recording_active: text_file_path.is_some(),
+            raw_recording_active: raw_file_path.is_some(),
+            text_file_path,
+            raw_file_path,
+            raw_rx_file_path,
+            write_error: health.error,
+            dropped_bytes: health.dropped_bytes,
+        }
+    }
+
+    /// Write data to text recording file with timestamp, direction, and newline
+    pub fn write_text(&self, data: &[u8], direction: Direction) {
+        let closed = match self.text.lock() {
+            Ok(mut guard) => match guard.as_mut() {
+                Some(recording) => {
+                    let tz_offset = FixedOffset::east_opt(self.timezone_offset() * 60)
+                        .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+                    let timestamp = Utc::now().with_timezone(&tz_offset).format(TXT_TIMESTAMP_FORMAT);
+                    let text = self.text_for(data, &direction);
+                    // Formatted line with timestamp, direction, content, and newline
+                    let line = format!("[{}] {}: {}\n", timestamp, direction.label(), text);
+                    let len = line.len() as u64;
+                    if self.writer.write(&recording.file, line.into_bytes()) {
+                        recording.bytes += len;
+                        recording.lines += 1;
+                    }
+                    self.rotate_if_due(recording)
+                }
+                None => None,
+            },
+            Err(_) => None,
+        };
+        if let Some(closed) = closed {
+            self.housekeep(closed);
+        }
+    }
+
+    /// Write sent or received bytes to the raw recording. System markers
+    /// only go into raw captures.
+    pub fn write_raw(&self, data: &[u8], direction: Direction) {
+        let closed = match self.raw.lock() {
+            Ok(mut guard) => match guard.as_mut() {
+                Some(recording) => {
+                    let (file, bytes) = match (&recording.kind, recording.rx_file.as_ref()) {
+                        (RecordingKind::Raw(RawRecordingMode::Container), _) => {
+                            let mut record = Vec::with_capacity(data.len() + 16);
+                            // Writing to a Vec cannot fail
+                            let _ = raw_capture::write_record(&mut record, &direction, Utc::now(), data);
+                            (&recording.file, record)
+                        }
+                        _ if direction == Direction::System => return,
+                        (_, Some(rx_file)) if direction == Direction::Received => (rx_file, data.to_vec()),
+                        _ => (&recording.file, data.to_vec()),
+                    };
+                    let len = bytes.len() as u64;
+                    if self.writer.write(file, bytes) {
+                        recording.bytes += len;
+                    }
+                    self.rotate_if_due(recording)
+                }
+                None => None,
+            },
+            Err(_) => None,
+        };
+        if let Some(closed) = closed {
+            self.housekeep(closed);
+        }
+    }
+
+    fn text_for(&self, data: &[u8], direction: &Direction) -> String {
+        // System entries are the app's own (UTF-8) messages
+        if *direction == Direction::System {
+            return String::from_utf8_lossy(data).into_owned();
+        }
+        let settings = self.display_settings.lock().unwrap_or_else(|e| e.into_inner());
+        match self.text_content() {
+            TextRecordingContent::Text => send_syntax::decode_text(data, &settings.encoding),
+            TextRecordingContent::Hex => format_bytes_as_hex(data),
+            TextRecordingContent::Display => format_data_for_display(data, &settings),
+        }
+    }
+
+    fn timezone_offset(&self) -> i32 {
+        *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn log_directory_path(&self) -> PathBuf {
+        let log_dir = self.log_directory
+            .lock()
+            .map(|guard| guard.clone())
+            .unwrap_or_else(|_| String::from("./SerialLogs"));
+        PathBuf::from(log_dir)
+    }
+
+    fn start(&self, slot: &Mutex<Option<Recording>>, kind: RecordingKind, port_name: &str) -> Result<String> {
+        // Errors and drops are reported until no recording is left
+        let status = self.status();
+        if !status.text_recording_active && !status.raw_recording_active {
+            self.writer.clear_health();
+        }
+
+        let mut guard = slot.lock().unwrap_or_else(|e| e.into_inner());
+        // Check if already recording
+        if guard.is_some() {
+            let name = if kind == RecordingKind::Text { "Text" } else { "Raw" };
+            return Err(anyhow!("{} recording is already active", name));
+        }
+
+        let rotation = self.rotation();
+        let rotating = [rotation.max_file_bytes, rotation.max_duration_secs, rotation.max_lines]
+            .iter()
+            .any(|limit| limit.is_some_and(|limit| limit > 0));
+        let recording = self.open(kind, port_name, rotating.then_some(1))?;
+
+        let path = recording.file.path.clone();
+        info!("Started {:?} recording to: {}", recording.kind, path);
+        *guard = Some(recording);
+        Ok(path)
+    }
+
+    fn stop(&self, slot: &Mutex<Option<Recording>>) -> Result<()> {
+        let recording = slot.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(recording) = recording {
+            info!("Stopped {:?} recording: {}", recording.kind, recording.file.path);
+            let closed = recording.close(&self.writer, true)?;
+            self.housekeep(closed);
+        }
+        Ok(())
+    }
+
+    /// Create the file(s) of a recording or of its next segment
+    fn open(&self, kind: RecordingKind, port_name: &str, segment: Option<u32>) -> Result<Recording> {
+        let (file, rx_file) = match &kind {
+            RecordingKind::Text => {
+                (self.writer.open(&self.generate_recording_filename(port_name, "txt", segment)?, None)?, None)
+            }
+            RecordingKind::Raw(RawRecordingMode::Combined) => {
+                (self.writer.open(&self.generate_recording_filename(port_name, "bin", segment)?, None)?, None)
+            }
+            RecordingKind::Raw(RawRecordingMode::Split) => {
+                let base = self.generate_recording_filename(port_name, "bin", segment)?;
+                (
+                    self.writer.open(&base.with_extension("tx.bin"), None)?,
+                    Some(self.writer.open(&base.with_extension("rx.bin"), None)?),
+                )
+            }
+            RecordingKind::Raw(RawRecordingMode::Container) => {
+                let path = self.generate_recording_filename(port_name, raw_capture::EXTENSION, segment)?;
+                let mut header = Vec::new();
+                raw_capture::write_header(&mut header)?;
+                // Appending to a capture started in the same second keeps its header
+                (self.writer.open(&path, Some(&header))?, None)
+            }
+        };
+
+        Ok(Recording {
+            kind,
+            port_name: port_name.to_string(),
+            segment,
+            file,
+            rx_file,
+            opened: Instant::now(),
+            bytes: 0,
+            lines: 0,
+        })
+    }
+
+    /// Move on to the next numbered file when a rotation limit is reached.
+    /// Returns the paths of the closed file(s).
+    fn rotate_if_due(&self, recording: &mut Recording) -> Option<Vec<PathBuf>> {
+        if !recording.rotation_due(&self.rotation()) {
+            return None;
+        }
+
+        let segment = recording.segment.unwrap_or(1) + 1;
+        match self.open(recording.kind.clone(), &recording.port_name, Some(segment)) {
+            Ok(next) => {
+                let previous = std::mem::replace(recording, next);
+                info!("Rotated {:?} recording to: {}", recording.kind, recording.file.path);
+                previous.close(&self.writer, false).ok()
+            }
+            Err(e) => {
+                warn!("Failed to rotate recording {}: {}", recording.file.path, e);
+                None
+            }
+        }
+    }
+
+    /// Compress closed files and apply the directory size cap in the
+    /// background, once the writer has closed them
+    fn housekeep(&self, closed: Vec<PathBuf>) {
+        let rotation = self.rotation();
+        if !rotation.compress && rotation.max_directory_bytes.is_none() {
+            return;
+        }
+
+        let status = self.status();
+        let active: HashSet<OsString> = [status.text_file_path, status.raw_file_path, status.raw_rx_file_path]
+            .into_iter()
+            .flatten()
+            .filter_map(|path| Path::new(&path).file_name().map(|name| name.to_os_string()))
+            .collect();
+        let directory = self.log_directory_path();
+
+        self.writer.after(move || {
+            thread::spawn(move || {
+                if rotation.compress {
+                    for path in &closed {
+                        if let Err(e) = compress_file(path) {
+                            warn!("Failed to compress recording {}: {}", path.display(), e);
+                        }
+                    }
+                }
+                if let Some(max_bytes) = rotation.max_directory_bytes {
+                    if let Err(e) = enforce_directory_cap(&directory, max_bytes, &active) {
+                        warn!("Failed to apply the recording size cap: {}", e);
+                    }
+                }
+            });
+        });
+    }
+}
+
+/// Open a recording for reading. Segments gzipped by `compress_file` are
+/// recognised by their content and decompressed as they are read.
+pub fn open_file(path: &Path) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(if reader.fill_buf()?.starts_with(&[0x1F, 0x8B]) {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Gzip `path` to `path.gz` and remove the original
+fn compress_file(path: &Path) -> Result<PathBuf> {
+    let mut gz_path = path.as_os_str().to_os_string();
+    gz_path.push(".gz");
+    let gz_path = PathBuf::from(gz_path);
+
+    let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+    std::io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::remove_file(path)?;
+    Ok(gz_path)
+}
+
+/// Delete the oldest recording files in `directory` until all of them take at
+/// most `max_bytes`. Files named in `active` are kept; other files in the
+/// directory are neither counted nor deleted. Returns the deleted paths.
+fn enforce_directory_cap(directory: &Path, max_bytes: u64, active: &HashSet<OsString>) -> Result<Vec<PathBuf>> {
+    let pattern = Regex::new(RECORDING_FILE_PATTERN)?;
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && pattern.is_match(&entry.file_name().to_string_lossy()) {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, entry.file_name(), entry.path(), metadata.len()));
+        }
+    }
+
+    let mut total: u64 = files.iter().map(|(_, _, _, len)| len).sum();
+    files.sort();
+    let mut deleted = Vec::new();
+    for (_, name, path, len) in files {
+        if total <= max_bytes {
+            break;
+        }
+        if active.contains(&name) {
+            continue;
+        }
+        std::fs::remove_file(&path)?;
+        info!("Deleted recording {} to stay under {} bytes", path.display(), max_bytes);
+        total -= len;
+        deleted.push(path);
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TextEncoding;
+
+    fn temp_recorder(tz_minutes: i32, settings: DisplaySettings) -> (Recorder, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("recording-test-{}", uuid::Uuid::new_v4()));
+        let recorder = Recorder::new(
+            Arc::new(Mutex::new(dir.to_string_lossy().to_string())),
+            Arc::new(Mutex::new(tz_minutes)),
+            Arc::new(Mutex::new(settings)),
+        );
+        (recorder, dir)
+    }
+
+    #[test]
+    fn text_recording_decodes_with_the_encoding_and_full_stamps() {
+        let settings = DisplaySettings { encoding: TextEncoding::Gbk, ..Default::default() };
+        let (recorder, dir) = temp_recorder(480, settings);
+        let path = recorder.start_text("COM3").unwrap();
+
+        let gbk = send_syntax::encode_text("温度", &TextEncoding::Gbk);
+        recorder.write_text(&gbk, Direction::Received);
+        recorder.set_text_content(TextRecordingContent::Hex);
+        recorder.write_text(&[0x00, 0xFF], Direction::Sent);
+        recorder.stop_text().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        let (stamp, text) = lines[0].trim_start_matches('[').split_once("] ").unwrap();
+        assert!(chrono::DateTime::parse_from_str(stamp, TXT_TIMESTAMP_FORMAT).is_ok());
+        assert!(stamp.ends_with("+08:00"));
+        assert_eq!(text, "RX: 温度");
+        assert!(lines[1].ends_with("] TX: 00 FF"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn raw_recording_separates_directions() {
+        let (recorder, dir) = temp_recorder(0, DisplaySettings::default());
+
+        recorder.set_raw_mode(RawRecordingMode::Split);
+        let tx_path = recorder.start_raw("COM3").unwrap();
+        let rx_path = recorder.status().raw_rx_file_path.unwrap();
+        assert!(tx_path.ends_with(".tx.bin") && rx_path.ends_with(".rx.bin"));
+        recorder.write_raw(b"AT\r\n", Direction::Sent);
+        recorder.write_raw(b"OK", Direction::Received);
+        recorder.stop_raw().unwrap();
+        assert_eq!(std::fs::read(&tx_path).unwrap(), b"AT\r\n");
+        assert_eq!(std::fs::read(&rx_path).unwrap(), b"OK");
+
+        recorder.set_raw_mode(RawRecordingMode::Container);
+        let path = recorder.start_raw("COM3").unwrap();
+        assert!(recorder.status().raw_rx_file_path.is_none());
+        recorder.write_raw(b"AT", Direction::Sent);
+        recorder.write_raw(b"OK", Direction::Received);
+        recorder.stop_raw().unwrap();
+        let records = raw_capture::read_records(Path::new(&path)).unwrap();
+        let directions: Vec<_> = records.iter().map(|record| record.direction.clone()).collect();
+        assert_eq!(directions, [Direction::Sent, Direction::Received]);
+        assert_eq!(records[1].data, b"OK");
+
+        let compressed = compress_file(Path::new(&path)).unwrap();
+        assert_eq!(raw_capture::read_records(&compressed).unwrap(), records);
+        let (tx, _) = raw_capture::split(&compressed).unwrap();
+        assert_eq!(tx, Path::new(&path).with_extension("tx.bin"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_numbers_files_and_the_cap_deletes_the_oldest() {
+        let (recorder, dir) = temp_recorder(0, DisplaySettings::default());
+        recorder.set_rotation(RecordingRotation { max_lines: Some(2), ..Default::default() });
+        let first = recorder.start_text("COM3").unwrap();
+        assert!(first.ends_with("_001.txt"));
+        for line in ["a", "b", "c"] {
+            recorder.write_text(line.as_bytes(), Direction::Received);
+        }
+        let second = recorder.status().text_file_path.unwrap();
+        assert!(second.ends_with("_002.txt"));
+        recorder.stop_text().unwrap();
+        assert_eq!(std::fs::read_to_string(&first).unwrap().lines().count(), 2);
+        assert!(std::fs::read_to_string(&second).unwrap().ends_with("RX: c\n"));
+
+        let compressed = compress_file(Path::new(&first)).unwrap();
+        assert!(!Path::new(&first).exists());
+        let imported = crate::log_import::load_entries(&compressed, 0).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[1].entry.data, b"b");
+
+        // Not a recording, so neither counted nor deleted
+        std::fs::write(dir.join("notes.txt"), [0u8; 4096]).unwrap();
+        let second_len = std::fs::metadata(&second).unwrap().len();
+        let active = HashSet::from([Path::new(&second).file_name().unwrap().to_os_string()]);
+        let deleted = enforce_directory_cap(&dir, second_len, &active).unwrap();
+        assert_eq!(deleted, [compressed]);
+        assert!(Path::new(&second).exists() && dir.join("notes.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn auto_recordings_continue_across_reconnects_of_the_same_device() {
+        let (recorder, dir) = temp_recorder(0, DisplaySettings::default());
+        recorder.set_raw_mode(RawRecordingMode::Container);
+        recorder.set_auto_record(AutoRecordSettings { text: true, raw: true });
+
+        recorder.connected("usb:1a86:7523:A1", "COM3", "Connected to COM3").unwrap();
+        let text_path = recorder.status().text_file_path.unwrap();
+        let raw_path = recorder.status().raw_file_path.unwrap();
+        recorder.write_text(b"boot", Direction::Received);
+        recorder.disconnected(Some("Disconnected from COM3"));
+        assert!(recorder.status().text_recording_active);
+
+        // The board comes back on another port after a reset
+        recorder.connected("usb:1a86:7523:A1", "COM4", "Connected to COM4").unwrap();
+        assert_eq!(recorder.status().text_file_path.as_ref(), Some(&text_path));
+        recorder.write_raw(b"OK", Direction::Received);
+
+        recorder.connected("usb:0483:5740:B2", "COM5", "Connected to COM5").unwrap();
+        assert_ne!(recorder.status().text_file_path.as_ref(), Some(&text_path));
+        recorder.set_auto_record(AutoRecordSettings::default());
+        recorder.disconnected(Some("Disconnected from COM5"));
+        assert!(!recorder.status().raw_recording_active);
+
+        let text = std::fs::read_to_string(&text_path).unwrap();
+        let lines: Vec<&str> = text.lines().map(|line| line.split_once("] ").unwrap().1).collect();
+        assert_eq!(lines, ["SYS: Connected to COM3", "RX: boot", "SYS: Disconnected from COM3", "SYS: Connected to COM4"]);
+        let records = raw_capture::read_records(Path::new(&raw_path)).unwrap();
+        let directions: Vec<_> = records.iter().map(|record| record.direction.clone()).collect();
+        assert_eq!(directions, [Direction::System, Direction::System, Direction::System, Direction::Received]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+//! Background writer for recording files
+//!
+//! Recordings are written by the reader thread, so writing must never block
+//! it. Data is queued to one writer thread that keeps every open file behind
+//! a buffer and syncs it to disk at most `SYNC_INTERVAL` after it was
+//! written. When the disk cannot keep up (or is full and the queue fills),
+//! new data is dropped and counted instead of waiting. Write errors are kept
+//! for `RecordingStatus`.
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest time written data may stay in buffers before it is synced to disk
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// Data waiting for the writer beyond this is dropped rather than queued
+const MAX_QUEUED_BYTES: u64 = 16 * 1024 * 1024;
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Problems since the health was last cleared
+#[derive(Debug, Clone, Default)]
+pub struct WriterHealth {
+    /// Last write, sync or close error, with the file it happened on
+    pub error: Option<String>,
+    /// Bytes dropped because the queue was full
+    pub dropped_bytes: u64,
+}
+
+#[derive(Default)]
+struct SharedState {
+    health: WriterHealth,
+    queued_bytes: u64,
+}
+
+/// A file owned by the writer thread
+pub struct WriterFile {
+    id: u64,
+    pub path: String,
+}
+
+enum Message {
+    Open { id: u64, path: String, file: File },
+    Write { id: u64, data: Vec<u8> },
+    /// Flush, sync and close; `done` receives the result
+    Close { id: u64, done: Option<Sender<std::io::Result<()>>> },
+    /// Run once everything queued before it has been handled
+    After(Box<dyn FnOnce() + Send>),
+}
+
+pub struct RecordingWriter {
+    sender: Mutex<Sender<Message>>,
+    state: Arc<Mutex<SharedState>>,
+    next_id: AtomicU64,
+}
+
+impl RecordingWriter {
+    /// Start the writer thread; it ends, closing its files, when the writer is dropped
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let state = Arc::new(Mutex::new(SharedState::default()));
+        let thread_state = Arc::clone(&state);
+        thread::spawn(move || run(receiver, thread_state));
+        Self {
+            sender: Mutex::new(sender),
+            state,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Open `path` for appending; `header` is written first when the file is new
+    pub fn open(&self, path: &Path, header: Option<&[u8]>) -> Result<WriterFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let path = path.to_string_lossy().to_string();
+        self.send(Message::Open { id, path: path.clone(), file })?;
+
+        if let (true, Some(header)) = (is_new, header) {
+            // Never dropped, or the file would be unreadable
+            self.state.lock().unwrap_or_else(|e| e.into_inner()).queued_bytes += header.len() as u64;
+            self.send(Message::Write { id, data: header.to_vec() })?;
+        }
+        Ok(WriterFile { id, path })
+    }
+
+    /// Queue `data` for `file`. Returns false when it was dropped because the
+    /// queue is full.
+    pub fn write(&self, file: &WriterFile, data: Vec<u8>) -> bool {
+        let len = data.len() as u64;
+        {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.queued_bytes + len > MAX_QUEUED_BYTES {
+                state.health.dropped_bytes += len;
+                return false;
+            }
+            state.queued_bytes += len;
+        }
+        if self.send(Message::Write { id: file.id, data }).is_err() {
+            self.state.lock().unwrap_or_else(|e| e.into_inner()).queued_bytes -= len;
+            return false;
+        }
+        true
+    }
+
+    /// Close `file` once its queued data is written, without waiting
+    pub fn close(&self, file: WriterFile) {
+        let _ = self.send(Message::Close { id: file.id, done: None });
+    }
+
+    /// Close `file` and wait until its data is on disk
+    pub fn close_and_wait(&self, file: WriterFile) -> Result<()> {
+        let (done, result) = mpsc::channel();
+        self.send(Message::Close { id: file.id, done: Some(done) })?;
+        result.recv().map_err(|_| anyhow!("Recording writer stopped"))??;
+        Ok(())
+    }
+
+    /// Run `job` on the writer thread after everything queued so far
+    pub fn after(&self, job: impl FnOnce() + Send + 'static) {
+        let _ = self.send(Message::After(Box::new(job)));
+    }
+
+    pub fn health(&self) -> WriterHealth {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).health.clone()
+    }
+
+    pub fn clear_health(&self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).health = WriterHealth::default();
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(message)
+            .map_err(|_| anyhow!("Recording writer stopped"))
+    }
+}
+
+fn run(receiver: Receiver<Message>, state: Arc<Mutex<SharedState>>) {
+    let mut files: HashMap<u64, (BufWriter<File>, String)> = HashMap::new();
+    let mut unsynced_since: Option<Instant> = None;
+    let report = |path: &str, error: &std::io::Error| {
+        warn!("Error writing to recording file {}: {}", path, error);
+        state.lock().unwrap_or_else(|e| e.into_inner()).health.error = Some(format!("{}: {}", path, error));
+    };
+
+    loop {
+        match receiver.recv_timeout(SYNC_INTERVAL) {
+            Ok(Message::Open { id, path, file }) => {
+                files.insert(id, (BufWriter::with_capacity(BUFFER_SIZE, file), path));
+            }
+            Ok(Message::Write { id, data }) => {
+                state.lock().unwrap_or_else(|e| e.into_inner()).queued_bytes -= data.len() as u64;
+                if let Some((writer, path)) = files.get_mut(&id) {
+                    if let Err(e) = writer.write_all(&data) {
+                        report(path, &e);
+                    }
+                    unsynced_since.get_or_insert_with(Instant::now);
+                }
+            }
+            Ok(Message::Close { id, done }) => {
+                let result = match files.remove(&id) {
+                    Some((writer, path)) => {
+                        let result = writer
+                            .into_inner()
+                            .map_err(|e| e.into_error())
+                            .and_then(|file| file.sync_all());
+                        if let Err(e) = &result {
+                            report(&path, e);
+                        }
+                        result
+                    }
+                    None => Ok(()),
+                };
+                if let Some(done) = done {
+                    let _ = done.send(result);
+                }
+            }
+            Ok(Message::After(job)) => job(),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if unsynced_since.is_some_and(|since| since.elapsed() >= SYNC_INTERVAL) {
+            for (writer, path) in files.values_mut() {
+                if let Err(e) = writer.flush().and_then(|_| writer.get_ref().sync_data()) {
+                    report(path, &e);
+                }
+            }
+            unsynced_since = None;
+        }
+    }
+
+    for (mut writer, path) in files.into_values() {
+        if let Err(e) = writer.flush().and_then(|_| writer.get_ref().sync_all()) {
+            report(&path, &e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_data_reaches_the_file_and_overflow_is_dropped() {
+        let dir = std::env::temp_dir().join(format!("recording-writer-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.bin");
+        let writer = RecordingWriter::new();
+
+        let file = writer.open(&path, Some(b"HDR")).unwrap();
+        assert!(writer.write(&file, b"abc".to_vec()));
+        // Hold the writer thread up so the queue fills
+        let (release, blocked) = mpsc::channel::<()>();
+        writer.after(move || {
+            let _ = blocked.recv();
+        });
+        assert!(writer.write(&file, vec![1; MAX_QUEUED_BYTES as usize - 3]));
+        assert!(!writer.write(&file, b"lost".to_vec()));
+        release.send(()).unwrap();
+        writer.close_and_wait(file).unwrap();
+
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(&written[..6], b"HDRabc");
+        assert_eq!(written.len(), 3 + MAX_QUEUED_BYTES as usize);
+        assert_eq!(writer.health().dropped_bytes, 4);
+        assert!(writer.health().error.is_none());
+
+        // An existing file gets no second header
+        let file = writer.open(&path, Some(b"HDR")).unwrap();
+        writer.close_and_wait(file).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), written.len());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
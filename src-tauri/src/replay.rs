@@ -0,0 +1,65 @@
+//! Timing of session replays
+//!
+//! Entries are loaded with `log_import` and replayed with the gaps between
+//! their timestamps, optionally capped and scaled.
+
+use crate::types::LogEntry;
+use std::time::Duration;
+
+/// Delay of each entry from the start of the replay. Gaps are capped at
+/// `max_gap` before being divided by `speed`; a speed of 0 removes all delays.
+pub fn schedule(entries: &[LogEntry], speed: f64, max_gap: Option<Duration>) -> Vec<Duration> {
+    let mut offset = Duration::ZERO;
+    let mut previous = entries.first().map(|entry| entry.timestamp);
+
+    entries
+        .iter()
+        .map(|entry| {
+            // Entries out of order replay back to back
+            let gap = previous
+                .and_then(|previous| (entry.timestamp - previous).to_std().ok())
+                .unwrap_or_default();
+            let gap = max_gap.map_or(gap, |max| gap.min(max));
+            if speed > 0.0 {
+                offset += gap.div_f64(speed);
+            }
+            previous = Some(entry.timestamp);
+            offset
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DataFormat, Direction};
+    use chrono::{TimeZone, Utc};
+
+    fn entry_at(ms: i64) -> LogEntry {
+        LogEntry {
+            id: None,
+            timestamp: Utc.timestamp_millis_opt(ms).unwrap(),
+            direction: Direction::Sent,
+            data: Vec::new(),
+            format: DataFormat::Text,
+            port_name: String::new(),
+            display_text: String::new(),
+            timestamp_formatted: None,
+            hidden: false,
+            highlights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn schedule_scales_and_caps_gaps() {
+        let mut entries = vec![entry_at(0), entry_at(1000), entry_at(60_000)];
+        let ms = |offsets: Vec<Duration>| offsets.iter().map(Duration::as_millis).collect::<Vec<_>>();
+
+        assert_eq!(ms(schedule(&entries, 1.0, None)), [0, 1000, 60_000]);
+        assert_eq!(ms(schedule(&entries, 2.0, Some(Duration::from_secs(5)))), [0, 500, 3000]);
+        assert_eq!(ms(schedule(&entries, 0.0, None)), [0, 0, 0]);
+
+        entries.swap(1, 2);
+        assert_eq!(ms(schedule(&entries, 1.0, None)), [0, 60_000, 60_000]);
+    }
+}
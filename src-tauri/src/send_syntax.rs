@@ -0,0 +1,383 @@
+//! Send-side input syntax resolved in the backend
+//!
+//! Hex input is a list of byte pairs separated by optional whitespace.
+//! Escaped input is text mixed with:
+//! - escapes: `\r`, `\n`, `\t`, `\0`, `\\`, `\xNN`, `\{`, `\}`
+//! - raw byte blocks: `{hex:01 02 0A}`
+//! - placeholders: `{len}`, `{xor}`, `{sum8}`, `{crc8}`, `{crc16}`, `{crc16-ccitt}`
+//!
+//! Checksum placeholders cover every byte of the frame before them. `{len}` is
+//! one byte holding the length of the whole resolved frame.
+
+use crate::types::{DataFormat, TextEncoding};
+use thiserror::Error;
+
+/// Parse error with the character position (0-based) where it was detected
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SendSyntaxError {
+    #[error("Invalid escape sequence '{sequence}' at position {position}")]
+    InvalidEscape { position: usize, sequence: String },
+    #[error("Invalid hex character '{character}' at position {position}")]
+    InvalidHexChar { position: usize, character: char },
+    #[error("Hex string must have even number of characters (unpaired digit at position {position})")]
+    UnpairedHexDigit { position: usize },
+    #[error("Unterminated '{{' block starting at position {position}")]
+    UnterminatedBlock { position: usize },
+    #[error("Unknown placeholder '{{{name}}}' at position {position}")]
+    UnknownPlaceholder { position: usize, name: String },
+    #[error("Frame length {length} does not fit in the one-byte {{len}} placeholder at position {position}")]
+    LengthOverflow { position: usize, length: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Len,
+    Xor,
+    Sum8,
+    Crc8,
+    Crc16,
+    Crc16Ccitt,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "len" => Some(Placeholder::Len),
+            "xor" => Some(Placeholder::Xor),
+            "sum8" | "add8" => Some(Placeholder::Sum8),
+            "crc8" => Some(Placeholder::Crc8),
+            "crc16" => Some(Placeholder::Crc16),
+            "crc16-ccitt" | "ccitt-crc16" => Some(Placeholder::Crc16Ccitt),
+            _ => None,
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self {
+            Placeholder::Crc16 | Placeholder::Crc16Ccitt => 2,
+            _ => 1,
+        }
+    }
+}
+
+enum Segment {
+    Bytes(Vec<u8>),
+    Placeholder { kind: Placeholder, position: usize },
+}
+
+/// Parse space-separated (or unseparated) hex byte pairs
+pub fn parse_hex(input: &str) -> Result<Vec<u8>, SendSyntaxError> {
+    let chars: Vec<char> = input.chars().collect();
+    parse_hex_chars(&chars, 0)
+}
+
+/// Resolve escaped/mixed input into the exact bytes to send.
+/// Literal text runs are encoded with `encoding`.
+pub fn parse_escaped(input: &str, encoding: &TextEncoding) -> Result<Vec<u8>, SendSyntaxError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut segments = Vec::new();
+    let mut bytes = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                let (byte, consumed) = parse_escape(&chars, i)?;
+                flush_text(&mut text, &mut bytes, encoding);
+                match byte {
+                    EscapeValue::Byte(b) => bytes.push(b),
+                    EscapeValue::Char(c) => text.push(c),
+                }
+                i += consumed;
+            }
+            '{' => {
+                let close = chars[i + 1..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .map(|offset| i + 1 + offset)
+                    .ok_or(SendSyntaxError::UnterminatedBlock { position: i })?;
+                let body: String = chars[i + 1..close].iter().collect();
+                flush_text(&mut text, &mut bytes, encoding);
+
+                if body.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("hex:")) {
+                    let hex_chars = &chars[i + 5..close];
+                    bytes.extend(parse_hex_chars(hex_chars, i + 5)?);
+                } else {
+                    let kind = Placeholder::from_name(body.trim()).ok_or_else(|| {
+                        SendSyntaxError::UnknownPlaceholder { position: i, name: body.clone() }
+                    })?;
+                    if !bytes.is_empty() {
+                        segments.push(Segment::Bytes(std::mem::take(&mut bytes)));
+                    }
+                    segments.push(Segment::Placeholder { kind, position: i });
+                }
+                i = close + 1;
+            }
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    flush_text(&mut text, &mut bytes, encoding);
+    if !bytes.is_empty() {
+        segments.push(Segment::Bytes(bytes));
+    }
+
+    resolve_segments(segments)
+}
+
+enum EscapeValue {
+    Byte(u8),
+    Char(char),
+}
+
+/// Parse the escape starting at `chars[start]` (a backslash).
+/// Returns the value and the number of characters consumed.
+fn parse_escape(chars: &[char], start: usize) -> Result<(EscapeValue, usize), SendSyntaxError> {
+    let invalid = |len: usize| SendSyntaxError::InvalidEscape {
+        position: start,
+        sequence: chars[start..(start + len).min(chars.len())].iter().collect(),
+    };
+
+    let Some(&next) = chars.get(start + 1) else {
+        return Err(invalid(1));
+    };
+
+    let value = match next {
+        'r' => EscapeValue::Byte(0x0D),
+        'n' => EscapeValue::Byte(0x0A),
+        't' => EscapeValue::Byte(0x09),
+        '0' => EscapeValue::Byte(0x00),
+        '\\' | '{' | '}' => EscapeValue::Char(next),
+        'x' => {
+            let digits: Vec<char> = chars.iter().skip(start + 2).take(2).copied().collect();
+            if digits.len() < 2 || !digits.iter().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid(2 + digits.len()));
+            }
+            let value = (hex_value(digits[0]) << 4) | hex_value(digits[1]);
+            return Ok((EscapeValue::Byte(value), 4));
+        }
+        _ => return Err(invalid(2)),
+    };
+    Ok((value, 2))
+}
+
+fn flush_text(text: &mut String, bytes: &mut Vec<u8>, encoding: &TextEncoding) {
+    if text.is_empty() {
+        return;
+    }
+    bytes.extend(encode_text(text, encoding));
+    text.clear();
+}
+
+/// Resolve send panel input in any `DataFormat` into bytes
+pub fn resolve(input: &str, format: &DataFormat, encoding: &TextEncoding) -> Result<Vec<u8>, SendSyntaxError> {
+    match format {
+        DataFormat::Text => Ok(encode_text(input, encoding)),
+        DataFormat::Hex => parse_hex(input),
+        DataFormat::Escaped => parse_escaped(input, encoding),
+    }
+}
+
+/// Encode literal text with the selected text encoding
+pub fn encode_text(text: &str, encoding: &TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::Utf8 => text.as_bytes().to_vec(),
+        TextEncoding::Gbk => {
+            let (encoded, _, had_errors) = encoding_rs::GBK.encode(text);
+            if had_errors {
+                log::warn!("Some characters could not be encoded to GBK");
+            }
+            encoded.into_owned()
+        }
+    }
+}
+
+/// Decode received bytes with the selected text encoding; invalid sequences
+/// become U+FFFD
+pub fn decode_text(data: &[u8], encoding: &TextEncoding) -> String {
+    match encoding {
+        TextEncoding::Utf8 => String::from_utf8_lossy(data).into_owned(),
+        TextEncoding::Gbk => encoding_rs::GBK.decode(data).0.into_owned(),
+    }
+}
+
+/// Parse hex pairs from a char slice. `offset` is the position of `chars[0]`
+/// in the original input, used for error reporting.
+fn parse_hex_chars(chars: &[char], offset: usize) -> Result<Vec<u8>, SendSyntaxError> {
+    let mut bytes = Vec::new();
+    let mut pending: Option<(usize, u8)> = None;
+
+    for (index, &c) in chars.iter().enumerate() {
+        let position = offset + index;
+        if c.is_whitespace() {
+            continue;
+        }
+        if !c.is_ascii_hexdigit() {
+            return Err(SendSyntaxError::InvalidHexChar { position, character: c });
+        }
+        match pending.take() {
+            Some((_, high)) => bytes.push((high << 4) | hex_value(c)),
+            None => pending = Some((position, hex_value(c))),
+        }
+    }
+
+    if let Some((position, _)) = pending {
+        return Err(SendSyntaxError::UnpairedHexDigit { position });
+    }
+    Ok(bytes)
+}
+
+fn hex_value(c: char) -> u8 {
+    c.to_digit(16).unwrap_or(0) as u8
+}
+
+/// Expand placeholders now that the total frame length is known
+fn resolve_segments(segments: Vec<Segment>) -> Result<Vec<u8>, SendSyntaxError> {
+    let total_len: usize = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Bytes(bytes) => bytes.len(),
+            Segment::Placeholder { kind, .. } => kind.width(),
+        })
+        .sum();
+
+    let mut frame = Vec::with_capacity(total_len);
+    for segment in segments {
+        match segment {
+            Segment::Bytes(bytes) => frame.extend(bytes),
+            Segment::Placeholder { kind: Placeholder::Len, position } => {
+                let len = u8::try_from(total_len)
+                    .map_err(|_| SendSyntaxError::LengthOverflow { position, length: total_len })?;
+                frame.push(len);
+            }
+            Segment::Placeholder { kind: Placeholder::Xor, .. } => {
+                frame.push(frame.iter().fold(0u8, |acc, b| acc ^ b));
+            }
+            Segment::Placeholder { kind: Placeholder::Sum8, .. } => {
+                frame.push(frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)));
+            }
+            Segment::Placeholder { kind: Placeholder::Crc8, .. } => {
+                frame.push(crc8(&frame));
+            }
+            Segment::Placeholder { kind: Placeholder::Crc16, .. } => {
+                frame.extend_from_slice(&crc16_modbus(&frame).to_le_bytes());
+            }
+            Segment::Placeholder { kind: Placeholder::Crc16Ccitt, .. } => {
+                frame.extend_from_slice(&crc16_ccitt(&frame).to_be_bytes());
+            }
+        }
+    }
+    Ok(frame)
+}
+
+/// CRC-8 (polynomial 0x07, init 0x00), same as the send panel checksum
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16/MODBUS (reflected polynomial 0xA001, init 0xFFFF), sent low byte first
+pub fn crc16_modbus(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 0x0001 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, init 0xFFFF), sent high byte first
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(input: &str) -> Result<Vec<u8>, SendSyntaxError> {
+        parse_escaped(input, &TextEncoding::Utf8)
+    }
+
+    #[test]
+    fn parses_hex_with_and_without_spaces() {
+        assert_eq!(parse_hex("01 0a FF").unwrap(), vec![0x01, 0x0A, 0xFF]);
+        assert_eq!(parse_hex("010aff\n").unwrap(), vec![0x01, 0x0A, 0xFF]);
+    }
+
+    #[test]
+    fn reports_hex_error_positions() {
+        assert_eq!(
+            parse_hex("01 0G"),
+            Err(SendSyntaxError::InvalidHexChar { position: 4, character: 'G' })
+        );
+        assert_eq!(parse_hex("01 0"), Err(SendSyntaxError::UnpairedHexDigit { position: 3 }));
+    }
+
+    #[test]
+    fn resolves_escapes() {
+        assert_eq!(escaped(r"AT\r\n").unwrap(), b"AT\r\n".to_vec());
+        assert_eq!(escaped(r"\x7F\0\t\\\{").unwrap(), vec![0x7F, 0x00, 0x09, b'\\', b'{']);
+    }
+
+    #[test]
+    fn reports_invalid_escape_position() {
+        assert_eq!(
+            escaped(r"ok\q"),
+            Err(SendSyntaxError::InvalidEscape { position: 2, sequence: r"\q".to_string() })
+        );
+        assert_eq!(
+            escaped(r"\x4"),
+            Err(SendSyntaxError::InvalidEscape { position: 0, sequence: r"\x4".to_string() })
+        );
+    }
+
+    #[test]
+    fn resolves_hex_blocks_and_placeholders() {
+        assert_eq!(escaped("A{hex:01 02}B").unwrap(), vec![b'A', 0x01, 0x02, b'B']);
+        // Modbus "read holding registers" request
+        assert_eq!(
+            escaped("{hex:01 03 00 00 00 0A}{crc16}").unwrap(),
+            vec![0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]
+        );
+        assert_eq!(escaped("{hex:AA}{len}{xor}").unwrap(), vec![0xAA, 0x03, 0xA9]);
+    }
+
+    #[test]
+    fn reports_block_errors() {
+        assert_eq!(escaped("ab{hex:01"), Err(SendSyntaxError::UnterminatedBlock { position: 2 }));
+        assert_eq!(
+            escaped("{foo}"),
+            Err(SendSyntaxError::UnknownPlaceholder { position: 0, name: "foo".to_string() })
+        );
+        assert_eq!(
+            escaped("{hex:0Z}"),
+            Err(SendSyntaxError::InvalidHexChar { position: 6, character: 'Z' })
+        );
+    }
+
+    #[test]
+    fn encodes_text_runs_with_selected_encoding() {
+        let bytes = parse_escaped("中\\n", &TextEncoding::Gbk).unwrap();
+        assert_eq!(bytes, vec![0xD6, 0xD0, 0x0A]);
+    }
+}
@@ -1,36 +1,86 @@
+use crate::esp_flasher;
+use crate::export;
+use crate::firmware_image;
+use crate::log_import::{self, ImportedEntry};
+use crate::log_store::{ContextIds, LogStore, SearchHit};
+use crate::pattern;
+use crate::raw_capture;
+use crate::recording::Recorder;
+use crate::replay;
+use crate::simulator::Simulator;
+use crate::triggers::{Firing, TriggerEngine};
+use crate::stm32_flasher;
+use crate::transfer::{self, TransferControl, TransferError, TransferOutcome};
 use crate::types::*;
+use crate::xmodem;
+use crate::zmodem;
 use anyhow::{anyhow, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use log::{debug, error, info, warn};
+use serde::Serialize;
 use serialport::{SerialPort, SerialPortType};
-use std::collections::{HashSet, VecDeque};
-use std::fs::{File, OpenOptions, create_dir_all};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, create_dir_all};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
 use std::thread;
 use std::time::{Duration, Instant};
 
+/// Callback that forwards an event to the frontend (installed by main with the AppHandle)
+pub type EventEmitter = Arc<dyn Fn(&str, serde_json::Value) + Send + Sync>;
+
+/// Bytes shown on each side of a search match in its preview
+const SEARCH_PREVIEW_BYTES: usize = 32;
+
+/// Minimum time between two `replay-progress` events
+const REPLAY_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Minimum time between two `zmodem-detected` events (sz/rz repeat their headers)
+const ZMODEM_DETECT_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Work run by `spawn_transfer` on a background thread with its own port handle
+type TransferJob = Box<dyn FnOnce(&mut dyn SerialPort, &TransferControl) -> Result<TransferOutcome> + Send>;
+
 pub struct SerialManager {
     current_port: Option<Box<dyn SerialPort>>,
     config: Option<SerialConfig>,
     is_connected: bool,
     port_name: Option<String>,
     logs: Arc<Mutex<VecDeque<LogEntry>>>,
+    // Full session history; `logs` only caches the newest entries for the view
+    log_store: Arc<LogStore>,
     stats: Arc<Mutex<SerialStats>>,
     shutdown_flag: Arc<AtomicBool>,
     max_log_entries: Arc<Mutex<usize>>,
     frame_segmentation_config: Arc<Mutex<FrameSegmentationConfig>>,
-    // Recording file handles
-    text_file: Arc<Mutex<Option<File>>>,
-    raw_file: Arc<Mutex<Option<File>>>,
-    text_file_path: Arc<Mutex<Option<String>>>,
-    raw_file_path: Arc<Mutex<Option<String>>>,
+    // Text and raw recording files
+    recorder: Arc<Recorder>,
+    // Actions run on matching received frames
+    triggers: Arc<TriggerEngine>,
+    // Received frames are not logged while set (e.g. by a PauseCapture trigger)
+    capture_paused: Arc<AtomicBool>,
+    // Auto-reply rules answering received frames
+    simulator: Arc<Simulator>,
     log_directory: Arc<Mutex<String>>,
     // Timezone offset in minutes for recording timestamps
     timezone_offset_minutes: Arc<Mutex<i32>>,
     // Display settings for pre-formatted log rendering
     display_settings: Arc<Mutex<DisplaySettings>>,
+    // Frontend event sink, None until main installs it
+    event_emitter: Arc<Mutex<Option<EventEmitter>>>,
+    // Background transfer state (only one transfer may run at a time)
+    transfer_active: Arc<AtomicBool>,
+    transfer_cancel: Arc<AtomicBool>,
+    // Set while a protocol transfer owns the port; the reader thread acknowledges via reader_idle
+    reader_paused: Arc<AtomicBool>,
+    reader_idle: Arc<AtomicBool>,
+    // Set once the disconnect marker of the current connection is written,
+    // by the reader thread when the port fails or by disconnect()
+    disconnect_marked: Arc<AtomicBool>,
+    // Target of the running replay, None when no replay is running
+    replay_target: Arc<Mutex<Option<ReplayTarget>>>,
+    replay_cancel: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Default)]
@@ -48,25 +98,43 @@ impl SerialManager {
             .unwrap_or_else(|| PathBuf::from("./SerialLogs"))
             .to_string_lossy()
             .to_string();
+        let log_directory = Arc::new(Mutex::new(default_log_dir));
+        let timezone_offset_minutes = Arc::new(Mutex::new(0));
+        let display_settings = Arc::new(Mutex::new(DisplaySettings::default()));
 
-        Self {
+        let manager = Self {
             current_port: None,
             config: None,
             is_connected: false,
             port_name: None,
             logs: Arc::new(Mutex::new(VecDeque::new())),
+            log_store: Arc::new(LogStore::new(Arc::clone(&log_directory), Arc::clone(&timezone_offset_minutes))),
             stats: Arc::new(Mutex::new(SerialStats::default())),
             shutdown_flag: Arc::new(AtomicBool::new(false)),
             max_log_entries: Arc::new(Mutex::new(1000)),
             frame_segmentation_config: Arc::new(Mutex::new(FrameSegmentationConfig::default())),
-            text_file: Arc::new(Mutex::new(None)),
-            raw_file: Arc::new(Mutex::new(None)),
-            text_file_path: Arc::new(Mutex::new(None)),
-            raw_file_path: Arc::new(Mutex::new(None)),
-            log_directory: Arc::new(Mutex::new(default_log_dir)),
-            timezone_offset_minutes: Arc::new(Mutex::new(0)),
-            display_settings: Arc::new(Mutex::new(DisplaySettings::default())),
-        }
+            recorder: Arc::new(Recorder::new(
+                Arc::clone(&log_directory),
+                Arc::clone(&timezone_offset_minutes),
+                Arc::clone(&display_settings),
+            )),
+            triggers: Arc::new(TriggerEngine::default()),
+            capture_paused: Arc::new(AtomicBool::new(false)),
+            simulator: Arc::new(Simulator::default()),
+            log_directory,
+            timezone_offset_minutes,
+            display_settings,
+            event_emitter: Arc::new(Mutex::new(None)),
+            transfer_active: Arc::new(AtomicBool::new(false)),
+            transfer_cancel: Arc::new(AtomicBool::new(false)),
+            reader_paused: Arc::new(AtomicBool::new(false)),
+            reader_idle: Arc::new(AtomicBool::new(false)),
+            disconnect_marked: Arc::new(AtomicBool::new(false)),
+            replay_target: Arc::new(Mutex::new(None)),
+            replay_cancel: Arc::new(AtomicBool::new(false)),
+        };
+        manager.restore_log_rules();
+        manager
     }
 
     pub fn list_available_ports() -> Result<Vec<SerialPortInfo>> {
@@ -125,13 +193,7 @@ impl SerialManager {
                 DataBits::Seven => serialport::DataBits::Seven,
                 DataBits::Eight => serialport::DataBits::Eight,
             })
-            .parity(match config.parity {
-                Parity::None => serialport::Parity::None,
-                Parity::Odd => serialport::Parity::Odd,
-                Parity::Even => serialport::Parity::Even,
-                Parity::Mark => serialport::Parity::None,
-                Parity::Space => serialport::Parity::None,
-            })
+            .parity(serialport_parity(&config.parity))
             .stop_bits(match config.stop_bits {
                 StopBits::One => serialport::StopBits::One,
                 StopBits::OnePointFive => serialport::StopBits::One,
@@ -149,22 +211,24 @@ impl SerialManager {
 
         // Reset and start reading thread
         self.shutdown_flag.store(false, Ordering::Relaxed);
-        let logs = Arc::clone(&self.logs);
-        let stats = Arc::clone(&self.stats);
-        let max_log_entries = Arc::clone(&self.max_log_entries);
+        self.disconnect_marked.store(false, Ordering::SeqCst);
+        let mut pipeline = self.receive_pipeline(port_name, Some(port.try_clone()?));
         let frame_segmentation_config = Arc::clone(&self.frame_segmentation_config);
-        let text_file = Arc::clone(&self.text_file);
-        let raw_file = Arc::clone(&self.raw_file);
-        let timezone_offset = Arc::clone(&self.timezone_offset_minutes);
         let display_settings = Arc::clone(&self.display_settings);
         let port_name_clone = port_name.to_string();
         let shutdown_flag = Arc::clone(&self.shutdown_flag);
+        let reader_paused = Arc::clone(&self.reader_paused);
+        let reader_idle = Arc::clone(&self.reader_idle);
+        let disconnect_marked = Arc::clone(&self.disconnect_marked);
+        let event_emitter = Arc::clone(&self.event_emitter);
         let mut read_port = port.try_clone()?;
 
         thread::spawn(move || {
             let mut buffer = [0; 1024];
             let mut accumulated_data = Vec::new();
             let mut last_data_time = Instant::now();
+            let mut last_zmodem_detect: Option<Instant> = None;
+            let mut zmodem_tail = 0usize;
 
             loop {
                 // Check shutdown flag
@@ -173,6 +237,14 @@ impl SerialManager {
                     break;
                 }
 
+                // Leave the port alone while a protocol transfer owns it
+                if reader_paused.load(Ordering::SeqCst) {
+                    reader_idle.store(true, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    continue;
+                }
+                reader_idle.store(false, Ordering::SeqCst);
+
                 // Get current segmentation config
                 let seg_config = frame_segmentation_config.lock()
                     .map(|guard| guard.clone())
@@ -184,19 +256,32 @@ impl SerialManager {
                     .map(|guard| guard.clone())
                     .unwrap_or_default();
 
+                pipeline.send_due_replies(&disp_settings);
+
                 match read_port.read(&mut buffer) {
                     Ok(bytes_read) if bytes_read > 0 => {
                         let received_bytes = &buffer[..bytes_read];
-                        accumulated_data.extend_from_slice(received_bytes);
+                        // Rest of a ZMODEM header split across reads
+                        let skipped = zmodem_tail.min(received_bytes.len());
+                        zmodem_tail -= skipped;
+                        accumulated_data.extend_from_slice(&received_bytes[skipped..]);
                         last_data_time = Instant::now();
 
-                        // Write to raw recording file (raw bytes, no framing)
-                        if let Ok(mut guard) = raw_file.lock() {
-                            if let Some(ref mut file) = *guard {
-                                let _ = file.write_all(received_bytes);
+                        // A ZMODEM session start is protocol data, not log text: keep what
+                        // came before it and let the frontend offer to send or receive
+                        if let Some((pos, request)) = zmodem::detect_request(&accumulated_data) {
+                            zmodem_tail = zmodem::REQUEST_HEADER_LEN.saturating_sub(accumulated_data.len() - pos);
+                            accumulated_data.truncate(pos);
+                            if last_zmodem_detect.is_none_or(|t| t.elapsed() >= ZMODEM_DETECT_COOLDOWN) {
+                                last_zmodem_detect = Some(Instant::now());
+                                info!("ZMODEM {:?} request detected on {}", request, port_name_clone);
+                                emit_event(&event_emitter, "zmodem-detected", request);
                             }
                         }
 
+                        // Write to raw recording file (raw bytes, no framing)
+                        pipeline.recorder.write_raw(received_bytes, Direction::Received);
+
                         // Check for delimiter-based segmentation (only in Combined mode)
                         if seg_config.mode == FrameSegmentationMode::Combined {
 
@@ -205,49 +290,8 @@ impl SerialManager {
                                 while let Some((pos, len)) = find_any_newline(&accumulated_data) {
                                     let frame_end = pos + len;
                                     let frame_data: Vec<u8> = accumulated_data.drain(..frame_end).collect();
-                                    let data_len = frame_data.len();
-
-                                    // Write to text recording file with timestamp and RX label
-                                    if let Ok(mut guard) = text_file.lock() {
-                                        if let Some(ref mut file) = *guard {
-                                            let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                                            let timestamp = format_timestamp_with_offset(tz_offset);
-                                            let text = String::from_utf8_lossy(&frame_data);
-                                            let _ = writeln!(file, "[{}] RX: {}", timestamp, text);
-                                        }
-                                    }
-
-                                    // Format display text and timestamp based on current settings
-                                    let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                                    let display_text = format_data_for_display(&frame_data, &disp_settings);
-                                    let timestamp_formatted = if disp_settings.show_timestamps {
-                                        Some(format_timestamp_with_offset(tz_offset))
-                                    } else {
-                                        None
-                                    };
-
-                                    let log_entry = LogEntry {
-                                        id: None,
-                                        timestamp: Utc::now(),
-                                        direction: Direction::Received,
-                                        data: frame_data,
-                                        format: DataFormat::Text,
-                                        port_name: port_name_clone.clone(),
-                                        display_text,
-                                        timestamp_formatted,
-                                    };
-
-                                    if let Ok(mut logs_guard) = logs.lock() {
-                                        logs_guard.push_back(log_entry);
-                                        let max_entries = *max_log_entries.lock().unwrap_or_else(|e| e.into_inner());
-                                        while logs_guard.len() > max_entries {
-                                            logs_guard.pop_front();
-                                        }
-                                    }
-
-                                    if let Ok(mut stats_guard) = stats.lock() {
-                                        stats_guard.bytes_received += data_len as u64;
-                                    }
+
+                                    pipeline.handle_frame(frame_data, &disp_settings);
                                 }
                             } else {
                                 // Standard delimiter matching
@@ -257,49 +301,8 @@ impl SerialManager {
                                 while let Some(pos) = find_delimiter(&accumulated_data, &delimiter_bytes) {
                                     let frame_end = pos + delimiter_bytes.len();
                                     let frame_data: Vec<u8> = accumulated_data.drain(..frame_end).collect();
-                                    let data_len = frame_data.len();
-
-                                    // Write to text recording file with timestamp and RX label
-                                    if let Ok(mut guard) = text_file.lock() {
-                                        if let Some(ref mut file) = *guard {
-                                            let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                                            let timestamp = format_timestamp_with_offset(tz_offset);
-                                            let text = String::from_utf8_lossy(&frame_data);
-                                            let _ = writeln!(file, "[{}] RX: {}", timestamp, text);
-                                        }
-                                    }
-
-                                    // Format display text and timestamp based on current settings
-                                    let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                                    let display_text = format_data_for_display(&frame_data, &disp_settings);
-                                    let timestamp_formatted = if disp_settings.show_timestamps {
-                                        Some(format_timestamp_with_offset(tz_offset))
-                                    } else {
-                                        None
-                                    };
-
-                                    let log_entry = LogEntry {
-                                        id: None,
-                                        timestamp: Utc::now(),
-                                        direction: Direction::Received,
-                                        data: frame_data,
-                                        format: DataFormat::Text,
-                                        port_name: port_name_clone.clone(),
-                                        display_text,
-                                        timestamp_formatted,
-                                    };
-
-                                    if let Ok(mut logs_guard) = logs.lock() {
-                                        logs_guard.push_back(log_entry);
-                                        let max_entries = *max_log_entries.lock().unwrap_or_else(|e| e.into_inner());
-                                        while logs_guard.len() > max_entries {
-                                            logs_guard.pop_front();
-                                        }
-                                    }
-
-                                    if let Ok(mut stats_guard) = stats.lock() {
-                                        stats_guard.bytes_received += data_len as u64;
-                                    }
+
+                                    pipeline.handle_frame(frame_data, &disp_settings);
                                 }
                             }
                         }
@@ -313,52 +316,7 @@ impl SerialManager {
                             last_data_time.elapsed() > timeout_duration;
 
                         if should_flush_timeout {
-                            let data_len = accumulated_data.len();
-
-                            // Write to text recording file with timestamp and RX label
-                            if let Ok(mut guard) = text_file.lock() {
-                                if let Some(ref mut file) = *guard {
-                                    let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                                    let timestamp = format_timestamp_with_offset(tz_offset);
-                                    let text = String::from_utf8_lossy(&accumulated_data);
-                                    let _ = writeln!(file, "[{}] RX: {}", timestamp, text);
-                                }
-                            }
-
-                            // Format display text and timestamp based on current settings
-                            let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                            let display_text = format_data_for_display(&accumulated_data, &disp_settings);
-                            let timestamp_formatted = if disp_settings.show_timestamps {
-                                Some(format_timestamp_with_offset(tz_offset))
-                            } else {
-                                None
-                            };
-
-                            let log_entry = LogEntry {
-                                id: None,
-                                timestamp: Utc::now(),
-                                direction: Direction::Received,
-                                data: accumulated_data.clone(),
-                                format: DataFormat::Text,
-                                port_name: port_name_clone.clone(),
-                                display_text,
-                                timestamp_formatted,
-                            };
-
-                            if let Ok(mut logs_guard) = logs.lock() {
-                                logs_guard.push_back(log_entry);
-                                let max_entries = *max_log_entries.lock().unwrap_or_else(|e| e.into_inner());
-                                while logs_guard.len() > max_entries {
-                                    logs_guard.pop_front();
-                                }
-                            }
-
-                            // Update received bytes statistics
-                            if let Ok(mut stats_guard) = stats.lock() {
-                                stats_guard.bytes_received += data_len as u64;
-                            }
-
-                            accumulated_data.clear();
+                            pipeline.handle_frame(std::mem::take(&mut accumulated_data), &disp_settings);
                         }
                         thread::sleep(Duration::from_millis(1));
                     }
@@ -371,57 +329,17 @@ impl SerialManager {
                             last_data_time.elapsed() > timeout_duration;
 
                         if should_flush_timeout {
-                            let data_len = accumulated_data.len();
-
-                            // Write to text recording file with timestamp and RX label
-                            if let Ok(mut guard) = text_file.lock() {
-                                if let Some(ref mut file) = *guard {
-                                    let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                                    let timestamp = format_timestamp_with_offset(tz_offset);
-                                    let text = String::from_utf8_lossy(&accumulated_data);
-                                    let _ = writeln!(file, "[{}] RX: {}", timestamp, text);
-                                }
-                            }
-
-                            // Format display text and timestamp based on current settings
-                            let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
-                            let display_text = format_data_for_display(&accumulated_data, &disp_settings);
-                            let timestamp_formatted = if disp_settings.show_timestamps {
-                                Some(format_timestamp_with_offset(tz_offset))
-                            } else {
-                                None
-                            };
-
-                            let log_entry = LogEntry {
-                                id: None,
-                                timestamp: Utc::now(),
-                                direction: Direction::Received,
-                                data: accumulated_data.clone(),
-                                format: DataFormat::Text,
-                                port_name: port_name_clone.clone(),
-                                display_text,
-                                timestamp_formatted,
-                            };
-
-                            if let Ok(mut logs_guard) = logs.lock() {
-                                logs_guard.push_back(log_entry);
-                                let max_entries = *max_log_entries.lock().unwrap_or_else(|e| e.into_inner());
-                                while logs_guard.len() > max_entries {
-                                    logs_guard.pop_front();
-                                }
-                            }
-
-                            // Update received bytes statistics
-                            if let Ok(mut stats_guard) = stats.lock() {
-                                stats_guard.bytes_received += data_len as u64;
-                            }
-
-                            accumulated_data.clear();
+                            pipeline.handle_frame(std::mem::take(&mut accumulated_data), &disp_settings);
                         }
                         thread::sleep(Duration::from_millis(1));
                     }
                     Err(e) => {
                         error!("Error reading from serial port: {}", e);
+                        // Mark the recordings when the device went away (e.g. a
+                        // board reset), not when the user gets to disconnect
+                        if !shutdown_flag.load(Ordering::Relaxed) && !disconnect_marked.swap(true, Ordering::SeqCst) {
+                            pipeline.recorder.mark(&format!("Disconnected from {}", port_name_clone));
+                        }
                         break;
                     }
                 }
@@ -439,18 +357,31 @@ impl SerialManager {
         }
 
         // Don't add connection log to reduce clutter
-        info!("Connected to {} at {} baud", port_name, self.config.as_ref().unwrap().baud_rate);
+        let marker = format!("Connected to {} at {} baud", port_name, self.config.as_ref().unwrap().baud_rate);
+        info!("{}", marker);
+
+        if let Err(e) = self.recorder.connected(&device_key(port_name), port_name, &marker) {
+            warn!("Failed to start auto recording: {}", e);
+        }
+        emit_event(&self.event_emitter, "recording-status", self.recorder.status());
 
         Ok(())
     }
 
     pub fn disconnect(&mut self) -> Result<()> {
         if self.is_connected {
-            // Signal reading thread to stop
+            // Signal reading thread and any running transfer to stop
             self.shutdown_flag.store(true, Ordering::Relaxed);
+            self.cancel_transfer();
+            self.cancel_port_replay();
 
-            // Stop all recordings before disconnecting
-            self.stop_all_recordings();
+            // Stop recordings before disconnecting, unless auto recording keeps them
+            // open for the device to come back
+            let port_name = self.port_name.clone().unwrap_or_default();
+            let marker = format!("Disconnected from {}", port_name);
+            let marked = self.disconnect_marked.swap(true, Ordering::SeqCst);
+            self.recorder.disconnected((!marked).then_some(marker.as_str()));
+            emit_event(&self.event_emitter, "recording-status", self.recorder.status());
 
             // Close the port first to force the reading thread to exit
             self.current_port = None;
@@ -482,13 +413,16 @@ impl SerialManager {
         if !self.is_connected {
             return Err(anyhow!("No port is currently open"));
         }
+        if self.port_busy() {
+            return Err(anyhow!("Port is busy with a file transfer"));
+        }
 
         if let Some(ref mut port) = self.current_port {
             port.write_all(&data)?;
 
             // Write to recording files (TX data)
-            self.write_to_text_file(&data, Direction::Sent);
-            self.write_to_raw_file(&data);
+            self.recorder.write_text(&data, Direction::Sent);
+            self.recorder.write_raw(&data, Direction::Sent);
 
             // Update sent bytes statistics
             if let Ok(mut stats_guard) = self.stats.lock() {
@@ -515,6 +449,8 @@ impl SerialManager {
                 port_name: self.port_name.clone().unwrap_or_default(),
                 display_text,
                 timestamp_formatted,
+                hidden: false,
+                highlights: Vec::new(),
             });
 
             Ok(())
@@ -548,16 +484,406 @@ impl SerialManager {
         }
     }
 
+    /// Clear the log view and start a new session database; earlier session
+    /// files stay on disk
     pub fn clear_logs(&mut self) {
         if let Ok(mut logs) = self.logs.lock() {
             logs.clear();
         }
+        self.log_store.start_new_session();
+    }
+
+    /// Replace the log view with the entries of an export or text recording,
+    /// starting a new session so they can be searched and paged like captured
+    /// data. Exported bookmarks are restored. `timezone_offset_minutes` is the
+    /// offset the file's local timestamps were written with.
+    pub fn import_logs(&mut self, file_path: &str, timezone_offset_minutes: i32) -> Result<LogImportSummary> {
+        let imported = log_import::load_entries(Path::new(file_path), timezone_offset_minutes)?;
+        self.clear_logs();
+
+        let settings = self.get_display_settings();
+        let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+        let entry_count = imported.len();
+        let mut bookmark_count = 0;
+        for ImportedEntry { mut entry, bookmark } in imported {
+            entry.id = None;
+            render_log_entry(&mut entry, &settings, tz_offset);
+            let marked = bookmark.map(|note| (entry.clone(), note));
+            let id = push_log_entry(&self.logs, &self.max_log_entries, &self.log_store, entry);
+            if let Some((mut entry, note)) = marked {
+                entry.id = id;
+                match self.log_store.add_bookmark(&entry, note) {
+                    Ok(_) => bookmark_count += 1,
+                    Err(e) => warn!("Failed to restore bookmark: {}", e),
+                }
+            }
+        }
+
+        info!("Imported {} log entries from {}", entry_count, file_path);
+        Ok(LogImportSummary {
+            file_name: file_display_name(file_path),
+            entry_count,
+            bookmark_count,
+        })
+    }
+
+    /// Page through the session history beyond the in-memory buffer. Without
+    /// ids the newest entries are returned; entries are rendered with the
+    /// current display settings.
+    pub fn get_log_page(&self, before_id: Option<i64>, after_id: Option<i64>, limit: usize) -> Result<LogPage> {
+        let (mut entries, has_more) = self.log_store.page(before_id, after_id, limit)?;
+        let disp_settings = self.get_display_settings();
+        let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+        for entry in entries.iter_mut() {
+            render_log_entry(entry, &disp_settings, tz_offset);
+        }
+        Ok(LogPage {
+            entries,
+            has_more,
+            total_count: self.log_store.count()?,
+        })
+    }
+
+    /// Search the session history. Falls back to the in-memory buffer when no
+    /// session database is available.
+    pub fn search_logs(&self, query: SearchQuery) -> Result<SearchResult> {
+        let disp_settings = self.get_display_settings();
+        let pattern = pattern::compile(&query.pattern, &query.mode, query.case_sensitive, &disp_settings.encoding)?;
+        let limit = query.max_results.clamp(1, 10000);
+        let context = query.context.min(50);
+
+        let (hits, truncated, neighbours) = match self.log_store.search(&pattern, &query, limit)? {
+            Some((hits, truncated)) => {
+                let neighbours = hits
+                    .iter()
+                    .map(|hit| self.log_store.neighbour_ids(hit.entry.id.unwrap_or_default(), context))
+                    .collect::<Result<Vec<_>>>()?;
+                (hits, truncated, neighbours)
+            }
+            None => self.search_buffered_logs(&pattern, &query, limit, context),
+        };
+
+        let matches = hits
+            .into_iter()
+            .zip(neighbours)
+            .map(|(hit, (context_before, context_after))| {
+                let preview_start = hit.start.saturating_sub(SEARCH_PREVIEW_BYTES);
+                let preview_end = (hit.end + SEARCH_PREVIEW_BYTES).min(hit.entry.data.len());
+                SearchMatch {
+                    id: hit.entry.id.unwrap_or_default(),
+                    timestamp: hit.entry.timestamp,
+                    preview: format_data_for_display(&hit.entry.data[preview_start..preview_end], &disp_settings),
+                    direction: hit.entry.direction,
+                    match_start: hit.start,
+                    match_end: hit.end,
+                    context_before,
+                    context_after,
+                }
+            })
+            .collect();
+        Ok(SearchResult { matches, truncated })
+    }
+
+    fn search_buffered_logs(
+        &self,
+        pattern: &regex::bytes::Regex,
+        query: &SearchQuery,
+        limit: usize,
+        context: usize,
+    ) -> (Vec<SearchHit>, bool, Vec<ContextIds>) {
+        let logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+        let ids: Vec<i64> = logs.iter().map(|entry| entry.id.unwrap_or_default()).collect();
+        let positions: Box<dyn Iterator<Item = usize>> = if query.newest_first {
+            Box::new((0..logs.len()).rev())
+        } else {
+            Box::new(0..logs.len())
+        };
+
+        let mut hits = Vec::new();
+        let mut neighbours = Vec::new();
+        let mut truncated = false;
+        for index in positions {
+            let entry = &logs[index];
+            if query.direction.as_ref().is_some_and(|d| *d != entry.direction)
+                || query.start_time.is_some_and(|start| entry.timestamp < start)
+                || query.end_time.is_some_and(|end| entry.timestamp > end)
+            {
+                continue;
+            }
+            let Some(found) = pattern.find(&entry.data) else {
+                continue;
+            };
+            if hits.len() == limit {
+                truncated = true;
+                break;
+            }
+            hits.push(SearchHit { entry: entry.clone(), start: found.start(), end: found.end() });
+            neighbours.push((
+                ids[index.saturating_sub(context)..index].to_vec(),
+                ids[(index + 1).min(ids.len())..(index + 1 + context).min(ids.len())].to_vec(),
+            ));
+        }
+        (hits, truncated, neighbours)
+    }
+
+    /// Replace the filter and highlight rules applied to new entries. They are
+    /// saved in the session database.
+    pub fn set_log_rules(&self, rules: Vec<LogRule>) -> Result<()> {
+        let encoding = self.get_display_settings().encoding;
+        self.log_store.set_rules(rules, &encoding)
     }
 
-    pub fn export_logs(&self, file_path: &str, format: ExportFormat, timezone_offset_minutes: i32) -> Result<()> {
-        use std::fs::File;
-        use std::io::Write;
-        use std::path::Path;
+    pub fn get_log_rules(&self) -> Vec<LogRule> {
+        self.log_store.rules()
+    }
+
+    /// Pick up the rules saved with the last session in the log directory
+    fn restore_log_rules(&self) {
+        let encoding = self.get_display_settings().encoding;
+        if let Err(e) = self.log_store.restore_rules(&encoding) {
+            warn!("Failed to restore log rules: {}", e);
+        }
+    }
+
+    // Session replay methods
+
+    /// Replay an export or text recording in the background, keeping the
+    /// recorded gaps between entries (see `ReplayOptions`).
+    /// Progress is reported via `replay-progress` and `replay-finished` events.
+    /// Returns the number of entries that will be replayed.
+    pub fn start_replay(&self, path: &str, options: ReplayOptions) -> Result<usize> {
+        if !(options.speed.is_finite() && options.speed >= 0.0) {
+            return Err(anyhow!("Replay speed must be zero or positive"));
+        }
+        let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries: Vec<LogEntry> = log_import::load_entries(Path::new(path), tz_offset)?
+            .into_iter()
+            .map(|imported| imported.entry)
+            .collect();
+
+        let port = match options.target {
+            ReplayTarget::LogView => None,
+            ReplayTarget::Port => {
+                // Received data comes from the device itself
+                entries.retain(|entry| entry.direction == Direction::Sent);
+                if entries.is_empty() {
+                    return Err(anyhow!("{} contains no sent data to replay", path));
+                }
+                if self.port_busy() {
+                    return Err(anyhow!("Port is busy with a file transfer"));
+                }
+                let port = self.current_port.as_ref().ok_or_else(|| anyhow!("No port is currently open"))?;
+                Some(port.try_clone()?)
+            }
+        };
+
+        {
+            let mut active = self.replay_target.lock().unwrap_or_else(|e| e.into_inner());
+            if active.is_some() {
+                return Err(anyhow!("A replay is already running"));
+            }
+            *active = Some(options.target.clone());
+        }
+        self.replay_cancel.store(false, Ordering::SeqCst);
+
+        let offsets = replay::schedule(&entries, options.speed, options.max_gap_ms.map(Duration::from_millis));
+        let total = entries.len();
+        let file_name = file_display_name(path);
+        let target = options.target;
+        let mut pipeline = self.receive_pipeline(&self.port_name.clone().unwrap_or_default(), port);
+        let display_settings = Arc::clone(&self.display_settings);
+        let replay_target = Arc::clone(&self.replay_target);
+        let cancel = Arc::clone(&self.replay_cancel);
+        let reader_paused = Arc::clone(&self.reader_paused);
+        let transfer_active = Arc::clone(&self.transfer_active);
+
+        info!("Starting replay of {} ({} entries) to {:?}", file_name, total, target);
+
+        thread::spawn(move || {
+            let started = Instant::now();
+            let mut last_progress = Instant::now();
+            let mut replayed = 0;
+            let mut error = None;
+
+            for (entry, offset) in entries.into_iter().zip(offsets) {
+                if !wait_until(started + offset, &cancel) {
+                    break;
+                }
+                // A transfer started meanwhile owns the port; writing into its
+                // stream would corrupt it
+                if target == ReplayTarget::Port
+                    && (reader_paused.load(Ordering::SeqCst) || transfer_active.load(Ordering::SeqCst))
+                {
+                    error = Some("Port is busy with a file transfer".to_string());
+                    break;
+                }
+                let settings = display_settings.lock()
+                    .map(|guard| guard.clone())
+                    .unwrap_or_default();
+                if let Err(e) = pipeline.replay(entry, &target, &settings) {
+                    error = Some(e.to_string());
+                    break;
+                }
+                replayed += 1;
+
+                if last_progress.elapsed() >= REPLAY_PROGRESS_INTERVAL {
+                    last_progress = Instant::now();
+                    emit_event(&pipeline.event_emitter, "replay-progress", ReplayProgress {
+                        file_name: file_name.clone(),
+                        replayed,
+                        total,
+                    });
+                }
+            }
+
+            let cancelled = error.is_none() && replayed < total;
+            match &error {
+                Some(e) => warn!("Replay of {} failed after {} entries: {}", file_name, replayed, e),
+                None => info!("Replay of {} ended after {} of {} entries", file_name, replayed, total),
+            }
+            *replay_target.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            emit_event(&pipeline.event_emitter, "replay-finished", ReplayResult {
+                file_name,
+                target,
+                replayed,
+                total,
+                success: error.is_none() && !cancelled,
+                cancelled,
+                error,
+            });
+        });
+
+        Ok(total)
+    }
+
+    /// Stop the running replay (no-op if none is running)
+    pub fn stop_replay(&self) {
+        if self.get_active_replay().is_some() {
+            self.replay_cancel.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Target of the running replay, None when idle
+    pub fn get_active_replay(&self) -> Option<ReplayTarget> {
+        self.replay_target.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// A replay into the port can't outlive the connection
+    fn cancel_port_replay(&self) {
+        if self.get_active_replay() == Some(ReplayTarget::Port) {
+            self.replay_cancel.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Bookmark methods
+
+    /// Bookmark a log entry with a note; re-marking an entry replaces its note
+    pub fn add_bookmark(&self, entry_id: i64, note: String) -> Result<Bookmark> {
+        let buffered = self.logs.lock()
+            .map(|logs| logs.iter().rev().find(|entry| entry.id == Some(entry_id)).cloned())
+            .unwrap_or(None);
+        let entry = match buffered {
+            Some(entry) => entry,
+            None => {
+                let mut entry = self.log_store.get(entry_id)?
+                    .ok_or_else(|| anyhow!("Log entry {} not found", entry_id))?;
+                let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+                render_log_entry(&mut entry, &self.get_display_settings(), tz_offset);
+                entry
+            }
+        };
+        self.log_store.add_bookmark(&entry, note)
+    }
+
+    pub fn update_bookmark(&self, entry_id: i64, note: String) -> Result<Bookmark> {
+        self.log_store.update_bookmark(entry_id, note)
+            .ok_or_else(|| anyhow!("Log entry {} is not bookmarked", entry_id))
+    }
+
+    pub fn remove_bookmark(&self, entry_id: i64) -> Result<()> {
+        if self.log_store.remove_bookmark(entry_id) {
+            Ok(())
+        } else {
+            Err(anyhow!("Log entry {} is not bookmarked", entry_id))
+        }
+    }
+
+    pub fn get_bookmarks(&self) -> Vec<Bookmark> {
+        self.log_store.bookmarks()
+    }
+
+    /// Next bookmark after `from_id`, or the previous one when `backwards`
+    pub fn find_bookmark(&self, from_id: Option<i64>, backwards: bool) -> Option<Bookmark> {
+        self.log_store.adjacent_bookmark(from_id, backwards)
+    }
+
+    // Trigger methods
+
+    /// Replace the triggers evaluated on received frames; counters restart
+    pub fn set_triggers(&self, triggers: Vec<Trigger>) -> Result<()> {
+        let encoding = self.get_display_settings().encoding;
+        self.triggers.set_triggers(triggers, &encoding)
+    }
+
+    pub fn get_triggers(&self) -> Vec<Trigger> {
+        self.triggers.triggers()
+    }
+
+    pub fn get_trigger_statuses(&self) -> Vec<TriggerStatus> {
+        self.triggers.statuses()
+    }
+
+    pub fn reset_trigger_counters(&self) {
+        self.triggers.reset_counters();
+    }
+
+    /// Pause or resume logging of received frames
+    pub fn set_capture_paused(&self, paused: bool) {
+        self.capture_paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_capture_paused(&self) -> bool {
+        self.capture_paused.load(Ordering::Relaxed)
+    }
+
+    // Device simulator methods
+
+    /// Replace the auto-reply rules; the simulator restarts in its initial state
+    pub fn set_simulator_config(&self, config: SimulatorConfig) -> Result<()> {
+        let encoding = self.get_display_settings().encoding;
+        self.simulator.configure(config, &encoding)
+    }
+
+    pub fn get_simulator_config(&self) -> SimulatorConfig {
+        self.simulator.config()
+    }
+
+    pub fn get_simulator_status(&self) -> SimulatorStatus {
+        self.simulator.status()
+    }
+
+    pub fn reset_simulator(&self) {
+        self.simulator.reset();
+    }
+
+    /// Location and size of the current session database
+    pub fn get_log_session_info(&self) -> Result<LogSessionInfo> {
+        Ok(LogSessionInfo {
+            path: self.log_store.session_path().map(|p| p.to_string_lossy().to_string()),
+            entry_count: self.log_store.count()?,
+        })
+    }
+
+    /// Export the buffered log entries, or with a filter the matching entries
+    /// of the whole session history; see `export::write_logs`
+    pub fn export_logs(
+        &self,
+        file_path: &str,
+        format: ExportFormat,
+        options: ExportOptions,
+        filter: Option<ExportFilter>,
+        timezone_offset_minutes: i32,
+    ) -> Result<()> {
         use chrono::FixedOffset;
 
         // Ensure the parent directory exists
@@ -568,66 +894,85 @@ impl SerialManager {
             }
         }
 
-        let logs = self.get_logs();
-        let mut file = File::create(file_path)?;
+        let logs = match filter {
+            Some(filter) => self.filtered_logs(&filter)?,
+            None => self.get_logs(),
+        };
+        let bookmarks: HashMap<i64, String> = self.log_store.bookmarks()
+            .into_iter()
+            .map(|bookmark| (bookmark.entry_id, bookmark.note))
+            .collect();
 
         // Create timezone offset for formatting
         let offset_seconds = timezone_offset_minutes * 60;
         let tz_offset = FixedOffset::east_opt(offset_seconds).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
 
-        match format {
-            ExportFormat::Txt => {
-                let now_with_tz = Utc::now().with_timezone(&tz_offset);
-                writeln!(file, "RSerial Debug Assistant - Log Export")?;
-                writeln!(file, "Generated: {}", now_with_tz.format("%Y-%m-%d %H:%M:%S %z"))?;
-                writeln!(file, "{}", "=".repeat(60))?;
-                writeln!(file)?;
-
-                for log in logs {
-                    let timestamp_with_tz = log.timestamp.with_timezone(&tz_offset);
-                    writeln!(
-                        file,
-                        "[{}] {}: {}",
-                        timestamp_with_tz.format("%H:%M:%S%.3f"),
-                        match log.direction {
-                            Direction::Sent => "TX",
-                            Direction::Received => "RX",
-                        },
-                        String::from_utf8_lossy(&log.data)
-                    )?;
-                }
+        let file = std::io::BufWriter::new(File::create(file_path)?);
+        export::write_logs(file, &logs, &bookmarks, &format, &options, &self.get_display_settings(), &tz_offset)
+    }
+
+    /// Convert a raw capture (`.rsrec`) to an export format, one entry per
+    /// recorded chunk. Returns the number of entries written.
+    pub fn convert_raw_capture(
+        &self,
+        file_path: &str,
+        output_path: &str,
+        format: ExportFormat,
+        options: ExportOptions,
+        timezone_offset_minutes: i32,
+    ) -> Result<usize> {
+        let mut logs = raw_capture::to_entries(raw_capture::read_records(Path::new(file_path))?);
+        if logs.is_empty() {
+            return Err(anyhow!("{} contains no records", file_path));
+        }
+        let settings = self.get_display_settings();
+        for entry in logs.iter_mut() {
+            render_log_entry(entry, &settings, timezone_offset_minutes);
+        }
+
+        if let Some(parent) = Path::new(output_path).parent() {
+            if !parent.exists() {
+                create_dir_all(parent)?;
             }
-            ExportFormat::Csv => {
-                writeln!(file, "timestamp,direction,port,data")?;
-                for log in logs {
-                    let timestamp_with_tz = log.timestamp.with_timezone(&tz_offset);
-                    writeln!(
-                        file,
-                        "{},{:?},{},\"{}\"",
-                        timestamp_with_tz.format("%Y-%m-%d %H:%M:%S%.3f"),
-                        log.direction,
-                        log.port_name,
-                        String::from_utf8_lossy(&log.data).replace("\"", "\"\"")
-                    )?;
+        }
+        let tz_offset = chrono::FixedOffset::east_opt(timezone_offset_minutes * 60)
+            .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap());
+        let file = std::io::BufWriter::new(File::create(output_path)?);
+        export::write_logs(file, &logs, &HashMap::new(), &format, &options, &settings, &tz_offset)?;
+        info!("Converted {} records from {} to {}", logs.len(), file_path, output_path);
+        Ok(logs.len())
+    }
+
+    /// Session entries passing `filter`, rendered with the current display
+    /// settings; the buffer is used when there is no session database
+    fn filtered_logs(&self, filter: &ExportFilter) -> Result<Vec<LogEntry>> {
+        let disp_settings = self.get_display_settings();
+        let pattern = if filter.pattern.is_empty() {
+            None
+        } else {
+            Some(pattern::compile(&filter.pattern, &filter.mode, filter.case_sensitive, &disp_settings.encoding)?)
+        };
+
+        let mut logs = match self.log_store.select(filter)? {
+            Some(mut entries) => {
+                let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+                for entry in entries.iter_mut() {
+                    render_log_entry(entry, &disp_settings, tz_offset);
                 }
+                entries
             }
-            ExportFormat::Json => {
-                let json_data = serde_json::to_string_pretty(&logs)?;
-                file.write_all(json_data.as_bytes())?;
-            }
-        }
+            None => self.get_logs(),
+        };
+        logs.retain(|entry| export::filter_matches(filter, pattern.as_ref(), entry));
 
-        Ok(())
+        if logs.is_empty() {
+            return Err(anyhow!("No log entries match the export filter"));
+        }
+        Ok(logs)
     }
 
     fn add_log(&mut self, log_entry: LogEntry) {
-        if let Ok(mut logs) = self.logs.lock() {
-            logs.push_back(log_entry);
-            let max_entries = *self.max_log_entries.lock().unwrap_or_else(|e| e.into_inner());
-            while logs.len() > max_entries {
-                logs.pop_front();
-            }
-        }
+        push_log_entry(&self.logs, &self.max_log_entries, &self.log_store, log_entry);
     }
 
     pub fn set_max_log_entries(&self, max_entries: usize) {
@@ -675,6 +1020,16 @@ impl SerialManager {
 
     /// Set the text encoding (UTF-8 or GBK)
     pub fn set_text_encoding(&self, encoding: TextEncoding) {
+        // Text patterns are encoded, so recompile rules and triggers for the new encoding
+        if let Err(e) = self.log_store.set_rules(self.log_store.rules(), &encoding) {
+            warn!("Failed to recompile log rules: {}", e);
+        }
+        if let Err(e) = self.triggers.set_triggers(self.triggers.triggers(), &encoding) {
+            warn!("Failed to recompile triggers: {}", e);
+        }
+        if let Err(e) = self.simulator.configure(self.simulator.config(), &encoding) {
+            warn!("Failed to recompile simulator rules: {}", e);
+        }
         if let Ok(mut guard) = self.display_settings.lock() {
             guard.encoding = encoding;
         }
@@ -702,12 +1057,31 @@ impl SerialManager {
             .unwrap_or_default()
     }
 
+    /// Re-render display text and timestamps of all buffered entries from their
+    /// stored bytes, so display setting changes also apply to existing data
+    pub fn reformat_logs(&self) {
+        let disp_settings = self.get_display_settings();
+        let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Ok(mut logs) = self.logs.lock() {
+            for entry in logs.iter_mut() {
+                render_log_entry(entry, &disp_settings, tz_offset);
+            }
+        }
+    }
+
     // Recording methods
 
     /// Set the log directory path (called from frontend settings)
     pub fn set_log_directory(&self, path: String) {
-        if let Ok(mut guard) = self.log_directory.lock() {
-            *guard = path;
+        let changed = match self.log_directory.lock() {
+            Ok(mut guard) if *guard != path => {
+                *guard = path;
+                true
+            }
+            _ => false,
+        };
+        if changed {
+            self.restore_log_rules();
         }
     }
 
@@ -728,175 +1102,734 @@ impl SerialManager {
 
     /// Generate a filename with port name and timestamp
     fn generate_recording_filename(&self, extension: &str) -> Result<PathBuf> {
-        let log_dir = self.get_log_directory();
-        let dir_path = PathBuf::from(&log_dir);
+        let port_name = self.port_name.clone().unwrap_or_else(|| "UNKNOWN".to_string());
+        self.recorder.generate_recording_filename(&port_name, extension, None)
+    }
 
-        // Create directory if it doesn't exist
-        if !dir_path.exists() {
-            create_dir_all(&dir_path)?;
-        }
+    /// Start text recording - creates a new text file and begins recording
+    pub fn start_text_recording(&self) -> Result<String> {
+        self.recorder.start_text(self.port_name.as_deref().unwrap_or("UNKNOWN"))
+    }
 
-        let port_name = self.port_name.clone().unwrap_or_else(|| "UNKNOWN".to_string());
-        // Sanitize port name for filename (replace special characters)
-        let safe_port_name = port_name.replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_");
+    /// Stop text recording - closes the file
+    pub fn stop_text_recording(&self) -> Result<()> {
+        self.recorder.stop_text()
+    }
 
-        let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
-        let timestamp = format_date_for_filename_with_offset(tz_offset);
-        let filename = format!("{}_{}.{}", safe_port_name, timestamp, extension);
+    /// Start raw binary recording - creates a new binary file and begins recording
+    pub fn start_raw_recording(&self) -> Result<String> {
+        self.recorder.start_raw(self.port_name.as_deref().unwrap_or("UNKNOWN"))
+    }
+
+    /// Stop raw binary recording - closes the file
+    pub fn stop_raw_recording(&self) -> Result<()> {
+        self.recorder.stop_raw()
+    }
 
-        Ok(dir_path.join(filename))
+    /// Choose what text recordings write for sent and received data
+    pub fn set_text_recording_content(&self, content: TextRecordingContent) {
+        self.recorder.set_text_content(content);
     }
 
-    /// Start text recording - creates a new text file and begins recording
-    pub fn start_text_recording(&self) -> Result<String> {
-        // Check if already recording
-        if let Ok(guard) = self.text_file.lock() {
-            if guard.is_some() {
-                return Err(anyhow!("Text recording is already active"));
-            }
-        }
+    pub fn get_text_recording_content(&self) -> TextRecordingContent {
+        self.recorder.text_content()
+    }
 
-        let file_path = self.generate_recording_filename("txt")?;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)?;
+    /// Choose how raw recordings started from now on store each direction
+    pub fn set_raw_recording_mode(&self, mode: RawRecordingMode) {
+        self.recorder.set_raw_mode(mode);
+    }
+
+    pub fn get_raw_recording_mode(&self) -> RawRecordingMode {
+        self.recorder.raw_mode()
+    }
 
-        let path_str = file_path.to_string_lossy().to_string();
+    /// Set when recordings move on to a new file, compression and the size cap
+    pub fn set_recording_rotation(&self, rotation: RecordingRotation) {
+        self.recorder.set_rotation(rotation);
+    }
+
+    pub fn get_recording_rotation(&self) -> RecordingRotation {
+        self.recorder.rotation()
+    }
 
-        if let Ok(mut guard) = self.text_file.lock() {
-            *guard = Some(file);
+    /// Get the current recording status
+    pub fn get_recording_status(&self) -> RecordingStatus {
+        self.recorder.status()
+    }
+
+    /// Start recordings on connect and keep them open across reconnects
+    pub fn set_auto_record(&self, settings: AutoRecordSettings) {
+        self.recorder.set_auto_record(settings);
+    }
+
+    pub fn get_auto_record(&self) -> AutoRecordSettings {
+        self.recorder.auto_record()
+    }
+
+    // Event and transfer methods
+
+    /// Install the callback used to push events to the frontend
+    pub fn set_event_emitter(&self, emitter: EventEmitter) {
+        if let Ok(mut guard) = self.event_emitter.lock() {
+            *guard = Some(emitter);
         }
-        if let Ok(mut guard) = self.text_file_path.lock() {
-            *guard = Some(path_str.clone());
+    }
+
+    /// Whether a background transfer is currently running
+    pub fn is_transfer_active(&self) -> bool {
+        self.transfer_active.load(Ordering::SeqCst)
+    }
+
+    /// Whether a transfer owns the port, so other writes would end up in its stream
+    fn port_busy(&self) -> bool {
+        self.reader_paused.load(Ordering::SeqCst) || self.is_transfer_active()
+    }
+
+    /// Request cancellation of the running transfer (no-op if none is running)
+    pub fn cancel_transfer(&self) {
+        if self.is_transfer_active() {
+            self.transfer_cancel.store(true, Ordering::SeqCst);
         }
+    }
 
-        info!("Started text recording to: {}", path_str);
-        Ok(path_str)
+    /// Stream a file through the open port in chunks without loading it into memory.
+    /// Progress is reported via `transfer-progress` events and a single summary
+    /// entry is added to the log when the transfer ends. Chunks go to the raw
+    /// recording and byte statistics as they are written.
+    /// With `decode_image`, Intel HEX and S-record files are sent as the binary
+    /// image they describe (gaps filled with 0xFF) instead of as text.
+    pub fn send_file(&self, path: &str, chunk_size: usize, inter_chunk_delay_ms: u64, decode_image: bool) -> Result<()> {
+        let (mut source, total, file_name) = open_send_source(path, decode_image)?;
+        let delay = Duration::from_millis(inter_chunk_delay_ms);
+        let recorder = Arc::clone(&self.recorder);
+        let stats = Arc::clone(&self.stats);
+
+        self.spawn_transfer(
+            TransferProtocol::Raw,
+            file_name,
+            Direction::Sent,
+            total,
+            false,
+            Box::new(move |port, control| {
+                let bytes = transfer::send_chunks(&mut source, port, total, chunk_size, delay, control, |chunk| {
+                    recorder.write_raw(chunk, Direction::Sent);
+                    if let Ok(mut stats_guard) = stats.lock() {
+                        stats_guard.bytes_sent += chunk.len() as u64;
+                    }
+                })?;
+                Ok(TransferOutcome { bytes, detail: None })
+            }),
+        )
     }
 
-    /// Stop text recording - closes the file
-    pub fn stop_text_recording(&self) -> Result<()> {
-        if let Ok(mut guard) = self.text_file.lock() {
-            if let Some(mut file) = guard.take() {
-                file.flush()?;
+    /// Send a file with XMODEM, XMODEM-1K, YMODEM or ZMODEM. The reader thread is paused
+    /// for the duration so protocol responses are not consumed as log data.
+    /// `decode_image` works as for `send_file`.
+    pub fn send_file_with_protocol(&self, path: &str, protocol: TransferProtocol, decode_image: bool) -> Result<()> {
+        let (mut file, total, file_name) = open_send_source(path, decode_image)?;
+        let header_name = file_name.clone();
+
+        let job: TransferJob = match protocol {
+            TransferProtocol::Xmodem | TransferProtocol::Xmodem1k => {
+                let use_1k = protocol == TransferProtocol::Xmodem1k;
+                Box::new(move |port, control| {
+                    let bytes = xmodem::send_xmodem(port, &mut file, total, use_1k, control)?;
+                    Ok(TransferOutcome { bytes, detail: None })
+                })
             }
-        }
-        if let Ok(mut guard) = self.text_file_path.lock() {
-            if let Some(path) = guard.take() {
-                info!("Stopped text recording: {}", path);
+            TransferProtocol::Ymodem => Box::new(move |port, control| {
+                let bytes = xmodem::send_ymodem(port, &header_name, &mut file, total, control)?;
+                Ok(TransferOutcome { bytes, detail: None })
+            }),
+            TransferProtocol::Zmodem => Box::new(move |port, control| {
+                let bytes = zmodem::send_zmodem(port, &header_name, &mut file, total, control)?;
+                Ok(TransferOutcome { bytes, detail: None })
+            }),
+            TransferProtocol::Raw => return Err(anyhow!("Use send_file for raw transfers")),
+            TransferProtocol::Stm32Bootloader => return Err(anyhow!("Use flash_stm32 for STM32 bootloader flashing")),
+            TransferProtocol::EspRomLoader => return Err(anyhow!("Use flash_esp for ESP ROM loader flashing")),
+        };
+
+        self.spawn_transfer(protocol, file_name, Direction::Sent, total, true, job)
+    }
+
+    /// Receive a file with XMODEM, XMODEM-1K, YMODEM or ZMODEM. XMODEM data is written
+    /// to `save_path` (or a new file in the log directory); YMODEM and ZMODEM files keep
+    /// their sender-supplied names inside `save_path` (or the log directory).
+    pub fn receive_file_with_protocol(&self, protocol: TransferProtocol, save_path: Option<String>) -> Result<()> {
+        let job: TransferJob = match protocol {
+            TransferProtocol::Xmodem | TransferProtocol::Xmodem1k => {
+                let path = match save_path {
+                    Some(path) => PathBuf::from(path),
+                    None => self.generate_recording_filename("xmodem.bin")?,
+                };
+                let mut file = File::create(&path)?;
+                Box::new(move |port, control| {
+                    let bytes = xmodem::receive_xmodem(port, &mut file, control)?;
+                    Ok(TransferOutcome { bytes, detail: Some(path.to_string_lossy().to_string()) })
+                })
+            }
+            TransferProtocol::Ymodem | TransferProtocol::Zmodem => {
+                let dir = PathBuf::from(save_path.unwrap_or_else(|| self.get_log_directory()));
+                create_dir_all(&dir)?;
+                let is_zmodem = protocol == TransferProtocol::Zmodem;
+                Box::new(move |port, control| {
+                    let files = if is_zmodem {
+                        zmodem::receive_zmodem(port, &dir, control)?
+                    } else {
+                        xmodem::receive_ymodem(port, &dir, control)?
+                    };
+                    let names: Vec<String> = files.iter()
+                        .map(|f| f.path.to_string_lossy().to_string())
+                        .collect();
+                    Ok(TransferOutcome {
+                        bytes: files.iter().map(|f| f.size).sum(),
+                        detail: Some(names.join(", ")),
+                    })
+                })
+            }
+            TransferProtocol::Raw => return Err(anyhow!("Raw receive is not a transfer; use recording instead")),
+            TransferProtocol::Stm32Bootloader | TransferProtocol::EspRomLoader => {
+                return Err(anyhow!("Bootloader protocols only support flashing"))
             }
+        };
+
+        self.spawn_transfer(protocol.clone(), format!("{:?} receive", protocol), Direction::Received, 0, true, job)
+    }
+
+    /// Flash an STM32 through its UART bootloader. The port is switched to even
+    /// parity for the session and restored to the connection settings afterwards.
+    pub fn flash_stm32(&self, options: Stm32FlashOptions) -> Result<()> {
+        let path = Path::new(&options.file_path);
+        let base_address = options.base_address.unwrap_or(stm32_flasher::DEFAULT_FLASH_BASE);
+        let mut image = firmware_image::load_file(path, base_address)?;
+        // Small holes would otherwise cost an extra Write Memory command each
+        image.fill_gaps(stm32_flasher::BLOCK_SIZE as u32, 0xFF);
+        let total = stm32_flasher::progress_total(&image, options.verify);
+        let parity = self.config.as_ref().map(|c| serialport_parity(&c.parity)).unwrap_or(serialport::Parity::None);
+        let image_len = image.len();
+
+        self.spawn_transfer(
+            TransferProtocol::Stm32Bootloader,
+            file_display_name(&options.file_path),
+            Direction::Sent,
+            total,
+            true,
+            Box::new(move |port, control| {
+                let result = stm32_flasher::flash(port, &image, &options, control);
+                if let Err(e) = port.set_parity(parity) {
+                    warn!("Failed to restore parity after STM32 flashing: {}", e);
+                }
+                let info = result?;
+                Ok(TransferOutcome { bytes: image_len, detail: Some(info.summary()) })
+            }),
+        )
+    }
+
+    /// Flash an ESP8266/ESP32 through its ROM serial loader. The connection baud
+    /// rate is restored afterwards if the loader was switched to a faster one.
+    pub fn flash_esp(&self, options: EspFlashOptions) -> Result<()> {
+        let mut image = firmware_image::load_file(Path::new(&options.file_path), options.offset)?;
+        // FLASH_BEGIN erases whole sectors, so segments sharing one must be written together
+        image.fill_gaps(esp_flasher::FLASH_SECTOR_SIZE, 0xFF);
+        let total = esp_flasher::progress_total(&image);
+        let baud_rate = self.config.as_ref().map(|c| c.baud_rate).unwrap_or(115200);
+
+        self.spawn_transfer(
+            TransferProtocol::EspRomLoader,
+            file_display_name(&options.file_path),
+            Direction::Sent,
+            total,
+            true,
+            Box::new(move |port, control| {
+                let result = esp_flasher::flash(port, &image, &options, control);
+                if options.flash_baud_rate.is_some() {
+                    if let Err(e) = port.set_baud_rate(baud_rate) {
+                        warn!("Failed to restore baud rate after ESP flashing: {}", e);
+                    }
+                }
+                let report = result?;
+                Ok(TransferOutcome { bytes: total, detail: Some(report.summary()) })
+            }),
+        )
+    }
+
+    /// Pipeline sharing the manager's logs, recorder and rules; `reply_port`
+    /// is the handle replies and replayed data are written to
+    fn receive_pipeline(&self, port_name: &str, reply_port: Option<Box<dyn SerialPort>>) -> ReceivePipeline {
+        ReceivePipeline {
+            logs: Arc::clone(&self.logs),
+            log_store: Arc::clone(&self.log_store),
+            max_log_entries: Arc::clone(&self.max_log_entries),
+            stats: Arc::clone(&self.stats),
+            recorder: Arc::clone(&self.recorder),
+            timezone_offset: Arc::clone(&self.timezone_offset_minutes),
+            event_emitter: Arc::clone(&self.event_emitter),
+            triggers: Arc::clone(&self.triggers),
+            capture_paused: Arc::clone(&self.capture_paused),
+            simulator: Arc::clone(&self.simulator),
+            port_name: port_name.to_string(),
+            reply_port,
+            pending_replies: Vec::new(),
         }
-        Ok(())
     }
 
-    /// Start raw binary recording - creates a new binary file and begins recording
-    pub fn start_raw_recording(&self) -> Result<String> {
-        // Check if already recording
-        if let Ok(guard) = self.raw_file.lock() {
-            if guard.is_some() {
-                return Err(anyhow!("Raw recording is already active"));
-            }
+    /// Run `job` on a background thread with a clone of the open port.
+    /// Handles progress/finish events, byte statistics and the log summary entry.
+    /// With `exclusive`, the reader thread is paused while the job runs.
+    fn spawn_transfer(
+        &self,
+        protocol: TransferProtocol,
+        file_name: String,
+        direction: Direction,
+        total: u64,
+        exclusive: bool,
+        job: TransferJob,
+    ) -> Result<()> {
+        let port = self.current_port.as_ref().ok_or_else(|| anyhow!("No port is currently open"))?;
+        let mut job_port = port.try_clone()?;
+
+        if self.get_active_replay() == Some(ReplayTarget::Port) {
+            return Err(anyhow!("A replay is writing to the port; stop it first"));
         }
+        if self.transfer_active.swap(true, Ordering::SeqCst) {
+            return Err(anyhow!("A transfer is already in progress"));
+        }
+        self.transfer_cancel.store(false, Ordering::SeqCst);
+
+        let logs = Arc::clone(&self.logs);
+        let log_store = Arc::clone(&self.log_store);
+        let max_log_entries = Arc::clone(&self.max_log_entries);
+        let stats = Arc::clone(&self.stats);
+        let recorder = Arc::clone(&self.recorder);
+        let timezone_offset = Arc::clone(&self.timezone_offset_minutes);
+        let display_settings = Arc::clone(&self.display_settings);
+        let emitter = Arc::clone(&self.event_emitter);
+        let transfer_active = Arc::clone(&self.transfer_active);
+        let cancel = Arc::clone(&self.transfer_cancel);
+        let reader_paused = Arc::clone(&self.reader_paused);
+        let reader_idle = Arc::clone(&self.reader_idle);
+        let port_name = self.port_name.clone().unwrap_or_default();
+
+        info!("Starting {:?} transfer of {} ({} bytes)", protocol, file_name, total);
+
+        thread::spawn(move || {
+            let started = Instant::now();
+
+            let progress_emitter = Arc::clone(&emitter);
+            let progress_stats = Arc::clone(&stats);
+            let progress_protocol = protocol.clone();
+            let progress_name = file_name.clone();
+            let progress_direction = direction.clone();
+            let last_reported = std::sync::atomic::AtomicU64::new(0);
+            let control = TransferControl::new(cancel, move |transferred, total| {
+                let delta = transferred.saturating_sub(last_reported.swap(transferred, Ordering::Relaxed));
+                // Raw sends count their chunks as they are written
+                let counted_here = progress_protocol != TransferProtocol::Raw;
+                if let Some(mut stats_guard) = progress_stats.lock().ok().filter(|_| counted_here) {
+                    match progress_direction {
+                        Direction::Received => stats_guard.bytes_received += delta,
+                        _ => stats_guard.bytes_sent += delta,
+                    }
+                }
+                emit_event(&progress_emitter, "transfer-progress", TransferProgress {
+                    protocol: progress_protocol.clone(),
+                    file_name: progress_name.clone(),
+                    transferred,
+                    total,
+                    percentage: transfer::progress_percentage(transferred, total),
+                });
+            });
 
-        let file_path = self.generate_recording_filename("bin")?;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)?;
+            if exclusive {
+                pause_reader(&reader_paused, &reader_idle);
+            }
+            // A panic (e.g. on malformed peer data) ends the transfer as failed
+            // instead of leaving the reader paused and the transfer flag set
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(job_port.as_mut(), &control)))
+                .unwrap_or_else(|panic| {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    error!("{:?} transfer panicked: {}", protocol, message);
+                    Err(anyhow!("Transfer aborted by an internal error: {}", message))
+                });
+            if exclusive {
+                reader_paused.store(false, Ordering::SeqCst);
+            }
+            let elapsed = started.elapsed();
+            let transferred = match &result {
+                Ok(outcome) => outcome.bytes,
+                Err(_) => control.transferred(),
+            };
+            let cancelled = matches!(
+                result.as_ref().err().and_then(|e| e.downcast_ref::<TransferError>()),
+                Some(TransferError::Cancelled)
+            );
+
+            let verb = match direction {
+                Direction::Received => "received",
+                _ => "sent",
+            };
+            let summary = match &result {
+                Ok(outcome) => {
+                    let mut text = format!(
+                        "[{:?} transfer] {}: {} {} in {:.2} s",
+                        protocol,
+                        file_name,
+                        verb,
+                        transfer::format_byte_count(transferred),
+                        elapsed.as_secs_f64()
+                    );
+                    if let Some(detail) = &outcome.detail {
+                        text.push_str(&format!(" ({})", detail));
+                    }
+                    text
+                }
+                Err(_) if cancelled => format!(
+                    "[{:?} transfer] {}: cancelled after {} {}",
+                    protocol,
+                    file_name,
+                    transfer::format_byte_count(transferred),
+                    verb
+                ),
+                Err(e) => format!(
+                    "[{:?} transfer] {}: failed after {} {}: {}",
+                    protocol,
+                    file_name,
+                    transfer::format_byte_count(transferred),
+                    verb,
+                    e
+                ),
+            };
+            info!("{}", summary);
+
+            // Write summary to text recording file
+            recorder.write_text(summary.as_bytes(), Direction::System);
+            let tz_offset = *timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
+
+            let disp_settings = display_settings.lock()
+                .map(|guard| guard.clone())
+                .unwrap_or_default();
+            push_log_entry(&logs, &max_log_entries, &log_store, system_log_entry(summary, port_name, &disp_settings, tz_offset));
+
+            emit_event(&emitter, "transfer-finished", TransferResult {
+                protocol,
+                file_name,
+                transferred,
+                success: result.is_ok(),
+                cancelled,
+                error: result.err().filter(|_| !cancelled).map(|e| e.to_string()),
+                elapsed_ms: elapsed.as_millis() as u64,
+            });
+
+            transfer_active.store(false, Ordering::SeqCst);
+        });
+
+        Ok(())
+    }
+}
+
+/// Per-frame work of the reader thread: recording, logging and triggers
+struct ReceivePipeline {
+    logs: Arc<Mutex<VecDeque<LogEntry>>>,
+    log_store: Arc<LogStore>,
+    max_log_entries: Arc<Mutex<usize>>,
+    stats: Arc<Mutex<SerialStats>>,
+    recorder: Arc<Recorder>,
+    timezone_offset: Arc<Mutex<i32>>,
+    event_emitter: Arc<Mutex<Option<EventEmitter>>>,
+    triggers: Arc<TriggerEngine>,
+    capture_paused: Arc<AtomicBool>,
+    simulator: Arc<Simulator>,
+    port_name: String,
+    // Separate handle so replies can be written while the reader owns its own
+    reply_port: Option<Box<dyn SerialPort>>,
+    // Delayed simulator replies with the time they are due
+    pending_replies: Vec<(Instant, Vec<u8>)>,
+}
 
-        let path_str = file_path.to_string_lossy().to_string();
+impl ReceivePipeline {
+    fn handle_frame(&mut self, frame: Vec<u8>, settings: &DisplaySettings) {
+        // Write to text recording file with timestamp and RX label
+        self.recorder.write_text(&frame, Direction::Received);
+        if let Ok(mut stats_guard) = self.stats.lock() {
+            stats_guard.bytes_received += frame.len() as u64;
+        }
 
-        if let Ok(mut guard) = self.raw_file.lock() {
-            *guard = Some(file);
+        let firings = self.triggers.evaluate(&frame);
+        let reply = self.simulator.respond(&frame);
+        let mut fired_entry = None;
+        if !self.capture_paused.load(Ordering::Relaxed) {
+            let log_entry = self.log_entry(Direction::Received, frame, settings);
+            // Trigger actions refer to the logged entry, so keep a copy when any fired
+            let mut copy = (!firings.is_empty()).then(|| log_entry.clone());
+            let id = push_log_entry(&self.logs, &self.max_log_entries, &self.log_store, log_entry);
+            if let Some(copy) = copy.as_mut() {
+                copy.id = id;
+            }
+            fired_entry = copy;
         }
-        if let Ok(mut guard) = self.raw_file_path.lock() {
-            *guard = Some(path_str.clone());
+
+        for firing in firings {
+            self.run_trigger(firing, fired_entry.as_ref(), settings);
         }
 
-        info!("Started raw recording to: {}", path_str);
-        Ok(path_str)
+        if let Some(reply) = reply {
+            debug!("Simulator rule '{}' replies after {:?}", reply.rule, reply.delay);
+            self.pending_replies.push((Instant::now() + reply.delay, reply.data));
+            self.send_due_replies(settings);
+        }
     }
 
-    /// Stop raw binary recording - closes the file
-    pub fn stop_raw_recording(&self) -> Result<()> {
-        if let Ok(mut guard) = self.raw_file.lock() {
-            if let Some(mut file) = guard.take() {
-                file.flush()?;
+    /// Send simulator replies whose delay has passed, in the order they were queued
+    fn send_due_replies(&mut self, settings: &DisplaySettings) {
+        let now = Instant::now();
+        while let Some(index) = self.pending_replies.iter().position(|(due, _)| *due <= now) {
+            let (_, data) = self.pending_replies.remove(index);
+            if let Err(e) = self.send(data, settings) {
+                warn!("Failed to send simulator reply: {}", e);
             }
         }
-        if let Ok(mut guard) = self.raw_file_path.lock() {
-            if let Some(path) = guard.take() {
-                info!("Stopped raw recording: {}", path);
-            }
+    }
+
+    /// Format display text and timestamp based on current settings
+    fn log_entry(&self, direction: Direction, data: Vec<u8>, settings: &DisplaySettings) -> LogEntry {
+        let tz_offset = *self.timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
+        LogEntry {
+            id: None,
+            timestamp: Utc::now(),
+            direction,
+            display_text: format_data_for_display(&data, settings),
+            data,
+            format: DataFormat::Text,
+            port_name: self.port_name.clone(),
+            timestamp_formatted: if settings.show_timestamps {
+                Some(format_timestamp_with_offset(tz_offset))
+            } else {
+                None
+            },
+            hidden: false,
+            highlights: Vec::new(),
         }
-        Ok(())
     }
 
-    /// Get the current recording status
-    pub fn get_recording_status(&self) -> RecordingStatus {
-        let text_recording_active = self.text_file
-            .lock()
-            .map(|guard| guard.is_some())
-            .unwrap_or(false);
-        let raw_recording_active = self.raw_file
-            .lock()
-            .map(|guard| guard.is_some())
-            .unwrap_or(false);
-        let text_file_path = self.text_file_path
-            .lock()
-            .map(|guard| guard.clone())
-            .unwrap_or(None);
-        let raw_file_path = self.raw_file_path
-            .lock()
-            .map(|guard| guard.clone())
-            .unwrap_or(None);
+    /// `entry` is the logged frame, None while capture is paused
+    fn run_trigger(&mut self, firing: Firing, entry: Option<&LogEntry>, settings: &DisplaySettings) {
+        debug!("Trigger '{}' fired", firing.name);
+        let entry_id = entry.and_then(|entry| entry.id);
+        for action in &firing.actions {
+            let result = match action {
+                TriggerAction::SendReply => self.send(firing.reply.clone(), settings),
+                TriggerAction::StartTextRecording => self.recorder.start_text(&self.port_name).map(|_| ()),
+                TriggerAction::StopTextRecording => self.recorder.stop_text(),
+                TriggerAction::StartRawRecording => self.recorder.start_raw(&self.port_name).map(|_| ()),
+                TriggerAction::StopRawRecording => self.recorder.stop_raw(),
+                TriggerAction::Bookmark => match entry {
+                    Some(entry) => self.log_store.add_bookmark(entry, firing.message.clone()).map(|_| ()),
+                    // The frame has no entry to mark while capture is paused
+                    None => Ok(()),
+                },
+                TriggerAction::Notify => {
+                    emit_event(&self.event_emitter, "trigger-notification", TriggerNotification {
+                        trigger: firing.name.clone(),
+                        message: firing.message.clone(),
+                        entry_id,
+                    });
+                    Ok(())
+                }
+                TriggerAction::PauseCapture => {
+                    self.capture_paused.store(true, Ordering::Relaxed);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                warn!("Trigger '{}' action {:?} failed: {}", firing.name, action, e);
+            }
+        }
 
-        RecordingStatus {
-            text_recording_active,
-            raw_recording_active,
-            text_file_path,
-            raw_file_path,
+        let changes_recording = firing.actions.iter().any(|action| matches!(
+            action,
+            TriggerAction::StartTextRecording
+                | TriggerAction::StopTextRecording
+                | TriggerAction::StartRawRecording
+                | TriggerAction::StopRawRecording
+        ));
+        if changes_recording {
+            emit_event(&self.event_emitter, "recording-status", self.recorder.status());
         }
     }
 
-    /// Write data to text recording file with timestamp, direction, and newline
-    pub fn write_to_text_file(&self, data: &[u8], direction: Direction) {
-        if let Ok(mut guard) = self.text_file.lock() {
-            if let Some(ref mut file) = *guard {
-                let tz_offset = *self.timezone_offset_minutes.lock().unwrap_or_else(|e| e.into_inner());
-                let timestamp = format_timestamp_with_offset(tz_offset);
-                let dir_label = match direction {
-                    Direction::Sent => "TX",
-                    Direction::Received => "RX",
+    /// Write data to the port and log it like `SerialManager::send_data`
+    fn send(&mut self, data: Vec<u8>, settings: &DisplaySettings) -> Result<()> {
+        let port = self.reply_port.as_mut().ok_or_else(|| anyhow!("No port is currently open"))?;
+        port.write_all(&data)?;
+        self.recorder.write_text(&data, Direction::Sent);
+        self.recorder.write_raw(&data, Direction::Sent);
+        if let Ok(mut stats_guard) = self.stats.lock() {
+            stats_guard.bytes_sent += data.len() as u64;
+        }
+        let log_entry = self.log_entry(Direction::Sent, data, settings);
+        push_log_entry(&self.logs, &self.max_log_entries, &self.log_store, log_entry);
+        Ok(())
+    }
+
+    /// Write a replayed entry to the port, or add it to the log with its
+    /// original direction and port name
+    fn replay(&mut self, entry: LogEntry, target: &ReplayTarget, settings: &DisplaySettings) -> Result<()> {
+        match target {
+            ReplayTarget::Port => self.send(entry.data, settings),
+            ReplayTarget::LogView => {
+                let port_name = if entry.port_name.is_empty() { self.port_name.clone() } else { entry.port_name };
+                let mut log_entry = LogEntry {
+                    id: None,
+                    timestamp: Utc::now(),
+                    direction: entry.direction,
+                    data: entry.data,
+                    format: DataFormat::Text,
+                    port_name,
+                    display_text: String::new(),
+                    timestamp_formatted: None,
+                    hidden: false,
+                    highlights: Vec::new(),
                 };
-                let text = String::from_utf8_lossy(data);
-                // Write formatted line with timestamp, direction, content, and newline
-                if let Err(e) = writeln!(file, "[{}] {}: {}", timestamp, dir_label, text) {
-                    warn!("Error writing to text recording file: {}", e);
-                }
+                let tz_offset = *self.timezone_offset.lock().unwrap_or_else(|e| e.into_inner());
+                render_log_entry(&mut log_entry, settings, tz_offset);
+                push_log_entry(&self.logs, &self.max_log_entries, &self.log_store, log_entry);
+                Ok(())
             }
         }
     }
+}
 
-    /// Write data to raw binary recording file
-    pub fn write_to_raw_file(&self, data: &[u8]) {
-        if let Ok(mut guard) = self.raw_file.lock() {
-            if let Some(ref mut file) = *guard {
-                if let Err(e) = file.write_all(data) {
-                    warn!("Error writing to raw recording file: {}", e);
-                }
-            }
+/// Sleep until `deadline`; returns false if `cancel` was set meanwhile
+fn wait_until(deadline: Instant, cancel: &AtomicBool) -> bool {
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(20)));
+    }
+}
+
+/// Store an entry in the session database (assigning its id) and append it to
+/// the log buffer, dropping the oldest buffered entries beyond the limit.
+/// Returns the assigned id.
+fn push_log_entry(logs: &Mutex<VecDeque<LogEntry>>, max_log_entries: &Mutex<usize>, log_store: &LogStore, mut log_entry: LogEntry) -> Option<i64> {
+    log_store.insert(&mut log_entry);
+    let id = log_entry.id;
+    if let Ok(mut logs_guard) = logs.lock() {
+        logs_guard.push_back(log_entry);
+        let max_entries = *max_log_entries.lock().unwrap_or_else(|e| e.into_inner());
+        while logs_guard.len() > max_entries {
+            logs_guard.pop_front();
         }
     }
+    id
+}
 
-    /// Stop all recordings (called on disconnect)
-    pub fn stop_all_recordings(&self) {
-        let _ = self.stop_text_recording();
-        let _ = self.stop_raw_recording();
+/// Ask the reader thread to stop touching the port and wait until it has.
+/// Gives up after a short delay in case the reader thread already exited.
+fn pause_reader(reader_paused: &AtomicBool, reader_idle: &AtomicBool) {
+    reader_idle.store(false, Ordering::SeqCst);
+    reader_paused.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + Duration::from_millis(500);
+    while !reader_idle.load(Ordering::SeqCst) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
     }
 }
 
+/// Build a `Direction::System` entry whose data and display text are `text`
+fn system_log_entry(text: String, port_name: String, settings: &DisplaySettings, tz_offset: i32) -> LogEntry {
+    LogEntry {
+        id: None,
+        timestamp: Utc::now(),
+        direction: Direction::System,
+        data: text.clone().into_bytes(),
+        format: DataFormat::Text,
+        port_name,
+        display_text: text,
+        timestamp_formatted: if settings.show_timestamps {
+            Some(format_timestamp_with_offset(tz_offset))
+        } else {
+            None
+        },
+        hidden: false,
+        highlights: Vec::new(),
+    }
+}
+
+/// Fill in display text and formatted timestamp from the entry's stored bytes
+fn render_log_entry(entry: &mut LogEntry, settings: &DisplaySettings, tz_offset: i32) {
+    // System entries carry their summary text verbatim
+    entry.display_text = if matches!(entry.direction, Direction::System) {
+        String::from_utf8_lossy(&entry.data).into_owned()
+    } else {
+        format_data_for_display(&entry.data, settings)
+    };
+    entry.timestamp_formatted = if settings.show_timestamps {
+        Some(format_datetime_with_offset(&entry.timestamp, tz_offset))
+    } else {
+        None
+    };
+}
+
+/// Send an event to the frontend if an emitter is installed
+fn emit_event<T: Serialize>(emitter: &Mutex<Option<EventEmitter>>, event: &str, payload: T) {
+    let emitter = emitter.lock().ok().and_then(|guard| guard.clone());
+    if let Some(emit) = emitter {
+        match serde_json::to_value(payload) {
+            Ok(value) => emit(event, value),
+            Err(e) => warn!("Failed to serialize {} event: {}", event, e),
+        }
+    }
+}
+
+fn serialport_parity(parity: &Parity) -> serialport::Parity {
+    match parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Odd => serialport::Parity::Odd,
+        Parity::Even => serialport::Parity::Even,
+        Parity::Mark => serialport::Parity::None,
+        Parity::Space => serialport::Parity::None,
+    }
+}
+
+/// Open a file for sending. Decoded firmware images are flattened into memory
+/// and named `<stem>.bin` so batch protocols announce what is actually sent.
+fn open_send_source(path: &str, decode_image: bool) -> Result<(Box<dyn transfer::SendSource>, u64, String)> {
+    let format = firmware_image::ImageFormat::from_path(Path::new(path));
+    if decode_image && format != firmware_image::ImageFormat::Binary {
+        let image = firmware_image::load_file(Path::new(path), 0)?;
+        let (_, data) = image.flatten(0xFF)?;
+        let name = Path::new(path)
+            .file_stem()
+            .map(|stem| format!("{}.bin", stem.to_string_lossy()))
+            .unwrap_or_else(|| file_display_name(path));
+        let total = data.len() as u64;
+        return Ok((Box::new(std::io::Cursor::new(data)), total, name));
+    }
+
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+    Ok((Box::new(file), total, file_display_name(path)))
+}
+
+/// File name component of a path, for log summaries
+fn file_display_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
 /// Find the position of a delimiter in the data buffer
 fn find_delimiter(data: &[u8], delimiter: &[u8]) -> Option<usize> {
     if delimiter.is_empty() || data.len() < delimiter.len() {
@@ -930,16 +1863,20 @@ fn find_any_newline(data: &[u8]) -> Option<(usize, usize)> {
 }
 
 /// Format current UTC time with timezone offset applied
-fn format_timestamp_with_offset(offset_minutes: i32) -> String {
+pub(crate) fn format_timestamp_with_offset(offset_minutes: i32) -> String {
+    format_datetime_with_offset(&Utc::now(), offset_minutes)
+}
+
+/// Format a UTC timestamp with timezone offset applied
+fn format_datetime_with_offset(timestamp: &DateTime<Utc>, offset_minutes: i32) -> String {
     use chrono::FixedOffset;
     let offset_seconds = offset_minutes * 60;
     let tz_offset = FixedOffset::east_opt(offset_seconds).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
-    let now_with_tz = Utc::now().with_timezone(&tz_offset);
-    now_with_tz.format("%H:%M:%S%.3f").to_string()
+    timestamp.with_timezone(&tz_offset).format("%H:%M:%S%.3f").to_string()
 }
 
 /// Format a date for filenames with timezone offset applied
-fn format_date_for_filename_with_offset(offset_minutes: i32) -> String {
+pub(crate) fn format_date_for_filename_with_offset(offset_minutes: i32) -> String {
     use chrono::FixedOffset;
     let offset_seconds = offset_minutes * 60;
     let tz_offset = FixedOffset::east_opt(offset_seconds).unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
@@ -948,7 +1885,7 @@ fn format_date_for_filename_with_offset(offset_minutes: i32) -> String {
 }
 
 /// Format bytes as hexadecimal string (e.g., "48 65 6C 6C 6F")
-fn format_bytes_as_hex(data: &[u8]) -> String {
+pub(crate) fn format_bytes_as_hex(data: &[u8]) -> String {
     data.iter()
         .map(|b| format!("{:02X}", b))
         .collect::<Vec<_>>()
@@ -1043,6 +1980,22 @@ fn format_bytes_as_text(data: &[u8], encoding: &TextEncoding, special_chars: &Sp
     result
 }
 
+/// Identity of the device behind `port_name` that survives it being
+/// re-enumerated: the USB serial number when there is one, otherwise the port
+/// name
+fn device_key(port_name: &str) -> String {
+    serialport::available_ports()
+        .ok()
+        .and_then(|ports| ports.into_iter().find(|port| port.port_name == port_name))
+        .and_then(|port| match port.port_type {
+            SerialPortType::UsbPort(usb) => usb
+                .serial_number
+                .map(|serial| format!("usb:{:04x}:{:04x}:{}", usb.vid, usb.pid, serial)),
+            _ => None,
+        })
+        .unwrap_or_else(|| port_name.to_string())
+}
+
 /// Deduplicate macOS callout/tty pairs, drop system virtual ports, and put
 /// USB adapters first so the UI auto-selects a useful device.
 fn sanitize_listed_ports(ports: Vec<SerialPortInfo>) -> Vec<SerialPortInfo> {
@@ -1094,7 +2047,7 @@ fn sort_usb_ports_first(mut ports: Vec<SerialPortInfo>) -> Vec<SerialPortInfo> {
 }
 
 /// Format data based on display settings
-fn format_data_for_display(data: &[u8], settings: &DisplaySettings) -> String {
+pub(crate) fn format_data_for_display(data: &[u8], settings: &DisplaySettings) -> String {
     match settings.format {
         ReceiveDisplayFormat::Hex => format_bytes_as_hex(data),
         ReceiveDisplayFormat::Txt => format_bytes_as_text(data, &settings.encoding, &settings.special_char_config),
@@ -1182,4 +2135,152 @@ mod tests {
 
         assert_eq!(names(&filtered), vec!["/dev/cu.usbserial-140", "/dev/cu.HUAWEIFreeBudsPro3"]);
     }
-}
\ No newline at end of file
+
+    fn received_entry(data: &[u8], settings: &DisplaySettings) -> LogEntry {
+        LogEntry {
+            id: None,
+            timestamp: Utc::now(),
+            direction: Direction::Received,
+            data: data.to_vec(),
+            format: DataFormat::Text,
+            port_name: "COM3".to_string(),
+            display_text: format_data_for_display(data, settings),
+            timestamp_formatted: None,
+            hidden: false,
+            highlights: Vec::new(),
+        }
+    }
+
+    /// Manager whose session database lives in a throwaway directory
+    fn temp_manager() -> (SerialManager, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("serial-manager-test-{}", uuid::Uuid::new_v4()));
+        let manager = SerialManager::new();
+        manager.set_log_directory(dir.to_string_lossy().to_string());
+        (manager, dir)
+    }
+
+    #[test]
+    fn reformat_logs_applies_current_display_settings() {
+        let (mut manager, dir) = temp_manager();
+        manager.add_log(received_entry(b"Hi", &manager.get_display_settings()));
+        assert_eq!(manager.get_logs()[0].display_text, "Hi");
+
+        manager.set_display_format(ReceiveDisplayFormat::Hex);
+        manager.reformat_logs();
+
+        let logs = manager.get_logs();
+        assert_eq!(logs[0].display_text, "48 69");
+        assert!(logs[0].timestamp_formatted.is_some());
+
+        manager.set_show_timestamps(false);
+        manager.reformat_logs();
+        assert!(manager.get_logs()[0].timestamp_formatted.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_page_reaches_entries_dropped_from_the_buffer() {
+        let (mut manager, dir) = temp_manager();
+        manager.set_max_log_entries(100);
+        let settings = manager.get_display_settings();
+        for i in 0..150u8 {
+            manager.add_log(received_entry(&[i], &settings));
+        }
+        let buffered = manager.get_logs();
+        assert_eq!(buffered.len(), 100);
+
+        let oldest_buffered = buffered[0].id.unwrap();
+        let page = manager.get_log_page(Some(oldest_buffered), None, 1000).unwrap();
+        assert_eq!(page.entries.len(), 50);
+        assert_eq!(page.total_count, 150);
+        assert!(!page.has_more);
+        assert_eq!(page.entries[0].data, vec![0]);
+        assert_eq!(page.entries[49].id.unwrap() + 1, oldest_buffered);
+
+        manager.clear_logs();
+        assert_eq!(manager.get_log_session_info().unwrap().entry_count, 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn search_logs_returns_ids_context_and_preview() {
+        let (mut manager, dir) = temp_manager();
+        let settings = manager.get_display_settings();
+        for text in ["boot", "temp=21", "ERR timeout", "temp=22"] {
+            manager.add_log(received_entry(text.as_bytes(), &settings));
+        }
+        let first_id = manager.get_logs()[0].id.unwrap();
+
+        let query = SearchQuery { pattern: "74 69 6D ?? 6F".to_string(), mode: PatternMode::Hex, context: 1, ..Default::default() };
+        let result = manager.search_logs(query).unwrap();
+        assert_eq!(result.matches.len(), 1);
+        let found = &result.matches[0];
+        assert_eq!(found.id, first_id + 2);
+        assert_eq!(found.preview, "ERR timeout");
+        assert_eq!((found.context_before.clone(), found.context_after.clone()), (vec![first_id + 1], vec![first_id + 3]));
+
+        let query = SearchQuery { pattern: "(".to_string(), mode: PatternMode::Regex, ..Default::default() };
+        assert!(manager.search_logs(query).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn json_export_imports_back_with_bookmarks() {
+        let (mut manager, dir) = temp_manager();
+        let settings = manager.get_display_settings();
+        manager.add_log(received_entry(&[0x00, 0x41, 0xFF], &settings));
+        manager.add_log(received_entry(b"OK", &settings));
+        let marked = manager.get_logs()[1].id.unwrap();
+        manager.add_bookmark(marked, "answer".to_string()).unwrap();
+
+        let export = dir.join("export.json");
+        let export = export.to_str().unwrap();
+        manager.export_logs(export, ExportFormat::Json, ExportOptions::default(), None, 0).unwrap();
+        let summary = manager.import_logs(export, 0).unwrap();
+        assert_eq!((summary.entry_count, summary.bookmark_count), (2, 1));
+
+        let logs = manager.get_logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].data, [0x00, 0x41, 0xFF]);
+        assert!(logs[0].id.unwrap() > marked);
+        let bookmark = manager.find_bookmark(Some(marked), false).unwrap();
+        assert_eq!((bookmark.entry_id, bookmark.note.as_str()), (logs[1].id.unwrap(), "answer"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn filtered_export_reads_the_session_history() {
+        let (mut manager, dir) = temp_manager();
+        manager.set_max_log_entries(100);
+        let settings = manager.get_display_settings();
+        for i in 0..150 {
+            let mut entry = received_entry(format!("line {}", i).as_bytes(), &settings);
+            if i % 3 == 0 {
+                entry.direction = Direction::Sent;
+            }
+            manager.add_log(entry);
+        }
+        let first_id = manager.get_log_page(None, None, 5000).unwrap().entries[0].id.unwrap();
+
+        let export = dir.join("export.txt");
+        let export = export.to_str().unwrap();
+        let filter = ExportFilter {
+            direction: Some(Direction::Received),
+            entry_ids: (first_id..first_id + 10).collect(),
+            pattern: "line [0-4]$".to_string(),
+            mode: PatternMode::Regex,
+            ..Default::default()
+        };
+        manager.export_logs(export, ExportFormat::Txt, ExportOptions::default(), Some(filter), 0).unwrap();
+        let lines: Vec<String> = std::fs::read_to_string(export).unwrap()
+            .lines()
+            .skip(4)
+            .map(|line| line.split_once("] ").unwrap().1.to_string())
+            .collect();
+        assert_eq!(lines, ["RX: line 1", "RX: line 2", "RX: line 4"]);
+
+        let filter = ExportFilter { entry_ids: vec![-1], ..Default::default() };
+        assert!(manager.export_logs(export, ExportFormat::Txt, ExportOptions::default(), Some(filter), 0).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
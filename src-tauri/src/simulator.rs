@@ -0,0 +1,395 @@
+//! Auto-reply device simulator
+//!
+//! Received frames are checked against the rule table in order; the first
+//! enabled rule whose pattern and state match produces the reply. Every match
+//! kind compiles to a byte regex with Unicode off, so `.` and `\xNN` match any
+//! byte of a binary frame. Exact, prefix and hex mask rules are anchored;
+//! regex rules are used as written (anchor them with `^`/`$`) and their
+//! literals match as UTF-8. Regex groups and hex mask wildcard bytes can be
+//! copied into escaped responses as `{$1}`/`{$name}`.
+//! Templates are expanded into `send_syntax` input, so checksum placeholders
+//! in a response cover the substituted bytes too.
+
+use crate::pattern;
+use crate::send_syntax;
+use crate::types::{DataFormat, SimulatorConfig, SimulatorMatch, SimulatorRule, SimulatorStatus, TextEncoding};
+use anyhow::{anyhow, Result};
+use regex::bytes::{Captures, Regex, RegexBuilder};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Response produced for a frame, to be sent after `delay`
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub rule: String,
+    pub data: Vec<u8>,
+    pub delay: Duration,
+}
+
+enum Response {
+    /// Text and hex responses are resolved once
+    Fixed(Vec<u8>),
+    /// Escaped responses are expanded and resolved per match
+    Template(String),
+}
+
+struct CompiledRule {
+    regex: Regex,
+    responses: Vec<Response>,
+    match_count: u64,
+}
+
+#[derive(Default)]
+struct SimulatorState {
+    config: SimulatorConfig,
+    rules: Vec<CompiledRule>,
+    encoding: TextEncoding,
+    state: String,
+    replies_sent: u64,
+}
+
+#[derive(Default)]
+pub struct Simulator {
+    inner: Mutex<SimulatorState>,
+}
+
+impl Simulator {
+    /// Replace the configuration and restart from the initial state. Nothing
+    /// changes if any rule is invalid.
+    pub fn configure(&self, config: SimulatorConfig, encoding: &TextEncoding) -> Result<()> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| compile_rule(rule, encoding).map_err(|e| anyhow!("Simulator rule '{}': {}", rule.name, e)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        *inner = SimulatorState {
+            state: config.initial_state.clone(),
+            config,
+            rules,
+            encoding: encoding.clone(),
+            replies_sent: 0,
+        };
+        Ok(())
+    }
+
+    pub fn config(&self) -> SimulatorConfig {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).config.clone()
+    }
+
+    pub fn status(&self) -> SimulatorStatus {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        SimulatorStatus {
+            enabled: inner.config.enabled,
+            state: inner.state.clone(),
+            replies_sent: inner.replies_sent,
+        }
+    }
+
+    /// Back to the initial state with fresh response sequences and counters
+    pub fn reset(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.state = inner.config.initial_state.clone();
+        inner.replies_sent = 0;
+        for rule in inner.rules.iter_mut() {
+            rule.match_count = 0;
+        }
+    }
+
+    /// Reply for a received frame, if the simulator is enabled and a rule matches
+    pub fn respond(&self, frame: &[u8]) -> Option<Reply> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if !inner.config.enabled {
+            return None;
+        }
+
+        let SimulatorState { config, rules, encoding, state, replies_sent } = &mut *inner;
+        let (rule, compiled) = config
+            .rules
+            .iter()
+            .zip(rules.iter_mut())
+            .filter(|(rule, _)| rule.enabled && rule.state.as_ref().is_none_or(|s| s == state))
+            .find(|(_, compiled)| compiled.regex.is_match(frame))?;
+        let captures = compiled.regex.captures(frame)?;
+
+        compiled.match_count += 1;
+        if let Some(next) = &rule.next_state {
+            state.clone_from(next);
+        }
+        if compiled.responses.is_empty() {
+            return None;
+        }
+
+        // Responses are used in turn on successive matches
+        let index = ((compiled.match_count - 1) % compiled.responses.len() as u64) as usize;
+        let data = match &compiled.responses[index] {
+            Response::Fixed(bytes) => bytes.clone(),
+            Response::Template(template) => {
+                let expanded = expand_template(template, Some(&captures), compiled.match_count);
+                match send_syntax::parse_escaped(&expanded, encoding) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        log::warn!("Simulator rule '{}' response is invalid: {}", rule.name, e);
+                        return None;
+                    }
+                }
+            }
+        };
+
+        *replies_sent += 1;
+        Some(Reply {
+            rule: rule.name.clone(),
+            data,
+            delay: Duration::from_millis(rule.delay_ms),
+        })
+    }
+}
+
+fn compile_rule(rule: &SimulatorRule, encoding: &TextEncoding) -> Result<CompiledRule> {
+    let regex = match rule.match_kind {
+        SimulatorMatch::Regex => byte_regex(&rule.pattern)?,
+        SimulatorMatch::Exact | SimulatorMatch::Prefix => {
+            let mut bytes = send_syntax::resolve(&rule.pattern, &rule.pattern_format, encoding)?;
+            let source = if rule.match_kind == SimulatorMatch::Exact {
+                while bytes.last().is_some_and(|b| matches!(b, b'\r' | b'\n')) {
+                    bytes.pop();
+                }
+                format!("^{}[\\r\\n]*$", pattern::escape_bytes(&bytes))
+            } else {
+                format!("^{}", pattern::escape_bytes(&bytes))
+            };
+            byte_regex(&source)?
+        }
+        SimulatorMatch::HexMask => byte_regex(&hex_mask_to_regex(&rule.pattern)?)?,
+    };
+
+    let responses = rule
+        .responses
+        .iter()
+        .map(|response| match rule.response_format {
+            DataFormat::Escaped => {
+                check_template(response, &regex)?;
+                Ok(Response::Template(response.clone()))
+            }
+            _ => Ok(Response::Fixed(send_syntax::resolve(response, &rule.response_format, encoding)?)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(CompiledRule { regex, responses, match_count: 0 })
+}
+
+fn byte_regex(source: &str) -> Result<Regex> {
+    Ok(RegexBuilder::new(source).unicode(false).dot_matches_new_line(true).build()?)
+}
+
+/// `AA 5? ??` matches a 3 byte frame; every byte with a wildcard is a capture group
+fn hex_mask_to_regex(mask: &str) -> Result<String> {
+    let digits: Vec<char> = mask.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() {
+        return Err(anyhow!("Hex mask is empty"));
+    }
+
+    let mut source = String::from("^");
+    for pair in digits.chunks(2) {
+        let &[high, low] = pair else {
+            return Err(anyhow!("Hex mask has an unpaired digit"));
+        };
+        let nibble = |c: char| match c {
+            '?' => Ok(None),
+            c => c.to_digit(16).map(|d| Some(d as u8)).ok_or_else(|| anyhow!("Invalid hex mask character '{}'", c)),
+        };
+        match (nibble(high)?, nibble(low)?) {
+            (Some(h), Some(l)) => {
+                let _ = write!(source, "\\x{:02X}", (h << 4) | l);
+            }
+            (None, None) => source.push_str("(.)"),
+            (h, l) => {
+                // One fixed nibble: list the 16 bytes it allows
+                source.push_str("([");
+                for other in 0..16u8 {
+                    let byte = match (h, l) {
+                        (Some(h), _) => (h << 4) | other,
+                        (_, Some(l)) => (other << 4) | l,
+                        _ => unreachable!(),
+                    };
+                    let _ = write!(source, "\\x{:02X}", byte);
+                }
+                source.push_str("])");
+            }
+        }
+    }
+    source.push('$');
+    Ok(source)
+}
+
+/// Field references in `template`: `{$count}`, `{$<group index>}` or `{$<group name>}`
+fn template_fields(template: &str) -> impl Iterator<Item = (usize, usize, &str)> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let start = offset + template[offset..].find("{$")?;
+        let end = start + template[start..].find('}')?;
+        offset = end + 1;
+        Some((start, end + 1, &template[start + 2..end]))
+    })
+}
+
+fn check_template(template: &str, regex: &Regex) -> Result<()> {
+    for (_, _, name) in template_fields(template) {
+        let known = name == "count"
+            || name.parse::<usize>().is_ok_and(|index| index < regex.captures_len())
+            || regex.capture_names().flatten().any(|group| group == name);
+        if !known {
+            return Err(anyhow!("Unknown response field '{{${}}}'", name));
+        }
+    }
+    // Captured bytes become `{hex:..}` blocks, so an empty expansion checks the rest
+    send_syntax::parse_escaped(&expand_template(template, None, 0), &TextEncoding::Utf8)?;
+    Ok(())
+}
+
+fn expand_template(template: &str, captures: Option<&Captures>, count: u64) -> String {
+    let mut expanded = String::new();
+    let mut last = 0;
+    for (start, end, name) in template_fields(template) {
+        expanded.push_str(&template[last..start]);
+        if name == "count" {
+            let _ = write!(expanded, "{}", count);
+        } else {
+            let bytes = captures
+                .and_then(|captures| match name.parse::<usize>() {
+                    Ok(index) => captures.get(index),
+                    Err(_) => captures.name(name),
+                })
+                .map(|m| m.as_bytes())
+                .unwrap_or_default();
+            expanded.push_str("{hex:");
+            for byte in bytes {
+                let _ = write!(expanded, "{:02X}", byte);
+            }
+            expanded.push('}');
+        }
+        last = end;
+    }
+    expanded.push_str(&template[last..]);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulator(rules: Vec<SimulatorRule>) -> Simulator {
+        let simulator = Simulator::default();
+        let config = SimulatorConfig { enabled: true, initial_state: "idle".to_string(), rules };
+        simulator.configure(config, &TextEncoding::Utf8).unwrap();
+        simulator
+    }
+
+    #[test]
+    fn exact_and_prefix_rules_cycle_responses() {
+        let sim = simulator(vec![
+            SimulatorRule {
+                name: "id".to_string(),
+                pattern: "AT+ID?".to_string(),
+                responses: vec!["ID=1\\r\\n".to_string(), "ID=2\\r\\n".to_string()],
+                delay_ms: 20,
+                ..Default::default()
+            },
+            SimulatorRule {
+                name: "any-at".to_string(),
+                match_kind: SimulatorMatch::Prefix,
+                pattern: "AT".to_string(),
+                responses: vec!["OK".to_string()],
+                response_format: DataFormat::Text,
+                ..Default::default()
+            },
+        ]);
+
+        let reply = sim.respond(b"AT+ID?\r\n").unwrap();
+        assert_eq!((reply.rule.as_str(), reply.data.as_slice()), ("id", b"ID=1\r\n".as_slice()));
+        assert_eq!(reply.delay, Duration::from_millis(20));
+        assert_eq!(sim.respond(b"AT+ID?").unwrap().data, b"ID=2\r\n");
+        assert_eq!(sim.respond(b"AT+ID?x").unwrap().data, b"OK");
+        assert!(sim.respond(b"hello").is_none());
+        assert_eq!(sim.status().replies_sent, 3);
+    }
+
+    #[test]
+    fn hex_mask_captures_feed_templates_and_checksums() {
+        let sim = simulator(vec![SimulatorRule {
+            name: "read".to_string(),
+            match_kind: SimulatorMatch::HexMask,
+            pattern: "01 03 ?? 0?".to_string(),
+            responses: vec!["{hex:01 83}{$1}{$2}{crc16}".to_string()],
+            ..Default::default()
+        }]);
+
+        let reply = sim.respond(&[0x01, 0x03, 0x10, 0x05]).unwrap();
+        let body = [0x01, 0x83, 0x10, 0x05];
+        let crc = send_syntax::crc16_modbus(&body).to_le_bytes();
+        assert_eq!(reply.data, [&body[..], &crc[..]].concat());
+        assert!(sim.respond(&[0x01, 0x03, 0x10, 0x15]).is_none());
+    }
+
+    #[test]
+    fn regex_rules_match_binary_frames() {
+        let sim = simulator(vec![SimulatorRule {
+            name: "echo".to_string(),
+            match_kind: SimulatorMatch::Regex,
+            pattern: "^\\xAA(.)\\x55$".to_string(),
+            responses: vec!["{hex:AB}{$1}".to_string()],
+            ..Default::default()
+        }]);
+
+        assert_eq!(sim.respond(&[0xAA, 0xFE, 0x55]).unwrap().data, [0xAB, 0xFE]);
+        assert!(sim.respond(&[0x00, 0xAA, 0xFE, 0x55]).is_none());
+    }
+
+    #[test]
+    fn states_gate_rules() {
+        let sim = simulator(vec![
+            SimulatorRule {
+                name: "login".to_string(),
+                match_kind: SimulatorMatch::Regex,
+                pattern: "^LOGIN (?P<user>\\w+)".to_string(),
+                responses: vec!["HI {$user} #{$count}".to_string()],
+                state: Some("idle".to_string()),
+                next_state: Some("ready".to_string()),
+                ..Default::default()
+            },
+            SimulatorRule {
+                name: "status".to_string(),
+                pattern: "STATUS".to_string(),
+                responses: vec!["READY".to_string()],
+                state: Some("ready".to_string()),
+                ..Default::default()
+            },
+        ]);
+
+        assert!(sim.respond(b"STATUS").is_none());
+        assert_eq!(sim.respond(b"LOGIN bob").unwrap().data, b"HI bob #1");
+        assert_eq!(sim.status().state, "ready");
+        assert_eq!(sim.respond(b"STATUS").unwrap().data, b"READY");
+        assert!(sim.respond(b"LOGIN bob").is_none());
+
+        sim.reset();
+        assert_eq!(sim.status().state, "idle");
+    }
+
+    #[test]
+    fn rejects_unknown_template_fields() {
+        let config = SimulatorConfig {
+            enabled: true,
+            initial_state: String::new(),
+            rules: vec![SimulatorRule {
+                name: "bad".to_string(),
+                pattern: "X".to_string(),
+                responses: vec!["{$3}".to_string()],
+                ..Default::default()
+            }],
+        };
+        assert!(Simulator::default().configure(config, &TextEncoding::Utf8).is_err());
+    }
+}
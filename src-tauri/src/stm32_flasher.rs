@@ -0,0 +1,529 @@
+//! STM32 system-memory bootloader over UART (ST application note AN3155)
+//!
+//! The bootloader runs at 8E1 and detects the baud rate from the 0x7F sync
+//! byte. BOOT0/NRST are driven through DTR/RTS when the adapter is wired for
+//! it; otherwise the target must already be in bootloader mode.
+
+use crate::firmware_image::FirmwareImage;
+use crate::transfer::{self, ControlPort, TransferControl, TransferError};
+use crate::types::{ControlLine, Stm32FlashOptions};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+const SYNC: u8 = 0x7F;
+const ACK: u8 = 0x79;
+const NACK: u8 = 0x1F;
+
+const CMD_GET: u8 = 0x00;
+const CMD_GET_ID: u8 = 0x02;
+const CMD_READ_MEMORY: u8 = 0x11;
+const CMD_GO: u8 = 0x21;
+const CMD_WRITE_MEMORY: u8 = 0x31;
+const CMD_ERASE: u8 = 0x43;
+const CMD_EXTENDED_ERASE: u8 = 0x44;
+
+/// Largest payload of a single Write/Read Memory command
+pub const BLOCK_SIZE: usize = 256;
+/// Start of main flash on all STM32 families
+pub const DEFAULT_FLASH_BASE: u32 = 0x0800_0000;
+
+/// Most pages one Erase command can take (its count byte holds pages - 1,
+/// with 0xFF reserved for mass erase)
+const ERASE_PAGES_PER_COMMAND: usize = 255;
+
+const SYNC_ATTEMPTS: u32 = 5;
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const ERASE_TIMEOUT: Duration = Duration::from_secs(60);
+const RESET_PULSE: Duration = Duration::from_millis(50);
+const BOOT_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Error)]
+pub enum Stm32Error {
+    #[error("STM32 bootloader did not answer the sync byte")]
+    NoResponse,
+    #[error("STM32 bootloader rejected {0}")]
+    Nack(&'static str),
+    #[error("Unexpected byte 0x{byte:02X} from STM32 bootloader during {operation}")]
+    UnexpectedByte { byte: u8, operation: &'static str },
+    #[error("Bootloader does not support the erase command")]
+    EraseUnsupported,
+    #[error("Image address 0x{address:08X} is outside the erasable flash pages")]
+    OutsideFlash { address: u32 },
+    #[error("Verify failed at 0x{address:08X}")]
+    VerifyFailed { address: u32 },
+}
+
+/// What the bootloader reported about itself
+#[derive(Debug, Clone)]
+pub struct BootloaderInfo {
+    /// Protocol version, e.g. 0x31 for v3.1
+    pub version: u8,
+    pub product_id: u16,
+    pub commands: Vec<u8>,
+}
+
+impl BootloaderInfo {
+    pub fn summary(&self) -> String {
+        format!(
+            "bootloader v{}.{}, product ID 0x{:04X}",
+            self.version >> 4,
+            self.version & 0x0F,
+            self.product_id
+        )
+    }
+}
+
+/// Bytes reported as progress for flashing `image` (write plus optional verify)
+pub fn progress_total(image: &FirmwareImage, verify: bool) -> u64 {
+    image.len() * if verify { 2 } else { 1 }
+}
+
+/// Reset into the bootloader, erase, write, verify and start the application
+pub fn flash<P: ControlPort + ?Sized>(
+    port: &mut P,
+    image: &FirmwareImage,
+    options: &Stm32FlashOptions,
+    control: &TransferControl,
+) -> Result<BootloaderInfo> {
+    port.set_parity(serialport::Parity::Even)?;
+    enter_bootloader(port, options)?;
+    synchronize(port, control)?;
+
+    let info = get_info(port, control)?;
+    if options.mass_erase {
+        mass_erase(port, &info, control)?;
+    } else {
+        erase_pages(port, &info, &image_pages(image, options.page_size)?, control)?;
+    }
+
+    let total = progress_total(image, options.verify);
+    let mut done = 0u64;
+    for (address, block, payload_len) in blocks(image) {
+        write_memory(port, address, &block, control)?;
+        done += payload_len as u64;
+        control.report(done, total);
+    }
+
+    if options.verify {
+        for (address, block, payload_len) in blocks(image) {
+            let read_back = read_memory(port, address, block.len(), control)?;
+            if let Some(offset) = block.iter().zip(&read_back).position(|(a, b)| a != b) {
+                return Err(Stm32Error::VerifyFailed { address: address + offset as u32 }.into());
+            }
+            done += payload_len as u64;
+            control.report(done, total);
+        }
+    }
+
+    // BOOT0 low first so any later reset boots the application
+    set_pin(port, options.boot0_line, false, options.invert_lines)?;
+    if options.start_application {
+        let start = image.start_address().unwrap_or(DEFAULT_FLASH_BASE);
+        go(port, start, control)?;
+    } else {
+        reset_target(port, options)?;
+    }
+    Ok(info)
+}
+
+/// Drive a target pin high or low. USB-UART adapters pull the pin low when the
+/// line is asserted, so the line level is the inverse of the pin level unless
+/// the wiring inverts it again.
+fn set_pin<P: ControlPort + ?Sized>(port: &mut P, line: ControlLine, high: bool, inverted: bool) -> Result<()> {
+    let asserted = high == inverted;
+    match line {
+        ControlLine::Dtr => port.set_dtr(asserted),
+        ControlLine::Rts => port.set_rts(asserted),
+        ControlLine::None => Ok(()),
+    }
+}
+
+fn enter_bootloader<P: ControlPort + ?Sized>(port: &mut P, options: &Stm32FlashOptions) -> Result<()> {
+    if options.reset_line == ControlLine::None {
+        return Ok(());
+    }
+    set_pin(port, options.boot0_line, true, options.invert_lines)?;
+    reset_target(port, options)
+}
+
+fn reset_target<P: ControlPort + ?Sized>(port: &mut P, options: &Stm32FlashOptions) -> Result<()> {
+    if options.reset_line == ControlLine::None {
+        return Ok(());
+    }
+    set_pin(port, options.reset_line, false, options.invert_lines)?;
+    thread::sleep(RESET_PULSE);
+    set_pin(port, options.reset_line, true, options.invert_lines)?;
+    thread::sleep(BOOT_DELAY);
+    Ok(())
+}
+
+/// Send 0x7F until the bootloader answers. A NACK means it was already
+/// synchronized (e.g. by an earlier session) and is ready for commands.
+fn synchronize<P: ControlPort + ?Sized>(port: &mut P, control: &TransferControl) -> Result<()> {
+    transfer::drain_input(port, control)?;
+    for _ in 0..SYNC_ATTEMPTS {
+        port.write_all(&[SYNC])?;
+        port.flush()?;
+        match transfer::read_byte(port, Duration::from_millis(500), control)? {
+            Some(ACK) | Some(NACK) => return Ok(()),
+            _ => transfer::drain_input(port, control)?,
+        }
+    }
+    Err(Stm32Error::NoResponse.into())
+}
+
+fn wait_ack<P: ControlPort + ?Sized>(
+    port: &mut P,
+    timeout: Duration,
+    operation: &'static str,
+    control: &TransferControl,
+) -> Result<()> {
+    match transfer::read_byte(port, timeout, control)? {
+        Some(ACK) => Ok(()),
+        Some(NACK) => Err(Stm32Error::Nack(operation).into()),
+        Some(byte) => Err(Stm32Error::UnexpectedByte { byte, operation }.into()),
+        None => Err(TransferError::Timeout(format!("STM32 {} acknowledgement", operation)).into()),
+    }
+}
+
+fn send_command<P: ControlPort + ?Sized>(
+    port: &mut P,
+    command: u8,
+    operation: &'static str,
+    control: &TransferControl,
+) -> Result<()> {
+    port.write_all(&[command, !command])?;
+    port.flush()?;
+    wait_ack(port, ACK_TIMEOUT, operation, control)
+}
+
+/// Address in big-endian order followed by its XOR checksum
+fn send_address<P: ControlPort + ?Sized>(
+    port: &mut P,
+    address: u32,
+    operation: &'static str,
+    control: &TransferControl,
+) -> Result<()> {
+    let bytes = address.to_be_bytes();
+    let checksum = bytes.iter().fold(0u8, |acc, b| acc ^ b);
+    port.write_all(&[bytes[0], bytes[1], bytes[2], bytes[3], checksum])?;
+    port.flush()?;
+    wait_ack(port, ACK_TIMEOUT, operation, control)
+}
+
+fn read_bytes<P: ControlPort + ?Sized>(
+    port: &mut P,
+    len: usize,
+    operation: &'static str,
+    control: &TransferControl,
+) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    if !transfer::read_exact_timeout(port, &mut buf, ACK_TIMEOUT, control)? {
+        return Err(TransferError::Timeout(format!("STM32 {} reply", operation)).into());
+    }
+    Ok(buf)
+}
+
+/// Get (version and supported commands) followed by Get ID
+fn get_info<P: ControlPort + ?Sized>(port: &mut P, control: &TransferControl) -> Result<BootloaderInfo> {
+    send_command(port, CMD_GET, "Get", control)?;
+    let count = read_bytes(port, 1, "Get", control)?[0] as usize;
+    let reply = read_bytes(port, count + 1, "Get", control)?;
+    wait_ack(port, ACK_TIMEOUT, "Get", control)?;
+
+    send_command(port, CMD_GET_ID, "Get ID", control)?;
+    let count = read_bytes(port, 1, "Get ID", control)?[0] as usize;
+    let id = read_bytes(port, count + 1, "Get ID", control)?;
+    wait_ack(port, ACK_TIMEOUT, "Get ID", control)?;
+
+    Ok(BootloaderInfo {
+        version: reply[0],
+        product_id: id.iter().take(2).fold(0u16, |acc, b| (acc << 8) | *b as u16),
+        commands: reply[1..].to_vec(),
+    })
+}
+
+fn mass_erase<P: ControlPort + ?Sized>(port: &mut P, info: &BootloaderInfo, control: &TransferControl) -> Result<()> {
+    if info.commands.contains(&CMD_EXTENDED_ERASE) {
+        send_command(port, CMD_EXTENDED_ERASE, "Extended Erase", control)?;
+        port.write_all(&[0xFF, 0xFF, 0x00])?;
+        port.flush()?;
+        wait_ack(port, ERASE_TIMEOUT, "Extended Erase", control)
+    } else if info.commands.contains(&CMD_ERASE) {
+        send_command(port, CMD_ERASE, "Erase", control)?;
+        port.write_all(&[0xFF, 0x00])?;
+        port.flush()?;
+        wait_ack(port, ERASE_TIMEOUT, "Erase", control)
+    } else {
+        Err(Stm32Error::EraseUnsupported.into())
+    }
+}
+
+/// Erase only `pages`, in as many commands as the page count needs
+fn erase_pages<P: ControlPort + ?Sized>(
+    port: &mut P,
+    info: &BootloaderInfo,
+    pages: &[u16],
+    control: &TransferControl,
+) -> Result<()> {
+    let (command, operation) = if info.commands.contains(&CMD_EXTENDED_ERASE) {
+        (CMD_EXTENDED_ERASE, "Extended Erase")
+    } else if info.commands.contains(&CMD_ERASE) {
+        (CMD_ERASE, "Erase")
+    } else {
+        return Err(Stm32Error::EraseUnsupported.into());
+    };
+    if let Some(page) = pages.iter().find(|&&page| command == CMD_ERASE && page > 0xFF) {
+        return Err(anyhow!("Page {} is beyond what the bootloader's Erase command can address", page));
+    }
+
+    for group in pages.chunks(ERASE_PAGES_PER_COMMAND) {
+        control.check_cancelled()?;
+        // Page count - 1 and page numbers: two bytes each for Extended Erase, one for Erase
+        let mut frame = Vec::with_capacity(group.len() * 2 + 3);
+        if command == CMD_EXTENDED_ERASE {
+            frame.extend_from_slice(&(group.len() as u16 - 1).to_be_bytes());
+            group.iter().for_each(|page| frame.extend_from_slice(&page.to_be_bytes()));
+        } else {
+            frame.push(group.len() as u8 - 1);
+            group.iter().for_each(|page| frame.push(*page as u8));
+        }
+        frame.push(frame.iter().fold(0u8, |acc, b| acc ^ b));
+
+        send_command(port, command, operation, control)?;
+        port.write_all(&frame)?;
+        port.flush()?;
+        wait_ack(port, ERASE_TIMEOUT, operation, control)?;
+    }
+    Ok(())
+}
+
+/// Numbers of the flash pages `image` touches, counted from the start of flash
+fn image_pages(image: &FirmwareImage, page_size: u32) -> Result<Vec<u16>> {
+    if page_size == 0 {
+        return Err(anyhow!("Flash page size must not be zero"));
+    }
+    let mut pages = BTreeSet::new();
+    for segment in image.segments.iter().filter(|segment| !segment.data.is_empty()) {
+        let outside = || Stm32Error::OutsideFlash { address: segment.address };
+        let start = u64::from(segment.address.checked_sub(DEFAULT_FLASH_BASE).ok_or_else(outside)?);
+        let end = start + segment.data.len() as u64 - 1;
+        for page in start / u64::from(page_size)..=end / u64::from(page_size) {
+            pages.insert(u16::try_from(page).map_err(|_| outside())?);
+        }
+    }
+    Ok(pages.into_iter().collect())
+}
+
+fn write_memory<P: ControlPort + ?Sized>(
+    port: &mut P,
+    address: u32,
+    data: &[u8],
+    control: &TransferControl,
+) -> Result<()> {
+    control.check_cancelled()?;
+    send_command(port, CMD_WRITE_MEMORY, "Write Memory", control)?;
+    send_address(port, address, "Write Memory address", control)?;
+
+    let mut frame = Vec::with_capacity(data.len() + 2);
+    frame.push((data.len() - 1) as u8);
+    frame.extend_from_slice(data);
+    frame.push(frame.iter().fold(0u8, |acc, b| acc ^ b));
+    port.write_all(&frame)?;
+    port.flush()?;
+    wait_ack(port, ACK_TIMEOUT, "Write Memory data", control)
+}
+
+fn read_memory<P: ControlPort + ?Sized>(
+    port: &mut P,
+    address: u32,
+    len: usize,
+    control: &TransferControl,
+) -> Result<Vec<u8>> {
+    control.check_cancelled()?;
+    send_command(port, CMD_READ_MEMORY, "Read Memory", control)?;
+    send_address(port, address, "Read Memory address", control)?;
+    let count = (len - 1) as u8;
+    port.write_all(&[count, !count])?;
+    port.flush()?;
+    wait_ack(port, ACK_TIMEOUT, "Read Memory length", control)?;
+    read_bytes(port, len, "Read Memory", control)
+}
+
+fn go<P: ControlPort + ?Sized>(port: &mut P, address: u32, control: &TransferControl) -> Result<()> {
+    send_command(port, CMD_GO, "Go", control)?;
+    send_address(port, address, "Go address", control)
+}
+
+/// Split the image into word-aligned Write Memory blocks of at most
+/// `BLOCK_SIZE` bytes, padding with 0xFF (erased flash). Each item carries the
+/// number of real image bytes in the block for progress reporting.
+fn blocks(image: &FirmwareImage) -> Vec<(u32, Vec<u8>, usize)> {
+    let mut result = Vec::new();
+    for segment in &image.segments {
+        let aligned = segment.address & !3;
+        let lead = (segment.address - aligned) as usize;
+        let mut data = vec![0xFF; lead];
+        data.extend_from_slice(&segment.data);
+
+        for (index, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+            let mut block = chunk.to_vec();
+            block.resize(chunk.len().div_ceil(4) * 4, 0xFF);
+            let padding = if index == 0 { lead } else { 0 };
+            result.push((aligned + (index * BLOCK_SIZE) as u32, block, chunk.len() - padding));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware_image::Segment;
+    use crate::transfer::test_support::{control, duplex_pair, PipeEnd};
+    use std::collections::BTreeMap;
+    use std::io::Write;
+
+    fn read_n(port: &mut PipeEnd, n: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; n];
+        transfer::read_exact_timeout(port, &mut buf, Duration::from_secs(2), &control())
+            .unwrap()
+            .then_some(buf)
+    }
+
+    /// Minimal AN3155 target with 2 KiB pages: returns its memory and the Go address
+    fn simulated_target(mut port: PipeEnd, mut memory: BTreeMap<u32, u8>) -> (BTreeMap<u32, u8>, Option<u32>) {
+        const PAGE_SIZE: u32 = 2048;
+        while read_n(&mut port, 1) != Some(vec![SYNC]) {}
+        port.write_all(&[ACK]).unwrap();
+
+        let read_address = |port: &mut PipeEnd| {
+            let bytes = read_n(port, 5).unwrap();
+            port.write_all(&[ACK]).unwrap();
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+
+        while let Some(command) = read_n(&mut port, 2) {
+            assert_eq!(command[1], !command[0]);
+            port.write_all(&[ACK]).unwrap();
+            match command[0] {
+                CMD_GET => port.write_all(&[3, 0x31, CMD_GET, CMD_GET_ID, CMD_EXTENDED_ERASE, ACK]).unwrap(),
+                CMD_GET_ID => port.write_all(&[1, 0x04, 0x13, ACK]).unwrap(),
+                CMD_EXTENDED_ERASE => {
+                    let count = read_n(&mut port, 2).unwrap();
+                    if count == [0xFF, 0xFF] {
+                        assert_eq!(read_n(&mut port, 1).unwrap(), vec![0x00]);
+                        memory.clear();
+                    } else {
+                        let pages = u16::from_be_bytes([count[0], count[1]]) as usize + 1;
+                        let list = read_n(&mut port, pages * 2 + 1).unwrap();
+                        let checksum = count.iter().chain(&list[..pages * 2]).fold(0u8, |acc, b| acc ^ b);
+                        assert_eq!(checksum, list[pages * 2]);
+                        for page in list[..pages * 2].chunks(2) {
+                            let start = DEFAULT_FLASH_BASE + u16::from_be_bytes([page[0], page[1]]) as u32 * PAGE_SIZE;
+                            memory.retain(|address, _| !(start..start + PAGE_SIZE).contains(address));
+                        }
+                    }
+                    port.write_all(&[ACK]).unwrap();
+                }
+                CMD_WRITE_MEMORY => {
+                    let address = read_address(&mut port);
+                    let count = read_n(&mut port, 1).unwrap()[0] as usize + 1;
+                    let data = read_n(&mut port, count + 1).unwrap();
+                    let checksum = data[..count].iter().fold((count - 1) as u8, |acc, b| acc ^ b);
+                    assert_eq!(checksum, data[count]);
+                    for (i, byte) in data[..count].iter().enumerate() {
+                        memory.insert(address + i as u32, *byte);
+                    }
+                    port.write_all(&[ACK]).unwrap();
+                }
+                CMD_READ_MEMORY => {
+                    let address = read_address(&mut port);
+                    let count = read_n(&mut port, 2).unwrap()[0] as usize + 1;
+                    port.write_all(&[ACK]).unwrap();
+                    let data: Vec<u8> = (0..count)
+                        .map(|i| *memory.get(&(address + i as u32)).unwrap_or(&0xFF))
+                        .collect();
+                    port.write_all(&data).unwrap();
+                }
+                CMD_GO => return (memory, Some(read_address(&mut port))),
+                other => panic!("unexpected command {:02X}", other),
+            }
+        }
+        (memory, None)
+    }
+
+    #[test]
+    fn flashes_verifies_and_starts_image() {
+        let first: Vec<u8> = (0..600u32).map(|i| (i % 251) as u8).collect();
+        let image = FirmwareImage {
+            segments: vec![
+                Segment { address: DEFAULT_FLASH_BASE, data: first.clone() },
+                Segment { address: DEFAULT_FLASH_BASE + 0x1002, data: vec![0xA5; 10] },
+            ],
+            entry_point: None,
+        };
+        let (mut host, target) = duplex_pair();
+        let target = thread::spawn(move || simulated_target(target, BTreeMap::new()));
+
+        let info = flash(&mut host, &image, &Stm32FlashOptions::default(), &control()).unwrap();
+        let (memory, go_address) = target.join().unwrap();
+
+        assert_eq!(info.summary(), "bootloader v3.1, product ID 0x0413");
+        assert_eq!(go_address, Some(DEFAULT_FLASH_BASE));
+        for (i, byte) in first.iter().enumerate() {
+            assert_eq!(memory[&(DEFAULT_FLASH_BASE + i as u32)], *byte);
+        }
+        assert_eq!(memory[&(DEFAULT_FLASH_BASE + 0x1000)], 0xFF);
+        assert_eq!(memory[&(DEFAULT_FLASH_BASE + 0x1002)], 0xA5);
+        assert_eq!(memory[&(DEFAULT_FLASH_BASE + 0x100B)], 0xA5);
+    }
+
+    #[test]
+    fn page_erase_leaves_pages_outside_the_image_alone() {
+        // Pages 0 and 2 hold the image; 1 and 8 (e.g. EEPROM emulation) must survive
+        let stale = BTreeMap::from([
+            (DEFAULT_FLASH_BASE + 0x10, 0x00),
+            (DEFAULT_FLASH_BASE + 0x900, 0x34),
+            (DEFAULT_FLASH_BASE + 0x1010, 0x00),
+            (DEFAULT_FLASH_BASE + 0x4000, 0x12),
+        ]);
+        let image = FirmwareImage {
+            segments: vec![
+                Segment { address: DEFAULT_FLASH_BASE, data: vec![0xA5; 8] },
+                Segment { address: DEFAULT_FLASH_BASE + 0x1002, data: vec![0x5A; 4] },
+            ],
+            entry_point: None,
+        };
+        assert_eq!(image_pages(&image, 2048).unwrap(), [0, 2]);
+        let (mut host, target) = duplex_pair();
+        let target = thread::spawn(move || simulated_target(target, stale));
+
+        let options = Stm32FlashOptions { mass_erase: false, ..Default::default() };
+        flash(&mut host, &image, &options, &control()).unwrap();
+        let (memory, _) = target.join().unwrap();
+
+        assert_eq!(memory.get(&(DEFAULT_FLASH_BASE + 0x10)), None);
+        assert_eq!(memory.get(&(DEFAULT_FLASH_BASE + 0x1010)), None);
+        assert_eq!(memory[&(DEFAULT_FLASH_BASE + 0x900)], 0x34);
+        assert_eq!(memory[&(DEFAULT_FLASH_BASE + 0x4000)], 0x12);
+        assert_eq!(memory[&(DEFAULT_FLASH_BASE + 0x1002)], 0x5A);
+
+        let below_flash = FirmwareImage::from_binary(0x0000_0000, vec![1; 4]);
+        assert!(image_pages(&below_flash, 2048).is_err());
+    }
+
+    #[test]
+    fn blocks_are_word_aligned_and_padded() {
+        let image = FirmwareImage::from_binary(0x0800_0002, vec![1; 300]);
+        let blocks = blocks(&image);
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].0, blocks[0].1.len(), blocks[0].2), (0x0800_0000, 256, 254));
+        assert_eq!((blocks[1].0, blocks[1].1.len(), blocks[1].2), (0x0800_0100, 48, 46));
+        assert_eq!(&blocks[1].1[46..], &[0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,344 @@
+//! Background file transfers over the open serial port
+
+use anyhow::Result;
+use serialport::SerialPort;
+use std::io::{Read, Seek, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Largest chunk accepted by `send_file`
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Progress step used when the total size is not known in advance
+const UNKNOWN_TOTAL_REPORT_STEP: u64 = 4 * 1024;
+
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("Transfer cancelled")]
+    Cancelled,
+    #[error("Transfer cancelled by remote side")]
+    RemoteCancelled,
+    #[error("Timed out waiting for {0}")]
+    Timeout(String),
+}
+
+/// What a finished transfer job reports back for the log summary
+#[derive(Debug, Default)]
+pub struct TransferOutcome {
+    pub bytes: u64,
+    /// Extra information for the summary entry (e.g. where a received file was saved)
+    pub detail: Option<String>,
+}
+
+/// Cancellation and progress reporting handed to a running transfer job
+pub struct TransferControl {
+    cancel: Arc<AtomicBool>,
+    transferred: AtomicU64,
+    last_reported: AtomicU64,
+    last_percentage: AtomicU8,
+    on_progress: Box<dyn Fn(u64, u64) + Send + Sync>,
+}
+
+impl TransferControl {
+    pub fn new(cancel: Arc<AtomicBool>, on_progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        Self {
+            cancel,
+            transferred: AtomicU64::new(0),
+            last_reported: AtomicU64::new(0),
+            last_percentage: AtomicU8::new(0),
+            on_progress: Box::new(on_progress),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Return `TransferError::Cancelled` once cancellation has been requested
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(TransferError::Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Bytes reported so far
+    pub fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    /// Record progress; the callback fires on every whole-percent step and at
+    /// completion, or every few KiB when `total` is unknown (0)
+    pub fn report(&self, transferred: u64, total: u64) {
+        self.transferred.store(transferred, Ordering::Relaxed);
+        let percentage = progress_percentage(transferred, total);
+        let due = if total == 0 {
+            transferred >= self.last_reported.load(Ordering::Relaxed) + UNKNOWN_TOTAL_REPORT_STEP
+        } else {
+            percentage > self.last_percentage.load(Ordering::Relaxed) || transferred == total
+        };
+        if due {
+            self.last_reported.store(transferred, Ordering::Relaxed);
+            self.last_percentage.store(percentage, Ordering::Relaxed);
+            (self.on_progress)(transferred, total);
+        }
+    }
+}
+
+pub fn progress_percentage(transferred: u64, total: u64) -> u8 {
+    if total == 0 {
+        return 0;
+    }
+    ((transferred.min(total) as f64 / total as f64) * 100.0) as u8
+}
+
+/// Copy `source` to `port` in chunks of `chunk_size`, sleeping `inter_chunk_delay`
+/// between chunks. `on_chunk` sees each chunk once it is written. Returns the
+/// number of bytes written.
+pub fn send_chunks(
+    source: &mut impl Read,
+    port: &mut (impl Write + ?Sized),
+    total: u64,
+    chunk_size: usize,
+    inter_chunk_delay: Duration,
+    control: &TransferControl,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> Result<u64> {
+    let mut buffer = vec![0u8; chunk_size.clamp(1, MAX_CHUNK_SIZE)];
+    let mut sent: u64 = 0;
+
+    loop {
+        control.check_cancelled()?;
+
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        port.write_all(&buffer[..read])?;
+        on_chunk(&buffer[..read]);
+        sent += read as u64;
+        control.report(sent, total);
+
+        if !inter_chunk_delay.is_zero() {
+            thread::sleep(inter_chunk_delay);
+        }
+    }
+
+    port.flush()?;
+    Ok(sent)
+}
+
+/// Read one byte, waiting at most `timeout`. Returns `None` if nothing arrived.
+/// Port read timeouts are treated as "no data yet" so the port can keep its
+/// short read timeout while protocols wait much longer.
+pub fn read_byte<P: Read + ?Sized>(
+    port: &mut P,
+    timeout: Duration,
+    control: &TransferControl,
+) -> Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    Ok(if read_exact_timeout(port, &mut byte, timeout, control)? {
+        Some(byte[0])
+    } else {
+        None
+    })
+}
+
+/// Fill `buf` completely, waiting at most `timeout` in total.
+/// Returns `false` if the deadline passed first.
+pub fn read_exact_timeout<P: Read + ?Sized>(
+    port: &mut P,
+    buf: &mut [u8],
+    timeout: Duration,
+    control: &TransferControl,
+) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        control.check_cancelled()?;
+        match port.read(&mut buf[filled..]) {
+            Ok(0) => thread::sleep(Duration::from_millis(1)),
+            Ok(n) => filled += n,
+            Err(ref e)
+                if e.kind() == std::io::ErrorKind::TimedOut
+                    || e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+        if filled < buf.len() && Instant::now() >= deadline {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Discard everything currently buffered on the port
+pub fn drain_input<P: Read + ?Sized>(port: &mut P, control: &TransferControl) -> Result<()> {
+    while read_byte(port, Duration::from_millis(20), control)?.is_some() {}
+    Ok(())
+}
+
+/// Seekable byte source for file senders (a file on disk or a decoded image)
+pub trait SendSource: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> SendSource for T {}
+
+/// Modem-control lines and line settings used by bootloader protocols
+pub trait ControlPort: Read + Write {
+    fn set_dtr(&mut self, level: bool) -> Result<()>;
+    fn set_rts(&mut self, level: bool) -> Result<()>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()>;
+    fn set_parity(&mut self, parity: serialport::Parity) -> Result<()>;
+}
+
+impl ControlPort for dyn SerialPort + '_ {
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        Ok(self.write_data_terminal_ready(level)?)
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        Ok(self.write_request_to_send(level)?)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        Ok(SerialPort::set_baud_rate(self, baud_rate)?)
+    }
+
+    fn set_parity(&mut self, parity: serialport::Parity) -> Result<()> {
+        Ok(SerialPort::set_parity(self, parity)?)
+    }
+}
+
+/// Human-readable size for summary entries
+pub fn format_byte_count(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.2} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// In-memory serial link for protocol tests: bytes written to one end are
+/// read from the other, and reads time out like a real port
+#[cfg(test)]
+pub mod test_support {
+    use super::TransferControl;
+    use std::io::{self, Read, Write};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    pub struct PipeEnd {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    pub fn duplex_pair() -> (PipeEnd, PipeEnd) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        (
+            PipeEnd { tx: a_tx, rx: a_rx, pending: Vec::new() },
+            PipeEnd { tx: b_tx, rx: b_rx, pending: Vec::new() },
+        )
+    }
+
+    /// A control that is never cancelled and ignores progress
+    pub fn control() -> TransferControl {
+        TransferControl::new(Arc::new(AtomicBool::new(false)), |_, _| {})
+    }
+
+    impl Read for PipeEnd {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.rx.recv_timeout(Duration::from_millis(10)) {
+                    Ok(data) => self.pending = data,
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+                    }
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    /// Line changes are not modelled; the simulated target is always listening
+    impl super::ControlPort for PipeEnd {
+        fn set_dtr(&mut self, _level: bool) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn set_rts(&mut self, _level: bool) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn set_baud_rate(&mut self, _baud_rate: u32) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn set_parity(&mut self, _parity: serialport::Parity) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for PipeEnd {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // The other end may already be gone once a test transfer finished
+            let _ = self.tx.send(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    #[test]
+    fn sends_file_in_chunks_and_reports_progress() {
+        let data: Vec<u8> = (0..=255).collect();
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports_clone = Arc::clone(&reports);
+        let control = TransferControl::new(Arc::new(AtomicBool::new(false)), move |done, total| {
+            reports_clone.lock().unwrap().push((done, total));
+        });
+
+        let mut port = Vec::new();
+        let mut chunks = Vec::new();
+        let sent = send_chunks(&mut Cursor::new(&data), &mut port, 256, 100, Duration::ZERO, &control, |chunk| {
+            chunks.push(chunk.len())
+        })
+        .unwrap();
+
+        assert_eq!(sent, 256);
+        assert_eq!(port, data);
+        assert_eq!(chunks, [100, 100, 56]);
+        assert_eq!(*reports.lock().unwrap(), vec![(100, 256), (200, 256), (256, 256)]);
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let control = TransferControl::new(Arc::new(AtomicBool::new(true)), |_, _| {});
+        let mut port = Vec::new();
+        let err = send_chunks(&mut Cursor::new(vec![1u8; 10]), &mut port, 10, 4, Duration::ZERO, &control, |_| {})
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<TransferError>(), Some(TransferError::Cancelled)));
+        assert!(port.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Trigger engine evaluated by the reader thread for every received frame
+//!
+//! A trigger matches when its pattern (if any) is found in the frame and its
+//! field condition (if any) holds. Matching triggers fire unless they are in
+//! their cooldown or have reached `max_fires`; the caller runs the actions of
+//! the returned firings. Field conditions compare an unsigned integer at a
+//! fixed byte offset, which covers simple binary protocols without a decoder.
+
+use crate::pattern;
+use crate::send_syntax;
+use crate::types::{FieldComparison, FieldCondition, TextEncoding, Trigger, TriggerAction, TriggerStatus};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use regex::bytes::Regex;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A trigger that fired for a frame, with what its actions need
+#[derive(Debug, Clone)]
+pub struct Firing {
+    pub name: String,
+    pub actions: Vec<TriggerAction>,
+    pub reply: Vec<u8>,
+    pub message: String,
+}
+
+struct ArmedTrigger {
+    trigger: Trigger,
+    pattern: Option<Regex>,
+    reply: Vec<u8>,
+    match_count: u64,
+    fire_count: u64,
+    last_fired: Option<(Instant, DateTime<Utc>)>,
+}
+
+#[derive(Default)]
+pub struct TriggerEngine {
+    triggers: Mutex<Vec<ArmedTrigger>>,
+}
+
+impl TriggerEngine {
+    /// Replace all triggers and reset their counters. Patterns and replies are
+    /// resolved with `encoding`; nothing changes if any trigger is invalid.
+    pub fn set_triggers(&self, triggers: Vec<Trigger>, encoding: &TextEncoding) -> Result<()> {
+        let armed = triggers
+            .into_iter()
+            .map(|trigger| arm(trigger, encoding))
+            .collect::<Result<Vec<_>>>()?;
+        *self.triggers.lock().unwrap_or_else(|e| e.into_inner()) = armed;
+        Ok(())
+    }
+
+    pub fn triggers(&self) -> Vec<Trigger> {
+        let triggers = self.triggers.lock().unwrap_or_else(|e| e.into_inner());
+        triggers.iter().map(|armed| armed.trigger.clone()).collect()
+    }
+
+    pub fn statuses(&self) -> Vec<TriggerStatus> {
+        let triggers = self.triggers.lock().unwrap_or_else(|e| e.into_inner());
+        triggers
+            .iter()
+            .map(|armed| TriggerStatus {
+                name: armed.trigger.name.clone(),
+                match_count: armed.match_count,
+                fire_count: armed.fire_count,
+                last_fired: armed.last_fired.map(|(_, at)| at),
+            })
+            .collect()
+    }
+
+    pub fn reset_counters(&self) {
+        let mut triggers = self.triggers.lock().unwrap_or_else(|e| e.into_inner());
+        for armed in triggers.iter_mut() {
+            armed.match_count = 0;
+            armed.fire_count = 0;
+            armed.last_fired = None;
+        }
+    }
+
+    /// Evaluate all enabled triggers against a received frame
+    pub fn evaluate(&self, frame: &[u8]) -> Vec<Firing> {
+        let mut triggers = self.triggers.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let mut firings = Vec::new();
+
+        for armed in triggers.iter_mut().filter(|armed| armed.trigger.enabled) {
+            let pattern_matches = armed.pattern.as_ref().is_none_or(|regex| regex.is_match(frame));
+            let field_matches = armed.trigger.field.as_ref().is_none_or(|field| field_matches(field, frame));
+            if !pattern_matches || !field_matches {
+                continue;
+            }
+            armed.match_count += 1;
+
+            let cooling_down = armed.last_fired.is_some_and(|(at, _)| {
+                now.duration_since(at) < Duration::from_millis(armed.trigger.cooldown_ms)
+            });
+            let exhausted = armed.trigger.max_fires.is_some_and(|max| armed.fire_count >= max);
+            if cooling_down || exhausted {
+                continue;
+            }
+
+            armed.fire_count += 1;
+            armed.last_fired = Some((now, Utc::now()));
+            firings.push(Firing {
+                name: armed.trigger.name.clone(),
+                actions: armed.trigger.actions.clone(),
+                reply: armed.reply.clone(),
+                message: armed.trigger.message.clone(),
+            });
+        }
+        firings
+    }
+}
+
+fn arm(trigger: Trigger, encoding: &TextEncoding) -> Result<ArmedTrigger> {
+    let invalid = |reason: String| anyhow!("Trigger '{}': {}", trigger.name, reason);
+
+    let pattern = if trigger.pattern.is_empty() {
+        None
+    } else {
+        Some(
+            pattern::compile(&trigger.pattern, &trigger.mode, trigger.case_sensitive, encoding)
+                .map_err(|e| invalid(e.to_string()))?,
+        )
+    };
+    if pattern.is_none() && trigger.field.is_none() {
+        return Err(invalid("needs a pattern or a field condition".to_string()));
+    }
+    if let Some(field) = &trigger.field {
+        if !(1..=8).contains(&field.length) {
+            return Err(invalid(format!("field length {} is not between 1 and 8 bytes", field.length)));
+        }
+        if field.offset.checked_add(field.length).is_none() {
+            return Err(invalid(format!("field offset {} is out of range", field.offset)));
+        }
+    }
+
+    let reply = if trigger.actions.contains(&TriggerAction::SendReply) {
+        send_syntax::resolve(&trigger.reply, &trigger.reply_format, encoding).map_err(|e| invalid(e.to_string()))?
+    } else {
+        Vec::new()
+    };
+
+    Ok(ArmedTrigger {
+        trigger,
+        pattern,
+        reply,
+        match_count: 0,
+        fire_count: 0,
+        last_fired: None,
+    })
+}
+
+/// Read the field from the frame and compare it; frames too short never match
+fn field_matches(field: &FieldCondition, frame: &[u8]) -> bool {
+    let Some(bytes) = field.offset.checked_add(field.length).and_then(|end| frame.get(field.offset..end)) else {
+        return false;
+    };
+    let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+    let value = if field.big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    };
+
+    match field.comparison {
+        FieldComparison::Equal => value == field.value,
+        FieldComparison::NotEqual => value != field.value,
+        FieldComparison::Greater => value > field.value,
+        FieldComparison::Less => value < field.value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DataFormat, PatternMode};
+
+    #[test]
+    fn fires_with_cooldown_and_limit() {
+        let engine = TriggerEngine::default();
+        engine
+            .set_triggers(
+                vec![Trigger {
+                    name: "ping".to_string(),
+                    pattern: "PING".to_string(),
+                    actions: vec![TriggerAction::SendReply],
+                    reply: "50 4F 4E 47".to_string(),
+                    reply_format: DataFormat::Hex,
+                    cooldown_ms: 60_000,
+                    ..Default::default()
+                }],
+                &TextEncoding::Utf8,
+            )
+            .unwrap();
+
+        let firings = engine.evaluate(b"PING\r\n");
+        assert_eq!(firings.len(), 1);
+        assert_eq!(firings[0].reply, b"PONG");
+        // Second match falls inside the cooldown
+        assert!(engine.evaluate(b"PING\r\n").is_empty());
+        assert!(engine.evaluate(b"other").is_empty());
+
+        let status = &engine.statuses()[0];
+        assert_eq!((status.match_count, status.fire_count), (2, 1));
+        assert!(status.last_fired.is_some());
+
+        engine.reset_counters();
+        assert_eq!(engine.statuses()[0].match_count, 0);
+        assert_eq!(engine.evaluate(b"PING").len(), 1);
+    }
+
+    #[test]
+    fn field_conditions_compare_integers_at_an_offset() {
+        let engine = TriggerEngine::default();
+        let field = FieldCondition {
+            offset: 1,
+            length: 2,
+            big_endian: false,
+            comparison: FieldComparison::Greater,
+            value: 1000,
+        };
+        engine
+            .set_triggers(
+                vec![Trigger {
+                    name: "over-temp".to_string(),
+                    pattern: "AA".to_string(),
+                    mode: PatternMode::Hex,
+                    field: Some(field),
+                    actions: vec![TriggerAction::Notify],
+                    max_fires: Some(1),
+                    ..Default::default()
+                }],
+                &TextEncoding::Utf8,
+            )
+            .unwrap();
+
+        assert!(engine.evaluate(&[0xAA, 0xE8, 0x03]).is_empty()); // 1000
+        assert!(engine.evaluate(&[0xAA, 0xE9]).is_empty()); // too short
+        assert_eq!(engine.evaluate(&[0xAA, 0xE9, 0x03]).len(), 1); // 1001
+        assert!(engine.evaluate(&[0xAA, 0xE9, 0x03]).is_empty()); // limit reached
+    }
+
+    #[test]
+    fn rejects_triggers_without_a_condition() {
+        let engine = TriggerEngine::default();
+        let result = engine.set_triggers(vec![Trigger { name: "empty".to_string(), ..Default::default() }], &TextEncoding::Utf8);
+        assert!(result.unwrap_err().to_string().contains("empty"));
+
+        let field = FieldCondition {
+            offset: usize::MAX,
+            length: 2,
+            big_endian: true,
+            comparison: FieldComparison::Equal,
+            value: 0,
+        };
+        let trigger = Trigger { name: "far".to_string(), field: Some(field), ..Default::default() };
+        let result = engine.set_triggers(vec![trigger], &TextEncoding::Utf8);
+        assert!(result.unwrap_err().to_string().contains("out of range"));
+    }
+}
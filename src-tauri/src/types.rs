@@ -71,6 +71,8 @@ pub enum FlowControl {
 pub enum DataFormat {
     Text,
     Hex,
+    /// Text with escape sequences, `{hex:..}` blocks and placeholders (see `send_syntax`)
+    Escaped,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -94,12 +96,169 @@ pub struct LogEntry {
     pub display_text: String,
     /// Pre-formatted timestamp string (None if timestamps were disabled when entry was created)
     pub timestamp_formatted: Option<String>,
+    /// Matched a hide rule; kept in the log but not shown by the viewer
+    #[serde(default)]
+    pub hidden: bool,
+    /// Tags of the highlight rules this entry matched
+    #[serde(default)]
+    pub highlights: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Direction {
     Sent,
     Received,
+    /// Entries generated by the app itself (e.g. file transfer summaries)
+    System,
+}
+
+impl Direction {
+    /// Short label used in recordings and exports
+    pub fn label(&self) -> &'static str {
+        match self {
+            Direction::Sent => "TX",
+            Direction::Received => "RX",
+            Direction::System => "SYS",
+        }
+    }
+}
+
+/// A page of session history read back from the log database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogPage {
+    pub entries: Vec<LogEntry>,
+    /// More entries exist beyond this page in the requested direction
+    pub has_more: bool,
+    /// Entries in the whole session
+    pub total_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogSessionInfo {
+    /// Database file of the current session (None until the first entry is logged)
+    pub path: Option<String>,
+    pub entry_count: i64,
+}
+
+/// Result of importing an exported log into the viewer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogImportSummary {
+    pub file_name: String,
+    pub entry_count: usize,
+    pub bookmark_count: usize,
+}
+
+/// Mark on a log entry. The entry's time, direction and text are copied so the
+/// mark stays meaningful after the log is cleared.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub entry_id: i64,
+    pub note: String,
+    pub created_at: DateTime<Utc>,
+    pub entry_timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    pub preview: String,
+}
+
+/// How a search or filter pattern is interpreted (see `pattern`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PatternMode {
+    #[default]
+    Text,
+    Regex,
+    /// Hex byte pairs, `??` matches any byte
+    Hex,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogRuleAction {
+    #[default]
+    Hide,
+    Highlight,
+}
+
+/// Filter or highlight rule evaluated when a log entry is created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogRule {
+    pub name: String,
+    pub enabled: bool,
+    pub pattern: String,
+    pub mode: PatternMode,
+    pub case_sensitive: bool,
+    /// Only entries in this direction (all directions when None)
+    pub direction: Option<Direction>,
+    pub action: LogRuleAction,
+    /// Tag attached by highlight rules, e.g. a color name the viewer maps to a style
+    pub tag: String,
+}
+
+impl Default for LogRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            pattern: String::new(),
+            mode: PatternMode::Regex,
+            case_sensitive: true,
+            direction: None,
+            action: LogRuleAction::Hide,
+            tag: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchQuery {
+    pub pattern: String,
+    pub mode: PatternMode,
+    pub case_sensitive: bool,
+    /// Only entries in this direction (all directions when None)
+    pub direction: Option<Direction>,
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    /// Number of neighbouring entry ids returned on each side of a match
+    pub context: usize,
+    pub max_results: usize,
+    pub newest_first: bool,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            mode: PatternMode::Text,
+            case_sensitive: false,
+            direction: None,
+            start_time: None,
+            end_time: None,
+            context: 0,
+            max_results: 1000,
+            newest_first: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchMatch {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    /// Byte range of the first match within the entry data
+    pub match_start: usize,
+    pub match_end: usize,
+    /// The match and some surrounding bytes, rendered with the current display settings
+    pub preview: String,
+    pub context_before: Vec<i64>,
+    pub context_after: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub matches: Vec<SearchMatch>,
+    /// More entries matched than `max_results`
+    pub truncated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -117,6 +276,40 @@ pub enum ExportFormat {
     Txt,
     Csv,
     Json,
+    /// Wireshark capture with one packet per sent or received entry
+    Pcapng,
+    /// Standalone page with colors, a hex/text toggle and bookmarks
+    Html,
+}
+
+/// Selects the entries an export includes; every filter that is set must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportFilter {
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub direction: Option<Direction>,
+    /// Only entries matching this pattern (no pattern filter when empty)
+    pub pattern: String,
+    pub mode: PatternMode,
+    pub case_sensitive: bool,
+    /// Only these entries (no id filter when empty)
+    pub entry_ids: Vec<i64>,
+    /// Leave out entries hidden by log rules
+    pub exclude_hidden: bool,
+}
+
+/// Extra representations of each entry in TXT and CSV exports, which
+/// otherwise only hold the bytes as (lossy) text
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// Bytes as space-separated hex pairs
+    pub include_hex: bool,
+    /// Bytes as standard base64
+    pub include_base64: bool,
+    /// The text as rendered in the log view
+    pub include_display_text: bool,
 }
 
 // Frame Segmentation types
@@ -178,7 +371,424 @@ pub struct RecordingStatus {
     pub text_recording_active: bool,
     pub raw_recording_active: bool,
     pub text_file_path: Option<String>,
+    /// The sent bytes when split, otherwise all recorded bytes
     pub raw_file_path: Option<String>,
+    /// The received bytes when split
+    pub raw_rx_file_path: Option<String>,
+    /// Last error writing a recording file
+    pub write_error: Option<String>,
+    /// Bytes left out of recordings because the disk could not keep up
+    pub dropped_bytes: u64,
+}
+
+/// How raw recordings store sent and received bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RawRecordingMode {
+    /// Both directions in one `.bin` file, without markers
+    #[default]
+    Combined,
+    /// `.tx.bin` and `.rx.bin` files
+    Split,
+    /// One `.rsrec` file of timestamped records with their direction
+    Container,
+}
+
+/// Recordings started on connect. While enabled, recordings stay open when
+/// the port disconnects and continue when the same device connects again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoRecordSettings {
+    pub text: bool,
+    pub raw: bool,
+}
+
+impl AutoRecordSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.text || self.raw
+    }
+}
+
+/// When text and raw recordings move on to a new numbered file. Limits are
+/// checked as data is written; unset or zero limits are off.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingRotation {
+    pub max_file_bytes: Option<u64>,
+    pub max_duration_secs: Option<u64>,
+    /// Text recordings only
+    pub max_lines: Option<u64>,
+    /// Gzip files once they are closed
+    pub compress: bool,
+    /// Delete the oldest closed recordings once all recordings in the log
+    /// directory take more than this many bytes
+    pub max_directory_bytes: Option<u64>,
+}
+
+/// What text recordings write for each sent or received entry
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextRecordingContent {
+    /// Bytes decoded with the configured text encoding
+    #[default]
+    Text,
+    /// Bytes as hex pairs
+    Hex,
+    /// The text as rendered in the log view
+    Display,
+}
+
+// File transfer types
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum TransferProtocol {
+    /// Raw bytes streamed through the port without framing
+    Raw,
+    /// XMODEM with 128-byte blocks (CRC, falling back to checksum)
+    Xmodem,
+    /// XMODEM-1K (1024-byte blocks, CRC)
+    Xmodem1k,
+    /// YMODEM batch transfer (file name and size in block 0)
+    Ymodem,
+    /// ZMODEM streaming transfer (lrzsz `sz`/`rz`)
+    Zmodem,
+    /// STM32 system-memory bootloader (AN3155)
+    Stm32Bootloader,
+    /// Espressif ROM serial loader (ESP8266/ESP32)
+    EspRomLoader,
+}
+
+/// Payload of the `zmodem-detected` event
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum ZmodemRequest {
+    /// The remote started `sz` and is waiting for us to receive
+    Receive,
+    /// The remote started `rz` and is waiting for us to send a file
+    Send,
+}
+
+/// Progress event payload for `transfer-progress`
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferProgress {
+    pub protocol: TransferProtocol,
+    pub file_name: String,
+    pub transferred: u64,
+    pub total: u64,
+    pub percentage: u8,
+}
+
+/// Final event payload for `transfer-finished`
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferResult {
+    pub protocol: TransferProtocol,
+    pub file_name: String,
+    pub transferred: u64,
+    pub success: bool,
+    pub cancelled: bool,
+    pub error: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+// Bootloader flashing types
+/// Modem-control output wired to a target's reset or boot-mode pin
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum ControlLine {
+    #[default]
+    None,
+    Dtr,
+    Rts,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stm32FlashOptions {
+    /// Intel HEX or raw binary file
+    pub file_path: String,
+    /// Load address for raw binaries (defaults to the start of flash, 0x08000000)
+    pub base_address: Option<u32>,
+    /// Mass-erase before writing; otherwise only the pages the image covers
+    /// are erased
+    pub mass_erase: bool,
+    /// Flash page size in bytes for page erase, e.g. 1024 on STM32F103x8/B or
+    /// 2048 on F0/F3/G0/G4/L4. Parts with mixed sector sizes need mass erase.
+    pub page_size: u32,
+    /// Read back and compare every written block
+    pub verify: bool,
+    /// Jump to the application with the Go command when done
+    pub start_application: bool,
+    /// Line driving NRST
+    pub reset_line: ControlLine,
+    /// Line driving BOOT0
+    pub boot0_line: ControlLine,
+    /// Set when the adapter inverts the lines (asserted line = pin high)
+    pub invert_lines: bool,
+}
+
+impl Default for Stm32FlashOptions {
+    fn default() -> Self {
+        Self {
+            file_path: String::new(),
+            base_address: None,
+            mass_erase: true,
+            page_size: 2048,
+            verify: true,
+            start_application: true,
+            reset_line: ControlLine::Dtr,
+            boot0_line: ControlLine::Rts,
+            invert_lines: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EspFlashOptions {
+    /// Raw binary or Intel HEX file
+    pub file_path: String,
+    /// Flash offset for raw binaries (Intel HEX files carry their own addresses)
+    pub offset: u32,
+    /// Compare the flash MD5 with the image after writing
+    pub verify: bool,
+    /// Enter the ROM loader through the usual DTR/RTS auto-reset circuit
+    pub auto_reset: bool,
+    /// Switch to this baud rate after syncing (None keeps the connection rate)
+    pub flash_baud_rate: Option<u32>,
+    /// Hard reset into the application when done
+    pub reset_after: bool,
+}
+
+impl Default for EspFlashOptions {
+    fn default() -> Self {
+        Self {
+            file_path: String::new(),
+            offset: 0,
+            verify: true,
+            auto_reset: true,
+            flash_baud_rate: None,
+            reset_after: true,
+        }
+    }
+}
+
+// Trigger types
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldComparison {
+    #[default]
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+}
+
+/// Unsigned integer field at a fixed position in the received frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldCondition {
+    pub offset: usize,
+    /// Field width in bytes (1-8)
+    pub length: usize,
+    pub big_endian: bool,
+    pub comparison: FieldComparison,
+    pub value: u64,
+}
+
+impl Default for FieldCondition {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            length: 1,
+            big_endian: true,
+            comparison: FieldComparison::Equal,
+            value: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerAction {
+    SendReply,
+    StartTextRecording,
+    StopTextRecording,
+    StartRawRecording,
+    StopRawRecording,
+    Bookmark,
+    /// Emit a `trigger-notification` event for the frontend to show or play
+    Notify,
+    /// Stop adding received frames to the log until capture is resumed
+    PauseCapture,
+}
+
+/// Actions run when a received frame matches the pattern and/or field condition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Trigger {
+    pub name: String,
+    pub enabled: bool,
+    /// Empty when only the field condition is used
+    pub pattern: String,
+    pub mode: PatternMode,
+    pub case_sensitive: bool,
+    pub field: Option<FieldCondition>,
+    pub actions: Vec<TriggerAction>,
+    /// Data sent by `SendReply`, in the send panel syntax of `reply_format`
+    pub reply: String,
+    pub reply_format: DataFormat,
+    /// Bookmark note and notification text
+    pub message: String,
+    /// Minimum time between two firings
+    pub cooldown_ms: u64,
+    /// Stop firing after this many times (until counters are reset)
+    pub max_fires: Option<u64>,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            pattern: String::new(),
+            mode: PatternMode::Text,
+            case_sensitive: true,
+            field: None,
+            actions: Vec::new(),
+            reply: String::new(),
+            reply_format: DataFormat::Text,
+            message: String::new(),
+            cooldown_ms: 0,
+            max_fires: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerStatus {
+    pub name: String,
+    /// Frames that matched, including those suppressed by cooldown or limit
+    pub match_count: u64,
+    pub fire_count: u64,
+    pub last_fired: Option<DateTime<Utc>>,
+}
+
+/// Payload of the `trigger-notification` event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerNotification {
+    pub trigger: String,
+    pub message: String,
+    pub entry_id: Option<i64>,
+}
+
+// Device simulator types
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimulatorMatch {
+    /// Whole frame equals the pattern (trailing CR/LF ignored)
+    #[default]
+    Exact,
+    Prefix,
+    Regex,
+    /// Hex bytes of the whole frame; `?` is a wildcard nibble
+    HexMask,
+}
+
+/// Maps an incoming frame to a canned response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulatorRule {
+    pub name: String,
+    pub enabled: bool,
+    pub match_kind: SimulatorMatch,
+    pub pattern: String,
+    /// How `Exact` and `Prefix` patterns are written
+    pub pattern_format: DataFormat,
+    /// Sent in turn on successive matches. Escaped responses may use `{$1}`,
+    /// `{$name}` for captured bytes and `{$count}` for the match count.
+    pub responses: Vec<String>,
+    pub response_format: DataFormat,
+    pub delay_ms: u64,
+    /// Only match while the simulator is in this state
+    pub state: Option<String>,
+    /// State entered after the rule matched
+    pub next_state: Option<String>,
+}
+
+impl Default for SimulatorRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            match_kind: SimulatorMatch::Exact,
+            pattern: String::new(),
+            pattern_format: DataFormat::Text,
+            responses: Vec::new(),
+            response_format: DataFormat::Escaped,
+            delay_ms: 0,
+            state: None,
+            next_state: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulatorConfig {
+    pub enabled: bool,
+    pub initial_state: String,
+    pub rules: Vec<SimulatorRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulatorStatus {
+    pub enabled: bool,
+    pub state: String,
+    pub replies_sent: u64,
+}
+
+// Session replay types
+
+/// Where replayed entries go
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayTarget {
+    /// Add all entries to the log view with their original direction
+    #[default]
+    LogView,
+    /// Write the sent entries to the open port; the device answers as usual
+    Port,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayOptions {
+    pub target: ReplayTarget,
+    /// Timing factor: 2.0 replays twice as fast, 0 replays without delays
+    pub speed: f64,
+    /// Longest pause between two entries; None keeps the original gaps
+    pub max_gap_ms: Option<u64>,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            target: ReplayTarget::LogView,
+            speed: 1.0,
+            max_gap_ms: None,
+        }
+    }
+}
+
+/// Progress event payload for `replay-progress`
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayProgress {
+    pub file_name: String,
+    pub replayed: usize,
+    pub total: usize,
+}
+
+/// Final event payload for `replay-finished`
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayResult {
+    pub file_name: String,
+    pub target: ReplayTarget,
+    pub replayed: usize,
+    pub total: usize,
+    pub success: bool,
+    pub cancelled: bool,
+    pub error: Option<String>,
 }
 
 // Display settings types for pre-formatted log rendering
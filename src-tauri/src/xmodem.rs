@@ -0,0 +1,627 @@
+//! XMODEM (checksum, CRC and 1K) and YMODEM batch transfers
+//!
+//! These functions expect exclusive access to the port, i.e. the normal reader
+//! thread must be paused while they run.
+
+use crate::transfer::{self, TransferControl, TransferError};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const BS: u8 = 0x08;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1A;
+const CRC_REQUEST: u8 = b'C';
+
+const MAX_RETRIES: u32 = 10;
+/// Attempts with 'C' before a receiver falls back to checksum mode
+const CRC_ATTEMPTS: u32 = 3;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const PACKET_TIMEOUT: Duration = Duration::from_secs(5);
+const RECEIVER_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Error)]
+pub enum XmodemError {
+    #[error("Too many retries at block {0}")]
+    TooManyRetries(u64),
+    #[error("Block sequence error: expected {expected}, received {received}")]
+    SequenceError { expected: u8, received: u8 },
+    #[error("Unexpected byte 0x{0:02X} while waiting for a block")]
+    UnexpectedByte(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Checksum {
+    Crc16,
+    Additive,
+}
+
+/// A file written by the YMODEM or ZMODEM receiver
+#[derive(Debug, Clone)]
+pub struct ReceivedFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, init 0x0000)
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Send `source` with XMODEM (128-byte blocks) or XMODEM-1K.
+/// Returns the number of payload bytes sent.
+pub fn send_xmodem<P: Read + Write + ?Sized>(
+    port: &mut P,
+    source: &mut impl Read,
+    total: u64,
+    use_1k: bool,
+    control: &TransferControl,
+) -> Result<u64> {
+    let result = (|| {
+        let checksum = wait_for_start(port, control)?;
+        let block_size = if use_1k { 1024 } else { 128 };
+        let sent = send_data_blocks(port, source, total, block_size, checksum, control)?;
+        send_eot(port, control)?;
+        Ok(sent)
+    })();
+    abort_on_error(port, result)
+}
+
+/// Send a single file as a YMODEM batch (header block, 1K data blocks, end-of-batch block)
+pub fn send_ymodem<P: Read + Write + ?Sized>(
+    port: &mut P,
+    file_name: &str,
+    source: &mut impl Read,
+    total: u64,
+    control: &TransferControl,
+) -> Result<u64> {
+    let result = (|| {
+        let checksum = wait_for_start(port, control)?;
+        let header = ymodem_header(file_name, total);
+        let header_size = if header.len() > 128 { 1024 } else { 128 };
+        send_block(port, &build_block(0, &header, header_size, checksum, 0x00), 0, control)?;
+
+        let checksum = wait_for_start(port, control)?;
+        let sent = send_data_blocks(port, source, total, 1024, checksum, control)?;
+        send_eot(port, control)?;
+
+        // An empty header block ends the batch
+        let checksum = wait_for_start(port, control)?;
+        send_block(port, &build_block(0, &[], 128, checksum, 0x00), 0, control)?;
+        Ok(sent)
+    })();
+    abort_on_error(port, result)
+}
+
+/// Receive one XMODEM/XMODEM-1K file into `sink`. Starts in CRC mode and falls
+/// back to checksum mode if the sender does not answer. The last block keeps
+/// its SUB padding because XMODEM does not transmit the file size.
+pub fn receive_xmodem<P: Read + Write + ?Sized>(
+    port: &mut P,
+    sink: &mut impl Write,
+    control: &TransferControl,
+) -> Result<u64> {
+    let result = (|| {
+        let mut checksum = Checksum::Crc16;
+        let first = start_receive(port, true, &mut checksum, control)?;
+        let mut received = 0u64;
+        receive_file_blocks(port, first, checksum, control, |data| {
+            sink.write_all(data)?;
+            received += data.len() as u64;
+            control.report(received, 0);
+            Ok(())
+        })?;
+        sink.flush()?;
+        Ok(received)
+    })();
+    abort_on_error(port, result)
+}
+
+/// Receive a YMODEM batch into `dir`, returning the files written
+pub fn receive_ymodem<P: Read + Write + ?Sized>(
+    port: &mut P,
+    dir: &Path,
+    control: &TransferControl,
+) -> Result<Vec<ReceivedFile>> {
+    let result = (|| {
+        let mut files = Vec::new();
+        loop {
+            let header = receive_header_block(port, control)?;
+            let Some((name, size)) = parse_ymodem_header(&header) else {
+                // Empty file name: end of batch
+                port.write_all(&[ACK])?;
+                return Ok(files);
+            };
+            port.write_all(&[ACK])?;
+
+            let path = unique_path(dir, &name);
+            let mut file = File::create(&path)?;
+            let mut checksum = Checksum::Crc16;
+            let first = start_receive(port, false, &mut checksum, control)?;
+            let mut received = 0u64;
+            receive_file_blocks(port, first, checksum, control, |data| {
+                // The header carries the real size, so padding can be dropped
+                let keep = match size {
+                    Some(size) => data.len().min(size.saturating_sub(received) as usize),
+                    None => data.len(),
+                };
+                file.write_all(&data[..keep])?;
+                received += keep as u64;
+                control.report(received, size.unwrap_or(0));
+                Ok(())
+            })?;
+            file.flush()?;
+            files.push(ReceivedFile { path, size: received });
+        }
+    })();
+    abort_on_error(port, result)
+}
+
+/// Tell the remote side to give up, unless it already cancelled
+fn abort_on_error<P: Write + ?Sized, T>(port: &mut P, result: Result<T>) -> Result<T> {
+    if let Err(e) = &result {
+        if !matches!(e.downcast_ref::<TransferError>(), Some(TransferError::RemoteCancelled)) {
+            let mut abort = vec![CAN; 8];
+            abort.extend_from_slice(&[BS; 8]);
+            let _ = port.write_all(&abort);
+            let _ = port.flush();
+        }
+    }
+    result
+}
+
+fn build_block(seq: u8, payload: &[u8], block_size: usize, checksum: Checksum, pad: u8) -> Vec<u8> {
+    let mut block = Vec::with_capacity(block_size + 5);
+    block.push(if block_size == 1024 { STX } else { SOH });
+    block.push(seq);
+    block.push(!seq);
+    let data_start = block.len();
+    block.extend_from_slice(payload);
+    block.resize(data_start + block_size, pad);
+    let data = &block[data_start..];
+    match checksum {
+        Checksum::Crc16 => {
+            let crc = crc16_xmodem(data);
+            block.extend_from_slice(&crc.to_be_bytes());
+        }
+        Checksum::Additive => {
+            let sum = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+            block.push(sum);
+        }
+    }
+    block
+}
+
+/// Wait for a second CAN to confirm a remote cancel
+fn confirm_remote_cancel<P: Read + ?Sized>(port: &mut P, control: &TransferControl) -> Result<()> {
+    if transfer::read_byte(port, Duration::from_secs(1), control)? == Some(CAN) {
+        return Err(TransferError::RemoteCancelled.into());
+    }
+    Ok(())
+}
+
+/// Wait for the receiver's start request: 'C' for CRC mode, NAK for checksum mode
+fn wait_for_start<P: Read + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Checksum> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match transfer::read_byte(port, remaining, control)? {
+            Some(CRC_REQUEST) => return Ok(Checksum::Crc16),
+            Some(NAK) => return Ok(Checksum::Additive),
+            Some(CAN) => confirm_remote_cancel(port, control)?,
+            // Anything else is line noise or leftover output from the device
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Err(TransferError::Timeout("receiver to start".to_string()).into())
+}
+
+/// Wait for ACK, NAK or CAN. Returns `None` on timeout.
+fn wait_response<P: Read + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Option<u8>> {
+    let deadline = Instant::now() + RESPONSE_TIMEOUT;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match transfer::read_byte(port, remaining, control)? {
+            Some(CAN) => confirm_remote_cancel(port, control)?,
+            Some(byte @ (ACK | NAK)) => return Ok(Some(byte)),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(None)
+}
+
+/// Send a block until it is acknowledged
+fn send_block<P: Read + Write + ?Sized>(
+    port: &mut P,
+    block: &[u8],
+    block_number: u64,
+    control: &TransferControl,
+) -> Result<()> {
+    for _ in 0..MAX_RETRIES {
+        port.write_all(block)?;
+        port.flush()?;
+        if wait_response(port, control)? == Some(ACK) {
+            return Ok(());
+        }
+    }
+    Err(XmodemError::TooManyRetries(block_number).into())
+}
+
+fn send_data_blocks<P: Read + Write + ?Sized>(
+    port: &mut P,
+    source: &mut impl Read,
+    total: u64,
+    block_size: usize,
+    checksum: Checksum,
+    control: &TransferControl,
+) -> Result<u64> {
+    let mut buffer = vec![0u8; block_size];
+    let mut sent = 0u64;
+    let mut block_number = 1u64;
+
+    loop {
+        let read = read_full(source, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        // Short tails fit in a 128-byte block even in 1K mode
+        let size = if read <= 128 { 128 } else { block_size };
+        let block = build_block(block_number as u8, &buffer[..read], size, checksum, SUB);
+        send_block(port, &block, block_number, control)?;
+
+        sent += read as u64;
+        block_number += 1;
+        control.report(sent, total);
+    }
+    Ok(sent)
+}
+
+fn send_eot<P: Read + Write + ?Sized>(port: &mut P, control: &TransferControl) -> Result<()> {
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&[EOT])?;
+        port.flush()?;
+        // YMODEM receivers commonly NAK the first EOT
+        if wait_response(port, control)? == Some(ACK) {
+            return Ok(());
+        }
+    }
+    Err(TransferError::Timeout("EOT acknowledgement".to_string()).into())
+}
+
+/// Read until `buf` is full or the source is exhausted
+fn read_full(source: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = source.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Poll the sender with 'C' (or NAK in checksum mode) until the first packet
+/// byte arrives. Returns that byte.
+fn start_receive<P: Read + Write + ?Sized>(
+    port: &mut P,
+    allow_checksum_fallback: bool,
+    checksum: &mut Checksum,
+    control: &TransferControl,
+) -> Result<u8> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let mut attempts = 0;
+
+    while Instant::now() < deadline {
+        if allow_checksum_fallback && attempts >= CRC_ATTEMPTS {
+            *checksum = Checksum::Additive;
+        }
+        let request = match checksum {
+            Checksum::Crc16 => CRC_REQUEST,
+            Checksum::Additive => NAK,
+        };
+        port.write_all(&[request])?;
+        port.flush()?;
+
+        let poll_deadline = Instant::now() + RECEIVER_POLL_INTERVAL;
+        while let Some(remaining) = poll_deadline.checked_duration_since(Instant::now()) {
+            match transfer::read_byte(port, remaining, control)? {
+                Some(byte @ (SOH | STX | EOT)) => return Ok(byte),
+                Some(CAN) => confirm_remote_cancel(port, control)?,
+                Some(_) => {}
+                None => break,
+            }
+        }
+        attempts += 1;
+    }
+    Err(TransferError::Timeout("sender to start".to_string()).into())
+}
+
+/// Read the rest of a packet whose header byte was `header`.
+/// Returns `None` if the packet was damaged or incomplete.
+fn read_packet<P: Read + ?Sized>(
+    port: &mut P,
+    header: u8,
+    checksum: Checksum,
+    control: &TransferControl,
+) -> Result<Option<(u8, Vec<u8>)>> {
+    let size = if header == STX { 1024 } else { 128 };
+    let trailer = match checksum {
+        Checksum::Crc16 => 2,
+        Checksum::Additive => 1,
+    };
+    let mut packet = vec![0u8; 2 + size + trailer];
+    if !transfer::read_exact_timeout(port, &mut packet, PACKET_TIMEOUT, control)? {
+        return Ok(None);
+    }
+
+    let (seq, complement) = (packet[0], packet[1]);
+    if seq != !complement {
+        return Ok(None);
+    }
+    let data = &packet[2..2 + size];
+    let valid = match checksum {
+        Checksum::Crc16 => crc16_xmodem(data).to_be_bytes() == packet[2 + size..],
+        Checksum::Additive => data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == packet[2 + size],
+    };
+    Ok(valid.then(|| (seq, data.to_vec())))
+}
+
+/// Receive data blocks until EOT, passing each new block to `on_block`
+fn receive_file_blocks<P: Read + Write + ?Sized>(
+    port: &mut P,
+    first: u8,
+    checksum: Checksum,
+    control: &TransferControl,
+    mut on_block: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut expected: u8 = 1;
+    let mut retries = 0;
+    let mut pending = Some(first);
+
+    loop {
+        if retries > MAX_RETRIES {
+            return Err(XmodemError::TooManyRetries(expected as u64).into());
+        }
+
+        let header = match pending.take() {
+            Some(byte) => byte,
+            None => match transfer::read_byte(port, RESPONSE_TIMEOUT, control)? {
+                Some(byte) => byte,
+                None => {
+                    retries += 1;
+                    port.write_all(&[NAK])?;
+                    continue;
+                }
+            },
+        };
+
+        match header {
+            EOT => {
+                port.write_all(&[ACK])?;
+                port.flush()?;
+                return Ok(());
+            }
+            SOH | STX => match read_packet(port, header, checksum, control)? {
+                Some((seq, data)) if seq == expected => {
+                    on_block(&data)?;
+                    port.write_all(&[ACK])?;
+                    expected = expected.wrapping_add(1);
+                    retries = 0;
+                }
+                // Our ACK was lost and the sender repeated the previous block
+                Some((seq, _)) if seq == expected.wrapping_sub(1) => {
+                    port.write_all(&[ACK])?;
+                }
+                Some((seq, _)) => {
+                    return Err(XmodemError::SequenceError { expected, received: seq }.into());
+                }
+                None => {
+                    retries += 1;
+                    transfer::drain_input(port, control)?;
+                    port.write_all(&[NAK])?;
+                }
+            },
+            CAN => confirm_remote_cancel(port, control)?,
+            // Noise between packets
+            _ => {}
+        }
+    }
+}
+
+/// Receive YMODEM block 0, retrying damaged copies
+fn receive_header_block<P: Read + Write + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Vec<u8>> {
+    let mut checksum = Checksum::Crc16;
+    let mut header = start_receive(port, false, &mut checksum, control)?;
+
+    for _ in 0..MAX_RETRIES {
+        match header {
+            SOH | STX => {
+                if let Some((0, data)) = read_packet(port, header, checksum, control)? {
+                    return Ok(data);
+                }
+            }
+            // Repeated EOT of the previous file: our ACK got lost
+            EOT => port.write_all(&[ACK])?,
+            other => return Err(XmodemError::UnexpectedByte(other).into()),
+        }
+        transfer::drain_input(port, control)?;
+        header = start_receive(port, false, &mut checksum, control)?;
+    }
+    Err(XmodemError::TooManyRetries(0).into())
+}
+
+/// Build "name\0size\0" for a YMODEM header block. A name too long for a
+/// 1K block is shortened so the size field still fits.
+fn ymodem_header(file_name: &str, total: u64) -> Vec<u8> {
+    let size = total.to_string();
+    let max_name_len = 1024 - size.len() - 2;
+    let mut name_len = file_name.len().min(max_name_len);
+    while !file_name.is_char_boundary(name_len) {
+        name_len -= 1;
+    }
+
+    let mut header = Vec::with_capacity(128);
+    header.extend_from_slice(&file_name.as_bytes()[..name_len]);
+    header.push(0);
+    header.extend_from_slice(size.as_bytes());
+    header.push(0);
+    header
+}
+
+/// Parse "name\0size ..." from a YMODEM header block. `None` marks the end of the batch.
+pub(crate) fn parse_ymodem_header(block: &[u8]) -> Option<(String, Option<u64>)> {
+    let name_end = block.iter().position(|&b| b == 0).unwrap_or(block.len());
+    if name_end == 0 {
+        return None;
+    }
+    let name = String::from_utf8_lossy(&block[..name_end]).to_string();
+    let size = block
+        .get(name_end + 1..)
+        .and_then(|rest| {
+            let end = rest.iter().position(|&b| b == 0 || b == b' ').unwrap_or(rest.len());
+            std::str::from_utf8(&rest[..end]).ok()
+        })
+        .and_then(|text| text.parse().ok());
+    Some((name, size))
+}
+
+/// Destination inside `dir` for a received file. Only the final path component
+/// of the sender's name is used, and existing files are not overwritten.
+pub(crate) fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let file_name = Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "received.bin".to_string());
+
+    let candidate = dir.join(&file_name);
+    if !candidate.exists() {
+        return candidate;
+    }
+    let stem = Path::new(&file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = Path::new(&file_name)
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{}_{}{}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap_or(candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::test_support::{control, duplex_pair};
+    use std::io::Cursor;
+    use std::thread;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn crc16_xmodem_check_value() {
+        assert_eq!(crc16_xmodem(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn xmodem_round_trip_pads_last_block() {
+        let data = payload(300);
+        let (mut sender_port, mut receiver_port) = duplex_pair();
+
+        let to_send = data.clone();
+        let sender = thread::spawn(move || {
+            send_xmodem(&mut sender_port, &mut Cursor::new(&to_send), 300, false, &control()).unwrap()
+        });
+        let mut received = Vec::new();
+        receive_xmodem(&mut receiver_port, &mut received, &control()).unwrap();
+
+        assert_eq!(sender.join().unwrap(), 300);
+        assert_eq!(received.len(), 384);
+        assert_eq!(&received[..300], &data[..]);
+        assert!(received[300..].iter().all(|&b| b == SUB));
+    }
+
+    #[test]
+    fn xmodem_1k_round_trip() {
+        let data = payload(2048);
+        let (mut sender_port, mut receiver_port) = duplex_pair();
+
+        let to_send = data.clone();
+        let sender = thread::spawn(move || {
+            send_xmodem(&mut sender_port, &mut Cursor::new(&to_send), 2048, true, &control()).unwrap()
+        });
+        let mut received = Vec::new();
+        receive_xmodem(&mut receiver_port, &mut received, &control()).unwrap();
+
+        sender.join().unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn ymodem_round_trip_keeps_name_and_size() {
+        let dir = std::env::temp_dir().join(format!("ymodem-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = payload(1500);
+        let (mut sender_port, mut receiver_port) = duplex_pair();
+
+        let to_send = data.clone();
+        let sender = thread::spawn(move || {
+            send_ymodem(&mut sender_port, "fw.bin", &mut Cursor::new(&to_send), 1500, &control()).unwrap()
+        });
+        let files = receive_ymodem(&mut receiver_port, &dir, &control()).unwrap();
+
+        sender.join().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, dir.join("fw.bin"));
+        assert_eq!(std::fs::read(&files[0].path).unwrap(), data);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sender_stops_on_remote_cancel() {
+        let (mut sender_port, mut receiver_port) = duplex_pair();
+        receiver_port.write_all(&[CAN, CAN]).unwrap();
+
+        let err = send_xmodem(&mut sender_port, &mut Cursor::new(vec![0u8; 10]), 10, false, &control())
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<TransferError>(), Some(TransferError::RemoteCancelled)));
+    }
+
+    #[test]
+    fn parses_ymodem_header() {
+        assert_eq!(
+            parse_ymodem_header(b"fw.bin\x001234 14300000000 100644\x00\x00"),
+            Some(("fw.bin".to_string(), Some(1234)))
+        );
+        assert_eq!(parse_ymodem_header(&[0u8; 128]), None);
+
+        let long_name = "é".repeat(600);
+        let header = ymodem_header(&long_name, 123_456);
+        assert!(header.len() <= 1024);
+        let (name, size) = parse_ymodem_header(&header).unwrap();
+        assert!(long_name.starts_with(&name) && !name.is_empty());
+        assert_eq!(size, Some(123_456));
+    }
+}
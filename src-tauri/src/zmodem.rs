@@ -0,0 +1,795 @@
+//! ZMODEM send and receive, compatible with lrzsz `sz`/`rz`
+//!
+//! Only the subset needed for plain file transfers is implemented: hex and
+//! binary (CRC-16/CRC-32) headers, streaming data subpackets with ZRPOS error
+//! recovery, and batch receive. Like the XMODEM module these functions expect
+//! exclusive access to the port.
+
+use crate::transfer::{self, TransferControl, TransferError};
+use crate::types::ZmodemRequest;
+use crate::xmodem::{self, ReceivedFile};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZDLEE: u8 = ZDLE ^ 0x40;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCHALLENGE: u8 = 14;
+const ZCAN: u8 = 16;
+const ZFREECNT: u8 = 17;
+const ZCOMMAND: u8 = 18;
+
+// Data subpacket terminators
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// ZRINIT capability flags (ZF0)
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+/// ZFILE conversion option: binary transfer
+const ZCBIN: u8 = 1;
+
+/// Consecutive CAN bytes that abort a session
+const CANCEL_COUNT: usize = 5;
+const SUBPACKET_SIZE: usize = 1024;
+/// Bytes streamed before the sender waits for an acknowledgement
+const WINDOW_SIZE: u64 = 16 * 1024;
+const MAX_SUBPACKET: usize = 8 * 1024;
+const MAX_RETRIES: u32 = 10;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const BYTE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Header prefix sent by `sz` (ZRQINIT) and `rz` (ZRINIT) when they start
+const REQUEST_PREFIX: &[u8] = b"**\x18B0";
+/// Length of a ZRQINIT/ZRINIT hex header including the trailing CR LF XON
+pub const REQUEST_HEADER_LEN: usize = 21;
+
+#[derive(Debug, Error)]
+pub enum ZmodemError {
+    #[error("ZMODEM: too many errors at offset {0}")]
+    TooManyRetries(u64),
+    #[error("ZMODEM: receiver skipped the file")]
+    Skipped,
+    #[error("ZMODEM: remote reported a file error")]
+    RemoteFileError,
+}
+
+/// Find a ZMODEM session request in received data. Returns the offset of the
+/// header and whether the remote wants to send (`Receive`) or receive (`Send`).
+pub fn detect_request(data: &[u8]) -> Option<(usize, ZmodemRequest)> {
+    let full_len = REQUEST_PREFIX.len() + 1;
+    data.windows(full_len).enumerate().find_map(|(pos, window)| {
+        if !window.starts_with(REQUEST_PREFIX) {
+            return None;
+        }
+        match window[REQUEST_PREFIX.len()] {
+            b'0' => Some((pos, ZmodemRequest::Receive)),
+            b'1' => Some((pos, ZmodemRequest::Send)),
+            _ => None,
+        }
+    })
+}
+
+/// CRC-32 as used by ZMODEM (IEEE 802.3, reflected)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    kind: u8,
+    /// ZP0..ZP3; positions are little-endian, flags are stored as ZF3..ZF0
+    data: [u8; 4],
+}
+
+impl Header {
+    fn new(kind: u8) -> Self {
+        Self { kind, data: [0; 4] }
+    }
+
+    fn with_position(kind: u8, position: u64) -> Self {
+        Self { kind, data: (position as u32).to_le_bytes() }
+    }
+
+    fn with_flags(kind: u8, zf0: u8) -> Self {
+        Self { kind, data: [0, 0, 0, zf0] }
+    }
+
+    fn position(&self) -> u64 {
+        u32::from_le_bytes(self.data) as u64
+    }
+
+    fn zf0(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn bytes(&self) -> [u8; 5] {
+        [self.kind, self.data[0], self.data[1], self.data[2], self.data[3]]
+    }
+}
+
+enum Incoming {
+    Header(Header, bool),
+    Garbled,
+    Timeout,
+}
+
+enum ZByte {
+    Data(u8),
+    FrameEnd(u8),
+}
+
+/// Append `byte` with ZDLE escaping. `previous` is the last unescaped byte
+/// written, needed for the "CR after @" rule that protects Telenet links.
+fn push_escaped(buf: &mut Vec<u8>, byte: u8, previous: &mut u8) {
+    let needs_escape = match byte {
+        ZDLE | 0x10 | 0x90 | XON | 0x91 | XOFF | 0x93 => true,
+        0x0D | 0x8D => *previous & 0x7F == b'@',
+        _ => false,
+    };
+    if needs_escape {
+        buf.push(ZDLE);
+        buf.push(if byte == ZDLE { ZDLEE } else { byte ^ 0x40 });
+    } else {
+        buf.push(byte);
+    }
+    *previous = byte;
+}
+
+fn escape_into(buf: &mut Vec<u8>, data: &[u8]) {
+    let mut previous = 0u8;
+    for &byte in data {
+        push_escaped(buf, byte, &mut previous);
+    }
+}
+
+fn encode_hex_header(header: &Header) -> Vec<u8> {
+    let bytes = header.bytes();
+    let crc = xmodem::crc16_xmodem(&bytes);
+    let mut buf = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+    for byte in bytes.iter().chain(crc.to_be_bytes().iter()) {
+        buf.extend_from_slice(format!("{:02x}", byte).as_bytes());
+    }
+    buf.extend_from_slice(b"\r\x8a");
+    if header.kind != ZFIN && header.kind != ZACK {
+        buf.push(XON);
+    }
+    buf
+}
+
+fn encode_binary_header(header: &Header, use_crc32: bool) -> Vec<u8> {
+    let bytes = header.bytes();
+    let mut buf = vec![ZPAD, ZDLE, if use_crc32 { ZBIN32 } else { ZBIN }];
+    escape_into(&mut buf, &bytes);
+    if use_crc32 {
+        escape_into(&mut buf, &crc32(&bytes).to_le_bytes());
+    } else {
+        escape_into(&mut buf, &xmodem::crc16_xmodem(&bytes).to_be_bytes());
+    }
+    buf
+}
+
+fn encode_subpacket(data: &[u8], frame_end: u8, use_crc32: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(data.len() + data.len() / 8 + 8);
+    escape_into(&mut buf, data);
+    buf.push(ZDLE);
+    buf.push(frame_end);
+
+    let mut covered = data.to_vec();
+    covered.push(frame_end);
+    if use_crc32 {
+        escape_into(&mut buf, &crc32(&covered).to_le_bytes());
+    } else {
+        escape_into(&mut buf, &xmodem::crc16_xmodem(&covered).to_be_bytes());
+    }
+    if frame_end == ZCRCW {
+        buf.push(XON);
+    }
+    buf
+}
+
+fn send_hex_header<P: Write + ?Sized>(port: &mut P, header: Header) -> Result<()> {
+    port.write_all(&encode_hex_header(&header))?;
+    port.flush()?;
+    Ok(())
+}
+
+fn read_raw<P: Read + ?Sized>(port: &mut P, timeout: Duration, control: &TransferControl) -> Result<Option<u8>> {
+    transfer::read_byte(port, timeout, control)
+}
+
+/// Read one ZDLE-decoded byte. `None` means the port went quiet.
+fn read_zdle_byte<P: Read + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Option<ZByte>> {
+    loop {
+        let Some(byte) = read_raw(port, BYTE_TIMEOUT, control)? else {
+            return Ok(None);
+        };
+        match byte {
+            ZDLE => {}
+            XON | XOFF | 0x91 | 0x93 => continue,
+            other => return Ok(Some(ZByte::Data(other))),
+        }
+
+        let mut cancels = 1;
+        let escaped = loop {
+            let Some(next) = read_raw(port, BYTE_TIMEOUT, control)? else {
+                return Ok(None);
+            };
+            match next {
+                ZDLE => {
+                    cancels += 1;
+                    if cancels >= CANCEL_COUNT {
+                        return Err(TransferError::RemoteCancelled.into());
+                    }
+                }
+                XON | XOFF | 0x91 | 0x93 => {}
+                other => break other,
+            }
+        };
+        return Ok(Some(match escaped {
+            ZCRCE | ZCRCG | ZCRCQ | ZCRCW => ZByte::FrameEnd(escaped),
+            ZRUB0 => ZByte::Data(0x7F),
+            ZRUB1 => ZByte::Data(0xFF),
+            other => ZByte::Data(other ^ 0x40),
+        }));
+    }
+}
+
+fn read_zdle_data<P: Read + ?Sized>(port: &mut P, buf: &mut [u8], control: &TransferControl) -> Result<bool> {
+    for slot in buf.iter_mut() {
+        match read_zdle_byte(port, control)? {
+            Some(ZByte::Data(byte)) => *slot = byte,
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|v| v as u8)
+}
+
+/// Wait for the next header, skipping anything that is not one
+fn read_header<P: Read + ?Sized>(port: &mut P, timeout: Duration, control: &TransferControl) -> Result<Incoming> {
+    let deadline = std::time::Instant::now() + timeout;
+    let mut cancels = 0;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        let Some(byte) = read_raw(port, remaining, control)? else {
+            return Ok(Incoming::Timeout);
+        };
+        if byte == ZDLE {
+            cancels += 1;
+            if cancels >= CANCEL_COUNT {
+                return Err(TransferError::RemoteCancelled.into());
+            }
+            continue;
+        }
+        cancels = 0;
+        if byte & 0x7F != ZPAD {
+            continue;
+        }
+
+        // Any number of ZPADs, then ZDLE and the header format
+        let mut next = read_raw(port, BYTE_TIMEOUT, control)?;
+        while next.map(|b| b & 0x7F) == Some(ZPAD) {
+            next = read_raw(port, BYTE_TIMEOUT, control)?;
+        }
+        if next != Some(ZDLE) {
+            continue;
+        }
+        let format = read_raw(port, BYTE_TIMEOUT, control)?.map(|b| b & 0x7F);
+
+        return Ok(match format {
+            Some(ZHEX) => read_hex_header(port, control)?,
+            Some(ZBIN) => {
+                let mut raw = [0u8; 7];
+                let valid = read_zdle_data(port, &mut raw, control)?
+                    && xmodem::crc16_xmodem(&raw[..5]) == u16::from_be_bytes([raw[5], raw[6]]);
+                if valid { Incoming::Header(header_from(&raw), false) } else { Incoming::Garbled }
+            }
+            Some(ZBIN32) => {
+                let mut raw = [0u8; 9];
+                let valid = read_zdle_data(port, &mut raw, control)?
+                    && crc32(&raw[..5]) == u32::from_le_bytes([raw[5], raw[6], raw[7], raw[8]]);
+                if valid { Incoming::Header(header_from(&raw), true) } else { Incoming::Garbled }
+            }
+            _ => continue,
+        });
+    }
+}
+
+fn read_hex_header<P: Read + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Incoming> {
+    let mut digits = [0u8; 14];
+    if !transfer::read_exact_timeout(port, &mut digits, BYTE_TIMEOUT, control)? {
+        return Ok(Incoming::Garbled);
+    }
+    let mut raw = [0u8; 7];
+    for (i, pair) in digits.chunks(2).enumerate() {
+        match (hex_value(pair[0] & 0x7F), hex_value(pair[1] & 0x7F)) {
+            (Some(high), Some(low)) => raw[i] = (high << 4) | low,
+            _ => return Ok(Incoming::Garbled),
+        }
+    }
+    if xmodem::crc16_xmodem(&raw[..5]) != u16::from_be_bytes([raw[5], raw[6]]) {
+        return Ok(Incoming::Garbled);
+    }
+
+    // Swallow the trailing CR LF so it is not mistaken for data
+    let short = Duration::from_millis(100);
+    if read_raw(port, short, control)?.map(|b| b & 0x7F) == Some(b'\r') {
+        read_raw(port, short, control)?;
+    }
+    Ok(Incoming::Header(header_from(&raw), false))
+}
+
+fn header_from(raw: &[u8]) -> Header {
+    Header { kind: raw[0], data: [raw[1], raw[2], raw[3], raw[4]] }
+}
+
+/// Read one data subpacket. `None` means it was damaged or cut short.
+fn read_subpacket<P: Read + ?Sized>(
+    port: &mut P,
+    use_crc32: bool,
+    control: &TransferControl,
+) -> Result<Option<(Vec<u8>, u8)>> {
+    let mut data = Vec::with_capacity(SUBPACKET_SIZE);
+    loop {
+        match read_zdle_byte(port, control)? {
+            Some(ZByte::Data(byte)) => {
+                if data.len() >= MAX_SUBPACKET {
+                    return Ok(None);
+                }
+                data.push(byte);
+            }
+            Some(ZByte::FrameEnd(frame_end)) => {
+                let mut crc = [0u8; 4];
+                let crc = &mut crc[..if use_crc32 { 4 } else { 2 }];
+                if !read_zdle_data(port, crc, control)? {
+                    return Ok(None);
+                }
+                data.push(frame_end);
+                let valid = if use_crc32 {
+                    crc32(&data) == u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]])
+                } else {
+                    xmodem::crc16_xmodem(&data) == u16::from_be_bytes([crc[0], crc[1]])
+                };
+                data.pop();
+                return Ok(valid.then_some((data, frame_end)));
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Send one file. The receiver may ask to resume from any offset, so the
+/// source has to be seekable. Returns the number of bytes in the file.
+pub fn send_zmodem<P: Read + Write + ?Sized, S: Read + Seek>(
+    port: &mut P,
+    file_name: &str,
+    source: &mut S,
+    total: u64,
+    control: &TransferControl,
+) -> Result<u64> {
+    let result = (|| {
+        // "rz\r" starts the receiver when the remote is sitting at a shell prompt
+        port.write_all(b"rz\r")?;
+        send_hex_header(port, Header::new(ZRQINIT))?;
+        let receiver = wait_for_receiver(port, control)?;
+        let use_crc32 = receiver.zf0() & CANFC32 != 0;
+
+        let start = offer_file(port, file_name, total, use_crc32, control)?;
+        send_file_data(port, source, start, total, use_crc32, control)?;
+        finish_session(port, control)?;
+        Ok(total)
+    })();
+    abort_on_error(port, result)
+}
+
+fn wait_for_receiver<P: Read + Write + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Header> {
+    for _ in 0..MAX_RETRIES {
+        match read_header(port, RESPONSE_TIMEOUT, control)? {
+            Incoming::Header(header, _) => match header.kind {
+                ZRINIT => return Ok(header),
+                ZCHALLENGE => send_hex_header(port, Header { kind: ZACK, data: header.data })?,
+                ZCAN | ZABORT => return Err(TransferError::RemoteCancelled.into()),
+                _ => send_hex_header(port, Header::new(ZRQINIT))?,
+            },
+            Incoming::Garbled | Incoming::Timeout => send_hex_header(port, Header::new(ZRQINIT))?,
+        }
+    }
+    Err(TransferError::Timeout("ZMODEM receiver".to_string()).into())
+}
+
+/// Send ZFILE until the receiver answers with the offset to start from
+fn offer_file<P: Read + Write + ?Sized>(
+    port: &mut P,
+    file_name: &str,
+    total: u64,
+    use_crc32: bool,
+    control: &TransferControl,
+) -> Result<u64> {
+    let mut info = file_name.as_bytes().to_vec();
+    info.push(0);
+    info.extend_from_slice(format!("{} 0 100644 0 1 {}", total, total).as_bytes());
+    info.push(0);
+
+    for _ in 0..MAX_RETRIES {
+        let mut frame = encode_binary_header(&Header::with_flags(ZFILE, ZCBIN), use_crc32);
+        frame.extend_from_slice(&encode_subpacket(&info, ZCRCW, use_crc32));
+        port.write_all(&frame)?;
+        port.flush()?;
+
+        // A late duplicate ZRINIT is answered by the ZFILE already in flight
+        while let Incoming::Header(header, _) = read_header(port, RESPONSE_TIMEOUT, control)? {
+            match header.kind {
+                ZRPOS => return Ok(header.position().min(total)),
+                ZSKIP => return Err(ZmodemError::Skipped.into()),
+                ZRINIT => continue,
+                ZCAN | ZABORT => return Err(TransferError::RemoteCancelled.into()),
+                ZFERR => return Err(ZmodemError::RemoteFileError.into()),
+                _ => break,
+            }
+        }
+    }
+    Err(ZmodemError::TooManyRetries(0).into())
+}
+
+fn send_file_data<P: Read + Write + ?Sized, S: Read + Seek>(
+    port: &mut P,
+    source: &mut S,
+    start: u64,
+    total: u64,
+    use_crc32: bool,
+    control: &TransferControl,
+) -> Result<()> {
+    let mut offset = start;
+    let mut buffer = vec![0u8; SUBPACKET_SIZE];
+    let mut retries = 0;
+
+    'resend: loop {
+        source.seek(SeekFrom::Start(offset))?;
+        port.write_all(&encode_binary_header(&Header::with_position(ZDATA, offset), use_crc32))?;
+
+        let mut in_window = 0u64;
+        loop {
+            control.check_cancelled()?;
+            let read = read_full(source, &mut buffer)?;
+            let at_end = read < buffer.len();
+            in_window += read as u64;
+
+            let frame_end = if at_end {
+                ZCRCE
+            } else if in_window >= WINDOW_SIZE {
+                ZCRCW
+            } else {
+                ZCRCG
+            };
+            port.write_all(&encode_subpacket(&buffer[..read], frame_end, use_crc32))?;
+            offset += read as u64;
+            control.report(offset, total);
+
+            if at_end {
+                break;
+            }
+            if frame_end == ZCRCW {
+                port.flush()?;
+                in_window = 0;
+                match wait_ack(port, control)? {
+                    Some(position) => {
+                        offset = position;
+                        retries += 1;
+                        if retries > MAX_RETRIES {
+                            return Err(ZmodemError::TooManyRetries(offset).into());
+                        }
+                        continue 'resend;
+                    }
+                    None => port.write_all(&encode_binary_header(&Header::with_position(ZDATA, offset), use_crc32))?,
+                }
+            }
+        }
+
+        // The receiver confirms the end of file with ZRINIT or asks for a resend
+        for _ in 0..MAX_RETRIES {
+            send_hex_header(port, Header::with_position(ZEOF, offset))?;
+            match read_header(port, RESPONSE_TIMEOUT, control)? {
+                Incoming::Header(header, _) => match header.kind {
+                    ZRINIT => return Ok(()),
+                    ZRPOS => {
+                        offset = header.position();
+                        retries += 1;
+                        if retries > MAX_RETRIES {
+                            return Err(ZmodemError::TooManyRetries(offset).into());
+                        }
+                        continue 'resend;
+                    }
+                    ZCAN | ZABORT => return Err(TransferError::RemoteCancelled.into()),
+                    ZFERR => return Err(ZmodemError::RemoteFileError.into()),
+                    _ => {}
+                },
+                Incoming::Garbled | Incoming::Timeout => {}
+            }
+        }
+        return Err(TransferError::Timeout("ZMODEM end of file acknowledgement".to_string()).into());
+    }
+}
+
+/// Wait for ZACK after a ZCRCW subpacket. Returns the resend offset if the
+/// receiver asked for one instead.
+fn wait_ack<P: Read + Write + ?Sized>(port: &mut P, control: &TransferControl) -> Result<Option<u64>> {
+    loop {
+        match read_header(port, RESPONSE_TIMEOUT, control)? {
+            Incoming::Header(header, _) => match header.kind {
+                ZACK => return Ok(None),
+                ZRPOS => return Ok(Some(header.position())),
+                ZCAN | ZABORT => return Err(TransferError::RemoteCancelled.into()),
+                ZFERR => return Err(ZmodemError::RemoteFileError.into()),
+                _ => {}
+            },
+            Incoming::Garbled => {}
+            Incoming::Timeout => return Err(TransferError::Timeout("ZMODEM acknowledgement".to_string()).into()),
+        }
+    }
+}
+
+fn finish_session<P: Read + Write + ?Sized>(port: &mut P, control: &TransferControl) -> Result<()> {
+    for _ in 0..MAX_RETRIES {
+        send_hex_header(port, Header::new(ZFIN))?;
+        match read_header(port, RESPONSE_TIMEOUT, control)? {
+            Incoming::Header(header, _) if header.kind == ZFIN => {
+                port.write_all(b"OO")?;
+                port.flush()?;
+                return Ok(());
+            }
+            Incoming::Timeout => break,
+            _ => {}
+        }
+    }
+    // Some receivers exit without answering ZFIN; the file itself was confirmed
+    Ok(())
+}
+
+fn read_full(source: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let read = source.read(&mut buf[filled..])?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
+
+struct IncomingFile {
+    file: File,
+    received: ReceivedFile,
+    expected: Option<u64>,
+}
+
+/// Receive a batch of files into `dir`. Existing files are not overwritten.
+pub fn receive_zmodem<P: Read + Write + ?Sized>(
+    port: &mut P,
+    dir: &Path,
+    control: &TransferControl,
+) -> Result<Vec<ReceivedFile>> {
+    let result = (|| {
+        let mut files = Vec::new();
+        let mut current: Option<IncomingFile> = None;
+        let mut total_received = 0u64;
+        let mut retries = 0;
+
+        let zrinit = Header::with_flags(ZRINIT, CANFDX | CANOVIO | CANFC32);
+        send_hex_header(port, zrinit)?;
+
+        loop {
+            let (header, use_crc32) = match read_header(port, RESPONSE_TIMEOUT, control)? {
+                Incoming::Header(header, use_crc32) => (header, use_crc32),
+                Incoming::Garbled | Incoming::Timeout => {
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return Err(TransferError::Timeout("ZMODEM sender".to_string()).into());
+                    }
+                    match &current {
+                        Some(incoming) => send_hex_header(port, Header::with_position(ZRPOS, incoming.received.size))?,
+                        None => send_hex_header(port, zrinit)?,
+                    }
+                    continue;
+                }
+            };
+
+            match header.kind {
+                ZRQINIT => send_hex_header(port, zrinit)?,
+                ZSINIT => {
+                    // The attention string is not needed; just acknowledge it
+                    read_subpacket(port, use_crc32, control)?;
+                    send_hex_header(port, Header::new(ZACK))?;
+                }
+                ZFILE => {
+                    let info = read_subpacket(port, use_crc32, control)?;
+                    if let Some(incoming) = &current {
+                        // Repeated offer of the file already being written
+                        send_hex_header(port, Header::with_position(ZRPOS, incoming.received.size))?;
+                        continue;
+                    }
+                    let Some((name, size)) = info.and_then(|(data, _)| xmodem::parse_ymodem_header(&data)) else {
+                        send_hex_header(port, Header::new(ZNAK))?;
+                        continue;
+                    };
+                    let path = xmodem::unique_path(dir, &name);
+                    current = Some(IncomingFile {
+                        file: File::create(&path)?,
+                        received: ReceivedFile { path, size: 0 },
+                        expected: size,
+                    });
+                    send_hex_header(port, Header::with_position(ZRPOS, 0))?;
+                }
+                ZDATA => {
+                    let Some(incoming) = current.as_mut() else {
+                        send_hex_header(port, zrinit)?;
+                        continue;
+                    };
+                    if header.position() != incoming.received.size {
+                        send_hex_header(port, Header::with_position(ZRPOS, incoming.received.size))?;
+                        continue;
+                    }
+                    loop {
+                        let Some((data, frame_end)) = read_subpacket(port, use_crc32, control)? else {
+                            retries += 1;
+                            if retries > MAX_RETRIES {
+                                return Err(ZmodemError::TooManyRetries(incoming.received.size).into());
+                            }
+                            send_hex_header(port, Header::with_position(ZRPOS, incoming.received.size))?;
+                            break;
+                        };
+                        retries = 0;
+                        incoming.file.write_all(&data)?;
+                        incoming.received.size += data.len() as u64;
+                        control.report(total_received + incoming.received.size, incoming.expected.unwrap_or(0));
+
+                        match frame_end {
+                            ZCRCW => {
+                                send_hex_header(port, Header::with_position(ZACK, incoming.received.size))?;
+                                break;
+                            }
+                            ZCRCQ => send_hex_header(port, Header::with_position(ZACK, incoming.received.size))?,
+                            ZCRCE => break,
+                            _ => {}
+                        }
+                    }
+                }
+                // A ZEOF for a different offset belongs to data we asked to resend
+                ZEOF if current.as_ref().is_some_and(|incoming| incoming.received.size == header.position()) => {
+                    let incoming = current.take().unwrap();
+                    incoming.file.sync_all()?;
+                    total_received += incoming.received.size;
+                    files.push(incoming.received);
+                    send_hex_header(port, zrinit)?;
+                }
+                ZFIN => {
+                    send_hex_header(port, Header::new(ZFIN))?;
+                    // Trailing "OO" from the sender; harmless if it never comes
+                    let mut over_and_out = [0u8; 2];
+                    transfer::read_exact_timeout(port, &mut over_and_out, Duration::from_millis(500), control)?;
+                    return Ok(files);
+                }
+                ZFREECNT => send_hex_header(port, Header::new(ZACK))?,
+                ZCOMMAND => send_hex_header(port, Header::new(ZNAK))?,
+                ZCAN | ZABORT => return Err(TransferError::RemoteCancelled.into()),
+                _ => {}
+            }
+        }
+    })();
+    abort_on_error(port, result)
+}
+
+/// Tell the remote side to give up, unless it already cancelled
+fn abort_on_error<P: Write + ?Sized, T>(port: &mut P, result: Result<T>) -> Result<T> {
+    if let Err(e) = &result {
+        if !matches!(e.downcast_ref::<TransferError>(), Some(TransferError::RemoteCancelled)) {
+            let mut abort = vec![ZDLE; 8];
+            abort.extend_from_slice(&[0x08; 8]);
+            let _ = port.write_all(&abort);
+            let _ = port.flush();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::test_support::{control, duplex_pair};
+    use std::io::Cursor;
+    use std::thread;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn detects_sz_and_rz_requests() {
+        assert_eq!(
+            detect_request(b"rz waiting\r\n**\x18B0100000023be50\r\x8a\x11"),
+            Some((12, ZmodemRequest::Send))
+        );
+        assert_eq!(detect_request(b"**\x18B00000000000000\r\x8a"), Some((0, ZmodemRequest::Receive)));
+        assert_eq!(detect_request(b"**\x18B0"), None);
+        assert_eq!(detect_request(b"plain text"), None);
+    }
+
+    #[test]
+    fn hex_header_matches_lrzsz() {
+        // ZRQINIT as sent by `sz`
+        assert_eq!(encode_hex_header(&Header::new(ZRQINIT)), b"**\x18B00000000000000\r\x8a\x11".to_vec());
+    }
+
+    #[test]
+    fn escaped_subpacket_round_trips() {
+        let data: Vec<u8> = (0..=255).chain([b'@', 0x0D, ZDLE, ZDLE]).collect();
+        let (mut writer, mut reader) = duplex_pair();
+        writer.write_all(&encode_subpacket(&data, ZCRCE, true)).unwrap();
+
+        let (decoded, frame_end) = read_subpacket(&mut reader, true, &control()).unwrap().unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(frame_end, ZCRCE);
+    }
+
+    #[test]
+    fn zmodem_round_trip() {
+        let dir = std::env::temp_dir().join(format!("zmodem-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let (mut sender_port, mut receiver_port) = duplex_pair();
+
+        let to_send = data.clone();
+        let sender = thread::spawn(move || {
+            send_zmodem(&mut sender_port, "fw.bin", &mut Cursor::new(&to_send), 40_000, &control()).unwrap()
+        });
+        let files = receive_zmodem(&mut receiver_port, &dir, &control()).unwrap();
+
+        assert_eq!(sender.join().unwrap(), 40_000);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, dir.join("fw.bin"));
+        assert_eq!(std::fs::read(&files[0].path).unwrap(), data);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}